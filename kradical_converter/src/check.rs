@@ -0,0 +1,48 @@
+use crate::{error::ConvertError, krad, opts::InputFormat, radk};
+use kradical_parsing::{consistency, radk::merge};
+use std::io::Write;
+
+// Writes the report, then fails if it found anything so that CI can rely on the exit code
+pub fn check(
+    krad_inputs: &[String],
+    radk_inputs: &[String],
+    writer: &mut dyn Write,
+) -> Result<(), ConvertError> {
+    let decompositions = krad::read(krad_inputs, InputFormat::Edrdg)?;
+    let memberships = merge(radk::read(radk_inputs, InputFormat::Edrdg)?);
+    let report = consistency::check(&decompositions, &memberships);
+    serde_json::to_writer_pretty(&mut *writer, &report)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    if report.is_consistent() {
+        Ok(())
+    } else {
+        Err(ConvertError::Inconsistent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(names: &[&str]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| format!("../assets/edrdg_files/{}", name))
+            .collect()
+    }
+
+    #[test]
+    fn reports_undeclared_radical() {
+        let mut report = Vec::new();
+        let checked = check(
+            &inputs(&["kradfile", "kradfile2"]),
+            &inputs(&["radkfile", "radkfile2"]),
+            &mut report,
+        );
+        assert!(matches!(checked, Err(ConvertError::Inconsistent)));
+        let report: consistency::Report = serde_json::from_slice(&report).unwrap();
+        assert_eq!(report.undeclared_radicals.len(), 1);
+        assert_eq!(report.undeclared_radicals[0].radical.original, '邑');
+    }
+}
@@ -0,0 +1,217 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    error::ConvertError,
+    krad,
+    opts::{CombinedFormat, CombinedOpts, Layout},
+    radk,
+    sqlite::Database,
+    table::Table,
+};
+use kradical_parsing::{
+    consistency::Source,
+    filter::Filter,
+    json,
+    krad::Decomposition,
+    radk::{Membership, Radical},
+    RadicalTable,
+};
+
+// The radicals and memberships of the radkfiles
+// along with the decompositions of the kradfiles
+struct Combined {
+    sources: Vec<(String, Source)>,
+    memberships: Vec<Membership>,
+    decompositions: Vec<Decomposition>,
+}
+
+impl Combined {
+    fn radicals(&self) -> Vec<Radical> {
+        self.memberships
+            .iter()
+            .map(|membership| membership.radical.clone())
+            .collect()
+    }
+
+    // Radicals used by the kradfiles without a radkfile ident line are missing
+    fn radicals_by_jis(&self) -> HashMap<u16, &Radical> {
+        self.memberships
+            .iter()
+            .map(|membership| (membership.radical.glyph.jis, &membership.radical))
+            .collect()
+    }
+}
+
+pub fn parse(
+    opts: &CombinedOpts,
+    filter: &Filter,
+    writer: &mut dyn Write,
+) -> Result<(), ConvertError> {
+    let krad_sources = opts.krad.iter().map(|path| (path.clone(), Source::Krad));
+    let radk_sources = opts.radk.iter().map(|path| (path.clone(), Source::Radk));
+    let memberships = radk::read(&opts.radk, opts.input_format)?;
+    // The stroke counts of radicals come from the radkfiles before filtering
+    let table = RadicalTable::from_memberships(&memberships);
+    let decompositions = krad::read(&opts.krad, opts.input_format)?;
+    let combined = Combined {
+        sources: krad_sources.chain(radk_sources).collect(),
+        memberships: radk::consolidate(filter.memberships(memberships)),
+        decompositions: filter.decompositions(decompositions, &table),
+    };
+    match opts.output_format {
+        CombinedFormat::Unicode => write_unicode(writer, &combined)?,
+        CombinedFormat::Rust => write_rust(writer, &combined)?,
+        CombinedFormat::Json => json::write_combined(
+            writer,
+            &combined.sources,
+            &combined.radicals(),
+            &combined.decompositions,
+            opts.json_style.into(),
+        )?,
+        CombinedFormat::Csv => write_table(Table::new(writer, ','), &combined, opts.layout)?,
+        CombinedFormat::Tsv => write_table(Table::new(writer, '\t'), &combined, opts.layout)?,
+        CombinedFormat::Sqlite => write_sqlite(&combined).write(opts.output.as_deref())?,
+    }
+    Ok(())
+}
+
+// The sources as comments, then a line for each radical,
+// then a line for each kanji as in the kradfile output
+fn write_unicode(writer: &mut dyn Write, combined: &Combined) -> Result<(), ConvertError> {
+    for (path, _) in &combined.sources {
+        writeln!(writer, "# Source: {}", path)?;
+    }
+    for radical in combined.radicals() {
+        writeln!(writer, "{}", radk::unicode_radical(&radical))?;
+    }
+    writeln!(writer)?;
+    krad::write_unicode(writer, &combined.decompositions)?;
+    Ok(())
+}
+
+fn write_rust(writer: &mut dyn Write, combined: &Combined) -> Result<(), ConvertError> {
+    for (path, _) in &combined.sources {
+        writeln!(writer, "// Source: {}", path)?;
+    }
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "use super::{{Alternate, Decomposition, Membership}};"
+    )?;
+    writeln!(writer)?;
+    krad::write_rust_const(writer, &combined.decompositions)?;
+    writeln!(writer)?;
+    writeln!(writer)?;
+    radk::write_rust_const(writer, &combined.memberships)?;
+    Ok(())
+}
+
+fn write_table<W: Write>(
+    mut table: Table<W>,
+    combined: &Combined,
+    layout: Layout,
+) -> Result<(), ConvertError> {
+    match layout {
+        Layout::Wide => {
+            table.row(&["kanji", "radicals"])?;
+            for decomposition in &combined.decompositions {
+                let radicals: Vec<_> = decomposition
+                    .radicals
+                    .iter()
+                    .map(|radical| radical.display.as_str())
+                    .collect();
+                table.row(&[decomposition.kanji.as_str(), &radicals.join(" ")])?;
+            }
+        }
+        Layout::Long => {
+            table.row(&["kanji", "radical", "strokes", "alternate"])?;
+            let radicals = combined.radicals_by_jis();
+            for decomposition in &combined.decompositions {
+                for glyph in &decomposition.radicals {
+                    let radical = radicals.get(&glyph.jis);
+                    let strokes = radical.map(|radical| radical.strokes.to_string());
+                    let alternate = radical.and_then(|radical| radk::alternate(radical));
+                    table.row(&[
+                        decomposition.kanji.as_str(),
+                        &glyph.display,
+                        &strokes.unwrap_or_default(),
+                        alternate.unwrap_or_default(),
+                    ])?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Radicals from the kradfiles share the rows of radkfile radicals with the same JIS code
+fn write_sqlite(combined: &Combined) -> Database {
+    let sources: Vec<_> = combined
+        .sources
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect();
+    let mut database = Database::new(&sources);
+    for radical in combined.radicals() {
+        radk::add_radical(&mut database, &radical);
+    }
+    for decomposition in &combined.decompositions {
+        let kanji = database.kanji(&decomposition.kanji);
+        for radical in &decomposition.radicals {
+            let radical = database.radical(radical.jis, &radical.display, None, None);
+            database.pair(kanji, radical);
+        }
+    }
+    database
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opts::{FilterOpts, InputFormat, JsonStyle};
+
+    fn opts(output_format: CombinedFormat) -> CombinedOpts {
+        let files = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| format!("../assets/edrdg_files/{}", name))
+                .collect()
+        };
+        CombinedOpts {
+            output_format,
+            input_format: InputFormat::Edrdg,
+            json_style: JsonStyle::Compact,
+            layout: Layout::Long,
+            filter: FilterOpts::default(),
+            krad: files(&["kradfile", "kradfile2"]),
+            radk: files(&["radkfile", "radkfile2"]),
+            output: None,
+        }
+    }
+
+    fn convert(opts: &CombinedOpts) -> Result<Vec<u8>, ConvertError> {
+        let mut out = vec![];
+        parse(opts, &Filter::default(), &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn writes_json() {
+        let bytes = convert(&opts(CombinedFormat::Json)).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(document["sources"].as_array().unwrap().len(), 4);
+        assert_eq!(document["sources"][2]["kind"], "radk");
+        assert_eq!(document["radicals"].as_array().unwrap().len(), 253);
+        assert_eq!(document["kanji"][0]["kanji"], "亜");
+    }
+
+    #[test]
+    fn writes_radicals_in_long_table() {
+        let bytes = convert(&opts(CombinedFormat::Csv)).unwrap();
+        let table = String::from_utf8(bytes).unwrap();
+        let mut rows = table.split("\r\n");
+        assert_eq!(rows.next(), Some("kanji,radical,strokes,alternate"));
+        assert_eq!(rows.next(), Some("亜,｜,1,"));
+        assert!(table.contains("\r\n悒,邑,,\r\n"));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    error::ConvertError,
+    opts::DiffFormat,
+    stdio::{self, STDIO},
+};
+use kradical_parsing::{
+    detect::{parse_any, parse_any_file, Contents},
+    diff::{self, Diff},
+    radk::{Alternate, Radical},
+};
+
+pub fn diff(old: &str, new: &str, format: DiffFormat) -> Result<Vec<u8>, ConvertError> {
+    // Standard input can only be read once
+    if old == STDIO && new == STDIO {
+        return Err(ConvertError::DoubleStdin);
+    }
+    let diff = match (parse(old)?, parse(new)?) {
+        (Contents::Krad(old), Contents::Krad(new)) => diff::diff_decompositions(&old, &new),
+        (Contents::Radk(old), Contents::Radk(new)) => diff::diff_memberships(&old, &new),
+        _ => return Err(ConvertError::Mismatch),
+    };
+    let out = match format {
+        DiffFormat::Text => to_text(&diff).into_bytes(),
+        DiffFormat::Json => {
+            let mut out = serde_json::to_vec_pretty(&diff)?;
+            out.push(b'\n');
+            out
+        }
+    };
+    Ok(out)
+}
+
+fn parse(path: &str) -> Result<Contents, ConvertError> {
+    let contents = if path == STDIO {
+        stdio::read_stdin()
+            .map_err(Into::into)
+            .and_then(|b| parse_any(&b))
+    } else {
+        parse_any_file(path)
+    };
+    contents.map_err(|err| ConvertError::ParseAny(path.to_string(), err))
+}
+
+// One line per change, marked + for added, - for removed, and ~ for changed
+fn to_text(diff: &Diff) -> String {
+    let mut lines = vec![];
+    for kanji in &diff.added_kanji {
+        lines.push(format!("+ {}", kanji));
+    }
+    for kanji in &diff.removed_kanji {
+        lines.push(format!("- {}", kanji));
+    }
+    for change in &diff.changed_kanji {
+        let added = change.added.iter().map(|radical| format!("+{}", radical));
+        let removed = change.removed.iter().map(|radical| format!("-{}", radical));
+        let radicals: Vec<_> = added.chain(removed).collect();
+        lines.push(format!("~ {} : {}", change.kanji, radicals.join(" ")));
+    }
+    for radical in &diff.added_radicals {
+        lines.push(format!("+ {}", ident(radical)));
+    }
+    for radical in &diff.removed_radicals {
+        lines.push(format!("- {}", ident(radical)));
+    }
+    for change in &diff.changed_radicals {
+        lines.push(format!(
+            "~ {} -> {}",
+            ident(&change.old),
+            ident(&change.new)
+        ));
+    }
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+// Like a radkfile ident line
+fn ident(radical: &Radical) -> String {
+    let ident = format!("$ {} {}", radical.glyph, radical.strokes);
+    match &radical.alternate {
+        Alternate::Image(alternate) | Alternate::Glyph(alternate) => {
+            format!("{} {}", ident, alternate)
+        }
+        Alternate::None => ident,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_no_differences() {
+        let path = "../assets/edrdg_files/kradfile";
+        let out = diff(path, path, DiffFormat::Text).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn rejects_mismatched_files() {
+        let res = diff(
+            "../assets/edrdg_files/kradfile",
+            "../assets/edrdg_files/radkfile",
+            DiffFormat::Json,
+        );
+        assert!(matches!(res, Err(ConvertError::Mismatch)));
+    }
+
+    #[test]
+    fn rejects_standard_input_twice() {
+        let res = diff(STDIO, STDIO, DiffFormat::Text);
+        assert!(matches!(res, Err(ConvertError::DoubleStdin)));
+    }
+
+    #[test]
+    fn lists_changes() {
+        let out = diff(
+            "../assets/edrdg_files/radkfile",
+            "../assets/edrdg_files/radkfile2",
+            DiffFormat::Text,
+        )
+        .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.lines().any(|line| line == "+ 丂"));
+        assert!(text.lines().any(|line| line == "- 亜"));
+        assert!(!text.lines().any(|line| line.starts_with("~ $")));
+    }
+}
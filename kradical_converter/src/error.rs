@@ -1,4 +1,4 @@
-use kradical_parsing::{krad::KradError, radk::RadkError};
+use kradical_parsing::{detect::ParseAnyError, krad::KradError, radk::RadkError};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,6 +9,48 @@ pub enum ConvertError {
     #[error("Error during radk parsing")]
     Radk(#[from] RadkError),
 
+    #[error("Error while parsing {0}")]
+    ParseAny(String, #[source] ParseAnyError),
+
+    #[error("Cannot compare a kradfile with a radkfile")]
+    Mismatch,
+
+    #[error("Only one of the files to compare can be standard input")]
+    DoubleStdin,
+
+    #[error("The kradfiles and radkfiles are inconsistent")]
+    Inconsistent,
+
+    #[error("Unknown radical {0}")]
+    UnknownRadical(String),
+
+    #[error("Filtering by radical stroke count requires the radkfiles")]
+    StrokesWithoutRadk,
+
+    #[error("Invalid manifest arguments for {0}")]
+    Manifest(String, #[source] clap::Error),
+
+    #[error("Invalid manifest")]
+    ManifestSyntax(#[from] toml::de::Error),
+
+    #[error("Rustfmt is needed to format generated Rust, as installed by `rustup component add rustfmt`")]
+    RustfmtMissing,
+
+    #[error("Rustfmt failed with {0}")]
+    Rustfmt(std::process::ExitStatus),
+
+    #[error("Generated files are out of date: {}", .0.join(", "))]
+    Stale(Vec<String>),
+
+    #[error("SQLite databases must be written to a file given with --output")]
+    SqliteOutput,
+
+    #[error("Error while writing the SQLite database")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("Error during JSON serialization")]
+    Json(#[from] serde_json::Error),
+
     #[error("IO error")]
     Io(#[from] std::io::Error),
 }
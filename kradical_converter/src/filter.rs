@@ -0,0 +1,71 @@
+// Builds the filter for the records from the command line options
+
+use std::{collections::HashSet, fs};
+
+use crate::{error::ConvertError, opts::FilterOpts};
+use kradical_parsing::{filter::Filter, mapping::RadicalMapping};
+
+pub fn filter(opts: &FilterOpts) -> Result<Filter, ConvertError> {
+    let kanji = match &opts.kanji_list {
+        Some(path) => Some(kanji_list(&fs::read_to_string(path)?)),
+        None => None,
+    };
+    let radicals = if opts.only_radicals.is_empty() {
+        None
+    } else {
+        let mapping = RadicalMapping::default();
+        let radicals: Result<_, _> = opts
+            .only_radicals
+            .iter()
+            .map(|radical| match mapping.from_display(radical) {
+                Some(glyph) => Ok(glyph.jis),
+                None => Err(ConvertError::UnknownRadical(radical.clone())),
+            })
+            .collect();
+        Some(radicals?)
+    };
+    Ok(Filter {
+        kanji,
+        strokes: opts.radical_strokes.clone(),
+        radicals,
+        exclude_jis212: opts.exclude_kradfile2,
+    })
+}
+
+// Every character other than whitespace,
+// so that the kanji may be on separate lines or run together
+fn kanji_list(text: &str) -> HashSet<String> {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.chars())
+        .filter(|c| !c.is_whitespace())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_kanji_list() {
+        let kanji = kanji_list("# Jōyō kanji\n亜 哀\n挨愛\n");
+        let expected: HashSet<_> = ["亜", "哀", "挨", "愛"]
+            .iter()
+            .map(|kanji| kanji.to_string())
+            .collect();
+        assert_eq!(kanji, expected);
+    }
+
+    #[test]
+    fn rejects_unknown_radical() {
+        let opts = FilterOpts {
+            only_radicals: vec!["口".to_string(), "x".to_string()],
+            ..FilterOpts::default()
+        };
+        assert!(matches!(
+            filter(&opts),
+            Err(ConvertError::UnknownRadical(radical)) if radical == "x"
+        ));
+    }
+}
@@ -1,76 +1,291 @@
-use crate::opts::OutputFormat;
-use kradical_parsing::krad::{self, Decomposition, KradError};
-
-pub fn parse(inputs: &[String], format: OutputFormat) -> Result<String, KradError> {
-    let parsed: Result<Vec<_>, _> = inputs.iter().map(krad::parse_file).collect();
-    let parsed: Vec<_> = parsed?
-        .into_iter()
-        .flat_map(|file| file.into_iter())
-        .collect();
-    Ok(formatter(format)(&parsed))
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    slice,
+};
+
+use crate::{
+    error::ConvertError,
+    opts::{ConvertOpts, InputFormat, JsonStyle, Layout, OutputFormat},
+    sqlite::Database,
+    stdio::{self, STDIO},
+    table::Table,
+};
+use kradical_parsing::{
+    decompress,
+    filter::Filter,
+    json,
+    krad::{self, Decomposition, Decompositions, KradError},
+    options::ParseOptions,
+    RadicalTable,
+};
+
+pub fn parse(
+    opts: &ConvertOpts,
+    filter: &Filter,
+    writer: &mut dyn Write,
+) -> Result<(), ConvertError> {
+    let inputs = &opts.inputs;
+    if opts.output_format == OutputFormat::Edrdg
+        && opts.input_format == InputFormat::Edrdg
+        && filter.keeps_everything()
+    {
+        return Ok(write_kradfile(inputs, writer)?);
+    }
+    if opts.input_format == InputFormat::Edrdg && streams(opts) {
+        return write_each(opts, filter, writer);
+    }
+    let parsed = read(inputs, opts.input_format)?;
+    let parsed = filter.decompositions(parsed, &RadicalTable::default());
+    match opts.output_format {
+        OutputFormat::Unicode => write_unicode(writer, &parsed)?,
+        OutputFormat::Rust => write_rust(writer, &parsed)?,
+        OutputFormat::Json => json::write_decompositions(writer, &parsed, opts.json_style.into())?,
+        OutputFormat::Edrdg => krad::write_kradfile(writer, &parsed)?,
+        OutputFormat::Csv => write_table(Table::new(writer, ','), &parsed, opts.layout)?,
+        OutputFormat::Tsv => write_table(Table::new(writer, '\t'), &parsed, opts.layout)?,
+        OutputFormat::Sqlite => {
+            write_sqlite(Database::new(inputs), &parsed).write(opts.output.as_deref())?
+        }
+    }
+    Ok(())
 }
 
-fn formatter(format: OutputFormat) -> fn(&[Decomposition]) -> String {
-    match format {
-        OutputFormat::Unicode => to_unicode,
-        OutputFormat::Rust => to_rust,
-        OutputFormat::Json => to_json,
+// The decompositions of the inputs one after another
+pub fn read(inputs: &[String], input_format: InputFormat) -> Result<Vec<Decomposition>, KradError> {
+    let mut decompositions = vec![];
+    for input in inputs {
+        decompositions.extend(read_input(input, input_format)?);
     }
+    Ok(decompositions)
 }
 
-fn to_json(decompositions: &[Decomposition]) -> String {
-    let mut lines = vec![];
-    lines.push("[".to_owned());
-    for (i, decomposition) in decompositions.iter().enumerate() {
-        lines.push("\t{".to_owned());
-        lines.push(format!("\t\t\"kanji\": \"{}\",", decomposition.kanji));
-        lines.push("\t\t\"radicals\": [".to_owned());
-        for (i, radical) in decomposition.radicals.iter().enumerate() {
-            if i == decomposition.radicals.len() - 1 {
-                lines.push(format!("\t\t\t\"{}\"", radical));
-            } else {
-                lines.push(format!("\t\t\t\"{}\",", radical));
+fn read_input(input: &str, input_format: InputFormat) -> Result<Vec<Decomposition>, KradError> {
+    match (input == STDIO, input_format) {
+        (_, InputFormat::Edrdg) => records(input)?.collect(),
+        (false, InputFormat::Utf8) => krad::parse_utf8_file(input),
+        (false, InputFormat::Json) => krad::parse_json_file(input),
+        (true, InputFormat::Utf8) => krad::parse_utf8(&stdio::read_stdin_text()?),
+        (true, InputFormat::Json) => krad::parse_json(&stdio::read_stdin_text()?),
+    }
+}
+
+// The decompositions of an EDRDG input as they are read
+fn records(input: &str) -> Result<Decompositions<Box<dyn BufRead>>, KradError> {
+    let reader = if input == STDIO {
+        stdio::stdin_reader()?
+    } else {
+        decompress(BufReader::new(File::open(input)?))?
+    };
+    Ok(Decompositions::new(reader))
+}
+
+// Whether each decomposition can be written without knowing the others
+fn streams(opts: &ConvertOpts) -> bool {
+    match opts.output_format {
+        OutputFormat::Unicode | OutputFormat::Edrdg | OutputFormat::Csv | OutputFormat::Tsv => true,
+        OutputFormat::Json => opts.json_style == JsonStyle::Lines,
+        OutputFormat::Rust | OutputFormat::Sqlite => false,
+    }
+}
+
+// Writes each decomposition as soon as it is read,
+// so that a pipeline produces output before its input ends
+fn write_each(
+    opts: &ConvertOpts,
+    filter: &Filter,
+    writer: &mut dyn Write,
+) -> Result<(), ConvertError> {
+    match opts.output_format {
+        OutputFormat::Csv => write_table_header(&mut Table::new(&mut *writer, ','), opts.layout)?,
+        OutputFormat::Tsv => write_table_header(&mut Table::new(&mut *writer, '\t'), opts.layout)?,
+        _ => {}
+    }
+    let table = RadicalTable::default();
+    let mut first = true;
+    for input in &opts.inputs {
+        for decomposition in records(input)? {
+            for decomposition in filter.decompositions(vec![decomposition?], &table) {
+                let decomposition = slice::from_ref(&decomposition);
+                match opts.output_format {
+                    OutputFormat::Unicode => {
+                        if !first {
+                            writeln!(writer)?;
+                        }
+                        write_unicode(writer, decomposition)?
+                    }
+                    OutputFormat::Json => json::write_decompositions(
+                        &mut *writer,
+                        decomposition,
+                        opts.json_style.into(),
+                    )?,
+                    OutputFormat::Edrdg => krad::write_kradfile(&mut *writer, decomposition)?,
+                    OutputFormat::Csv => write_table_rows(
+                        &mut Table::new(&mut *writer, ','),
+                        decomposition,
+                        opts.layout,
+                    )?,
+                    OutputFormat::Tsv => write_table_rows(
+                        &mut Table::new(&mut *writer, '\t'),
+                        decomposition,
+                        opts.layout,
+                    )?,
+                    OutputFormat::Rust | OutputFormat::Sqlite => unreachable!(),
+                }
+                first = false;
             }
         }
-        lines.push("\t\t]".to_owned());
-        if i == decompositions.len() - 1 {
-            lines.push("\t}".to_owned());
+    }
+    Ok(())
+}
+
+// The inputs one after another, including their comments
+fn write_kradfile(inputs: &[String], writer: &mut dyn Write) -> Result<(), KradError> {
+    for input in inputs {
+        let file = if input == STDIO {
+            krad::parse_bytes_with_comments(&stdio::read_stdin()?, &ParseOptions::default())?
         } else {
-            lines.push("\t},".to_owned());
+            krad::parse_file_with_comments(input, &ParseOptions::default())?
+        };
+        krad::write_kradfile_with_comments(&mut *writer, &file)?;
+    }
+    Ok(())
+}
+
+fn write_table<W: Write>(
+    mut table: Table<W>,
+    decompositions: &[Decomposition],
+    layout: Layout,
+) -> Result<(), KradError> {
+    write_table_header(&mut table, layout)?;
+    write_table_rows(&mut table, decompositions, layout)
+}
+
+fn write_table_header<W: Write>(table: &mut Table<W>, layout: Layout) -> Result<(), KradError> {
+    match layout {
+        Layout::Wide => table.row(&["kanji", "radicals"])?,
+        Layout::Long => table.row(&["kanji", "radical"])?,
+    }
+    Ok(())
+}
+
+fn write_table_rows<W: Write>(
+    table: &mut Table<W>,
+    decompositions: &[Decomposition],
+    layout: Layout,
+) -> Result<(), KradError> {
+    match layout {
+        Layout::Wide => {
+            for decomposition in decompositions {
+                let radicals: Vec<_> = decomposition
+                    .radicals
+                    .iter()
+                    .map(|radical| radical.display.as_str())
+                    .collect();
+                table.row(&[decomposition.kanji.as_str(), &radicals.join(" ")])?;
+            }
+        }
+        Layout::Long => {
+            for decomposition in decompositions {
+                for radical in &decomposition.radicals {
+                    table.row(&[&decomposition.kanji, &radical.display])?;
+                }
+            }
         }
     }
-    lines.push("]".to_owned());
-    lines.join("\n")
+    Ok(())
 }
 
-fn to_unicode(decompositions: &[Decomposition]) -> String {
-    let lines: Vec<String> = decompositions
-        .iter()
-        .map(|decomposition| {
-            let radicals = decomposition.radicals.join(" ");
-            format!("{} : {}", decomposition.kanji, &radicals)
-        })
-        .collect();
-    lines.join("\n")
+// Kradfiles have no stroke counts or alternates for the radicals
+fn write_sqlite(mut database: Database, decompositions: &[Decomposition]) -> Database {
+    for decomposition in decompositions {
+        let kanji = database.kanji(&decomposition.kanji);
+        for radical in &decomposition.radicals {
+            let radical = database.radical(radical.jis, &radical.display, None, None);
+            database.pair(kanji, radical);
+        }
+    }
+    database
 }
 
-fn to_rust(decompositions: &[Decomposition]) -> String {
-    let mut lines = vec![
-        "use super::Decomposition;".to_string(),
-        "".to_string(),
-        "/// The list of radical decompositions from the `kradfile`".to_string(),
-        "pub const DECOMPOSITIONS: &[Decomposition] = &[".to_string(),
-    ];
+pub fn write_unicode(
+    writer: &mut dyn Write,
+    decompositions: &[Decomposition],
+) -> Result<(), KradError> {
+    for (i, decomposition) in decompositions.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer)?;
+        }
+        let radicals: Vec<_> = decomposition
+            .radicals
+            .iter()
+            .map(|radical| radical.display.as_str())
+            .collect();
+        write!(writer, "{} : {}", decomposition.kanji, radicals.join(" "))?;
+    }
+    Ok(())
+}
+
+fn write_rust(writer: &mut dyn Write, decompositions: &[Decomposition]) -> Result<(), KradError> {
+    writeln!(writer, "use super::Decomposition;")?;
+    writeln!(writer)?;
+    write_rust_const(writer, decompositions)
+}
+
+pub fn write_rust_const(
+    writer: &mut dyn Write,
+    decompositions: &[Decomposition],
+) -> Result<(), KradError> {
+    writeln!(
+        writer,
+        "/// The list of radical decompositions from the `kradfile`"
+    )?;
+    writeln!(writer, "pub const DECOMPOSITIONS: &[Decomposition] = &[")?;
     for decomposition in decompositions {
-        lines.push("\t Decomposition {".to_string());
-        lines.push(format!("\t\tkanji: \'{}\',", decomposition.kanji));
-        lines.push("\t\tradicals: &[".to_string());
+        writeln!(writer, "\t Decomposition {{")?;
+        writeln!(writer, "\t\tkanji: \'{}\',", decomposition.kanji)?;
+        writeln!(writer, "\t\tradicals: &[")?;
         for radical in decomposition.radicals.iter() {
-            lines.push(format!("\t\t\t\'{}\',", radical));
+            writeln!(writer, "\t\t\t\'{}\',", radical)?;
+        }
+        writeln!(writer, "\t\t],")?;
+        writeln!(writer, "\t}},")?;
+    }
+    write!(writer, "];")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opts::FilterOpts;
+
+    fn convert(inputs: &[&str], input_format: InputFormat, output_format: OutputFormat) -> Vec<u8> {
+        let opts = ConvertOpts {
+            output_format,
+            input_format,
+            json_style: JsonStyle::Lines,
+            layout: Layout::Long,
+            filter: FilterOpts::default(),
+            inputs: inputs
+                .iter()
+                .map(|name| format!("../assets/{}", name))
+                .collect(),
+            output: None,
+        };
+        let mut out = vec![];
+        parse(&opts, &Filter::default(), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn streams_like_collected_output() {
+        let edrdg = ["edrdg_files/kradfile", "edrdg_files/kradfile2"];
+        let utf8 = ["outputs/krad_utf8.txt"];
+        for output_format in [OutputFormat::Csv, OutputFormat::Json] {
+            let streamed = convert(&edrdg, InputFormat::Edrdg, output_format);
+            let collected = convert(&utf8, InputFormat::Utf8, output_format);
+            assert!(streamed == collected);
         }
-        lines.push("\t\t],".to_string());
-        lines.push("\t},".to_string());
     }
-    lines.push("];".to_string());
-    lines.join("\n")
 }
@@ -1,25 +1,112 @@
-use clap::Clap;
+use clap::Parser;
 use error::ConvertError;
-use std::{fs::OpenOptions, io::Write};
+use std::{
+    error::Error,
+    io::{self, ErrorKind, Write},
+    process,
+};
 
-use crate::opts::{InputFormat, Opts};
+use crate::opts::{CombinedFormat, Command, Opts, OutputFormat};
 
+mod check;
+mod combined;
+mod diff;
 mod error;
+mod filter;
 mod krad;
 mod opts;
 mod radk;
+mod regenerate;
+mod sqlite;
+mod stdio;
+mod table;
 
-fn main() -> Result<(), ConvertError> {
-    let opts = Opts::parse();
-    let text = match opts.input_format {
-        InputFormat::Radk => radk::parse(&opts.inputs, opts.output_format)?,
-        InputFormat::Krad => krad::parse(&opts.inputs, opts.output_format)?,
-    };
-    OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(opts.output)
-        .and_then(|mut file| file.write_all(text.as_bytes()))?;
+fn main() {
+    if let Err(error) = run(&Opts::parse()) {
+        report(&error);
+        process::exit(1);
+    }
+}
+
+// Prints the error followed by each of the errors that caused it
+fn report(error: &ConvertError) {
+    eprintln!("Error: {}", error);
+    let mut source = error.source();
+    while let Some(error) = source {
+        eprintln!("Caused by: {}", error);
+        source = error.source();
+    }
+}
+
+fn run(opts: &Opts) -> Result<(), ConvertError> {
+    match &opts.command {
+        Command::Regenerate(opts) => regenerate::regenerate(opts)?,
+        // The database is written straight to its file
+        command if writes_database(command) => convert(command, &mut io::sink())?,
+        command => {
+            let mut writer = stdio::open_output(output(command))?;
+            let written = convert(command, &mut writer).and_then(|_| Ok(writer.flush()?));
+            match written {
+                Err(error) if is_broken_pipe(&error) => {}
+                written => written?,
+            }
+        }
+    }
     Ok(())
 }
+
+// The reader closing the pipe early, as head does, is not a failure
+fn is_broken_pipe(error: &ConvertError) -> bool {
+    let mut source: Option<&dyn Error> = Some(error);
+    while let Some(error) = source {
+        // JSON errors do not expose their IO errors as sources
+        let kind = match error.downcast_ref::<serde_json::Error>() {
+            Some(error) => error.io_error_kind(),
+            None => error.downcast_ref::<io::Error>().map(io::Error::kind),
+        };
+        if kind == Some(ErrorKind::BrokenPipe) {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+// Writes the output of any subcommand other than regenerate
+fn convert(command: &Command, writer: &mut dyn Write) -> Result<(), ConvertError> {
+    match command {
+        Command::Radk(opts) => radk::parse(opts, &filter::filter(&opts.filter)?, writer)?,
+        Command::Krad(opts) => {
+            // Kradfiles have no stroke counts for their radicals
+            if opts.filter.radical_strokes.is_some() {
+                return Err(ConvertError::StrokesWithoutRadk);
+            }
+            krad::parse(opts, &filter::filter(&opts.filter)?, writer)?
+        }
+        Command::Combined(opts) => combined::parse(opts, &filter::filter(&opts.filter)?, writer)?,
+        Command::Check(opts) => check::check(&opts.krad, &opts.radk, writer)?,
+        Command::Diff(opts) => {
+            writer.write_all(&diff::diff(&opts.old, &opts.new, opts.format)?)?;
+        }
+        Command::Regenerate(_) => unreachable!(),
+    }
+    Ok(())
+}
+
+fn writes_database(command: &Command) -> bool {
+    match command {
+        Command::Radk(opts) | Command::Krad(opts) => opts.output_format == OutputFormat::Sqlite,
+        Command::Combined(opts) => opts.output_format == CombinedFormat::Sqlite,
+        _ => false,
+    }
+}
+
+fn output(command: &Command) -> Option<&str> {
+    match command {
+        Command::Radk(opts) | Command::Krad(opts) => opts.output.as_deref(),
+        Command::Combined(opts) => opts.output.as_deref(),
+        Command::Check(opts) => opts.output.as_deref(),
+        Command::Diff(opts) => opts.output.as_deref(),
+        Command::Regenerate(_) => None,
+    }
+}
@@ -1,30 +1,273 @@
-use clap::{AppSettings, Clap};
+use clap::{ArgEnum, Args, Parser, Subcommand};
+use kradical_parsing::json::Style;
+use std::ops::RangeInclusive;
 
-#[derive(Clap, Clone, PartialEq, Eq, Debug)]
-#[clap(setting = AppSettings::ColoredHelp)]
+#[derive(Parser, Clone, PartialEq, Eq, Debug)]
 pub struct Opts {
-    #[clap(arg_enum)]
-    pub input_format: InputFormat,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// Convert radkfile and radkfile2
+    Radk(ConvertOpts),
+
+    /// Convert kradfile and kradfile2
+    Krad(ConvertOpts),
 
+    /// Convert the radicals of radkfile and radkfile2
+    /// together with the decompositions of kradfile and kradfile2
+    Combined(CombinedOpts),
+
+    /// Report inconsistencies between the kradfiles and radkfiles as JSON
+    Check(CheckOpts),
+
+    /// Report the differences between two releases of a kradfile or radkfile
+    Diff(DiffOpts),
+
+    /// Rebuild the generated files listed in a manifest
+    Regenerate(RegenerateOpts),
+}
+
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct ConvertOpts {
     #[clap(arg_enum)]
     pub output_format: OutputFormat,
 
-    #[clap(short, long, required = true)]
+    /// The format of the inputs
+    #[clap(arg_enum, long, default_value = "edrdg")]
+    pub input_format: InputFormat,
+
+    /// The layout of JSON output
+    #[clap(arg_enum, long, default_value = "pretty")]
+    pub json_style: JsonStyle,
+
+    /// The layout of CSV and TSV output, either one row per record
+    /// or one row per kanji and radical pair
+    #[clap(arg_enum, long, default_value = "wide")]
+    pub layout: Layout,
+
+    #[clap(flatten)]
+    pub filter: FilterOpts,
+
+    /// The files to convert, with `-` for standard input
+    #[clap(short, long, required = true, multiple_values = true)]
     pub inputs: Vec<String>,
 
+    /// Where to write the output instead of standard output.
+    /// SQLite databases can only be written to a file.
+    #[clap(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct CombinedOpts {
+    #[clap(arg_enum)]
+    pub output_format: CombinedFormat,
+
+    /// The format of the inputs
+    #[clap(arg_enum, long, default_value = "edrdg")]
+    pub input_format: InputFormat,
+
+    /// The layout of JSON output
+    #[clap(arg_enum, long, default_value = "pretty")]
+    pub json_style: JsonStyle,
+
+    /// The layout of CSV and TSV output, either one row per kanji
+    /// or one row per kanji and radical pair
+    #[clap(arg_enum, long, default_value = "wide")]
+    pub layout: Layout,
+
+    #[clap(flatten)]
+    pub filter: FilterOpts,
+
+    /// The kradfiles, such as kradfile and kradfile2
+    #[clap(long, required = true, multiple_values = true)]
+    pub krad: Vec<String>,
+
+    /// The radkfiles, such as radkfile and radkfile2
+    #[clap(long, required = true, multiple_values = true)]
+    pub radk: Vec<String>,
+
+    /// Where to write the output instead of standard output.
+    /// SQLite databases can only be written to a file.
     #[clap(short, long)]
-    pub output: String,
+    pub output: Option<String>,
+}
+
+#[derive(Args, Clone, Default, PartialEq, Eq, Debug)]
+pub struct FilterOpts {
+    /// A UTF-8 file listing the kanji to keep,
+    /// where lines starting with # are ignored
+    #[clap(long)]
+    pub kanji_list: Option<String>,
+
+    /// The stroke counts of the radicals to keep, such as 1..4 for one to four strokes.
+    /// Requires the radkfiles.
+    #[clap(long, value_parser = parse_strokes)]
+    pub radical_strokes: Option<RangeInclusive<u8>>,
+
+    /// The radicals to keep
+    #[clap(long, multiple_values = true)]
+    pub only_radicals: Vec<String>,
+
+    /// Leave out the JIS X 0212 kanji of kradfile2 and radkfile2
+    #[clap(long)]
+    pub exclude_kradfile2: bool,
+}
+
+// Either a single stroke count or a range including both ends,
+// either of which may be left open
+fn parse_strokes(range: &str) -> Result<RangeInclusive<u8>, String> {
+    let bound = |bound: &str, default: u8| match bound.trim() {
+        "" => Ok(default),
+        bound => bound
+            .parse()
+            .map_err(|_| format!("Invalid stroke count {}", bound)),
+    };
+    match range.split_once("..") {
+        Some((start, end)) => {
+            let end = end.strip_prefix('=').unwrap_or(end);
+            let (start, end) = (bound(start, u8::MIN)?, bound(end, u8::MAX)?);
+            if start > end {
+                return Err(format!("Empty stroke range {}", range));
+            }
+            Ok(start..=end)
+        }
+        None => {
+            let strokes = bound(range, 0)?;
+            Ok(strokes..=strokes)
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Clap, Debug)]
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct CheckOpts {
+    /// The kradfiles, such as kradfile and kradfile2
+    #[clap(long, required = true, multiple_values = true)]
+    pub krad: Vec<String>,
+
+    /// The radkfiles, such as radkfile and radkfile2
+    #[clap(long, required = true, multiple_values = true)]
+    pub radk: Vec<String>,
+
+    /// Where to write the report instead of standard output
+    #[clap(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct DiffOpts {
+    /// The old release
+    pub old: String,
+
+    /// The new release
+    pub new: String,
+
+    #[clap(arg_enum, short, long, default_value = "text")]
+    pub format: DiffFormat,
+
+    /// Where to write the differences instead of standard output
+    #[clap(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct RegenerateOpts {
+    /// The manifest listing each generated file with the arguments that produce it
+    #[clap(long, default_value = "outputs.toml")]
+    pub manifest: String,
+
+    /// Fail if any generated file is out of date instead of rewriting it
+    #[clap(long)]
+    pub check: bool,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, ArgEnum, Debug)]
 pub enum InputFormat {
-    Radk,
-    Krad,
+    Edrdg,
+    Utf8,
+    Json,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, ArgEnum, Debug)]
+pub enum JsonStyle {
+    Pretty,
+    Compact,
+    Lines,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Clap, Debug)]
+impl From<JsonStyle> for Style {
+    fn from(style: JsonStyle) -> Self {
+        match style {
+            JsonStyle::Pretty => Style::Pretty,
+            JsonStyle::Compact => Style::Compact,
+            JsonStyle::Lines => Style::Lines,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, ArgEnum, Debug)]
+pub enum Layout {
+    Wide,
+    Long,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, ArgEnum, Debug)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, ArgEnum, Debug)]
 pub enum OutputFormat {
     Unicode,
     Rust,
     Json,
+    Edrdg,
+    Csv,
+    Tsv,
+    Sqlite,
+}
+
+// The edrdg format cannot hold both kinds of file
+#[derive(PartialEq, Eq, Clone, Copy, ArgEnum, Debug)]
+pub enum CombinedFormat {
+    Unicode,
+    Rust,
+    Json,
+    Csv,
+    Tsv,
+    Sqlite,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stroke_ranges() {
+        assert_eq!(parse_strokes("1..4"), Ok(1..=4));
+        assert_eq!(parse_strokes("1..=4"), Ok(1..=4));
+        assert_eq!(parse_strokes("3"), Ok(3..=3));
+        assert_eq!(parse_strokes("..2"), Ok(0..=2));
+        assert_eq!(parse_strokes("10.."), Ok(10..=255));
+        assert!(parse_strokes("one..4").is_err());
+        assert!(parse_strokes("4..1").is_err());
+    }
+
+    #[test]
+    fn rejects_combined_edrdg() {
+        let arguments = [
+            "kradical_converter",
+            "combined",
+            "edrdg",
+            "--krad",
+            "kradfile",
+            "--radk",
+            "radkfile",
+        ];
+        assert!(Opts::try_parse_from(arguments).is_err());
+    }
 }
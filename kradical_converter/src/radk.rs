@@ -1,87 +1,208 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
 
-use crate::opts::OutputFormat;
-use kradical_parsing::radk::{self, Membership, Radical, RadkError};
+use crate::{
+    error::ConvertError,
+    opts::{ConvertOpts, InputFormat, Layout, OutputFormat},
+    sqlite::Database,
+    stdio::{self, STDIO},
+    table::Table,
+};
+use kradical_parsing::{
+    filter::Filter,
+    json,
+    options::ParseOptions,
+    radk::{self, Alternate, Membership, Memberships, Radical, RadkError},
+};
 
-pub fn parse(inputs: &[String], format: OutputFormat) -> Result<String, RadkError> {
-    let parsed: Result<Vec<_>, _> = inputs.iter().map(radk::parse_file).collect();
-    let parsed: Vec<_> = parsed?
-        .into_iter()
-        .flat_map(|file| file.into_iter())
-        .collect();
+pub fn parse(
+    opts: &ConvertOpts,
+    filter: &Filter,
+    writer: &mut dyn Write,
+) -> Result<(), ConvertError> {
+    let inputs = &opts.inputs;
+    if opts.output_format == OutputFormat::Edrdg
+        && opts.input_format == InputFormat::Edrdg
+        && filter.keeps_everything()
+    {
+        return Ok(write_radkfilex(inputs, writer)?);
+    }
+    // The kanji of a radical can be spread across radkfile and radkfile2,
+    // so nothing is written until every input has been read
+    let parsed = filter.memberships(read(inputs, opts.input_format)?);
+    if opts.output_format == OutputFormat::Edrdg {
+        return Ok(radk::write_radkfile(writer, &radk::merge(parsed))?);
+    }
     let parsed = consolidate(parsed);
-    Ok(formatter(format)(&parsed))
+    match opts.output_format {
+        OutputFormat::Unicode => write_unicode(writer, &parsed)?,
+        OutputFormat::Rust => write_rust(writer, &parsed)?,
+        OutputFormat::Json => json::write_memberships(writer, &parsed, opts.json_style.into())?,
+        OutputFormat::Csv => write_table(Table::new(writer, ','), &parsed, opts.layout)?,
+        OutputFormat::Tsv => write_table(Table::new(writer, '\t'), &parsed, opts.layout)?,
+        OutputFormat::Sqlite => {
+            write_sqlite(Database::new(inputs), &parsed).write(opts.output.as_deref())?
+        }
+        OutputFormat::Edrdg => unreachable!(),
+    }
+    Ok(())
 }
 
-fn formatter(format: OutputFormat) -> fn(&[Membership]) -> String {
-    match format {
-        OutputFormat::Unicode => to_unicode,
-        OutputFormat::Rust => to_rust,
-        OutputFormat::Json => to_json,
+// The memberships of the inputs one after another
+pub fn read(inputs: &[String], input_format: InputFormat) -> Result<Vec<Membership>, RadkError> {
+    let mut memberships = vec![];
+    for input in inputs {
+        memberships.extend(read_input(input, input_format)?);
     }
+    Ok(memberships)
 }
 
-fn to_json(expansions: &[Membership]) -> String {
-    let mut lines = vec!["[".to_owned()];
-    for (i, expansion) in expansions.iter().enumerate() {
-        lines.push("\t{".to_owned());
-        lines.push(format!("\t\t\"radical\": \"{}\",", expansion.radical.glyph));
-        lines.push(format!("\t\t\"stroke\": {},", expansion.radical.strokes));
-        lines.push("\t\t\"kanji\": [".to_owned());
-        for (i, kanji) in expansion.kanji.iter().enumerate() {
-            if i == expansion.kanji.len() - 1 {
-                lines.push(format!("\t\t\t\"{}\"", kanji));
-            } else {
-                lines.push(format!("\t\t\t\"{}\",", kanji));
+fn read_input(input: &str, input_format: InputFormat) -> Result<Vec<Membership>, RadkError> {
+    match (input == STDIO, input_format) {
+        (false, InputFormat::Edrdg) => radk::parse_file(input),
+        (false, InputFormat::Utf8) => radk::parse_utf8_file(input),
+        (false, InputFormat::Json) => radk::parse_json_file(input),
+        (true, InputFormat::Edrdg) => Memberships::new(stdio::stdin_reader()?).collect(),
+        (true, InputFormat::Utf8) => radk::parse_utf8(&stdio::read_stdin_text()?),
+        (true, InputFormat::Json) => radk::parse_json(&stdio::read_stdin_text()?),
+    }
+}
+
+// Unlike the other formats, keeps the comments of the inputs
+fn write_radkfilex(inputs: &[String], writer: &mut dyn Write) -> Result<(), RadkError> {
+    let mut files = vec![];
+    for input in inputs {
+        files.push(if input == STDIO {
+            radk::parse_bytes_with_comments(&stdio::read_stdin()?, &ParseOptions::default())?
+        } else {
+            radk::parse_file_with_comments(input, &ParseOptions::default())?
+        });
+    }
+    radk::write_radkfile_with_comments(writer, &radk::merge_files(&files))
+}
+
+fn write_table<W: Write>(
+    mut table: Table<W>,
+    memberships: &[Membership],
+    layout: Layout,
+) -> Result<(), RadkError> {
+    match layout {
+        Layout::Wide => table.row(&["radical", "strokes", "alternate", "kanji_count", "kanji"])?,
+        Layout::Long => table.row(&["radical", "strokes", "alternate", "kanji"])?,
+    }
+    for membership in memberships {
+        let radical = &membership.radical;
+        let strokes = radical.strokes.to_string();
+        let alternate = alternate(radical).unwrap_or_default();
+        let glyph = radical.glyph.display.as_str();
+        match layout {
+            Layout::Wide => {
+                let count = membership.kanji.len().to_string();
+                let kanji = membership.kanji.join(" ");
+                table.row(&[glyph, &strokes, alternate, &count, &kanji])?;
+            }
+            Layout::Long => {
+                for kanji in &membership.kanji {
+                    table.row(&[glyph, &strokes, alternate, kanji])?;
+                }
             }
         }
-        lines.push("\t\t]".to_owned());
-        if i == expansions.len() - 1 {
-            lines.push("\t}".to_owned());
-        } else {
-            lines.push("\t},".to_owned());
+    }
+    Ok(())
+}
+
+// The alternate as written in the radkfile
+pub fn alternate(radical: &Radical) -> Option<&str> {
+    match &radical.alternate {
+        Alternate::Image(alternate) | Alternate::Glyph(alternate) => Some(alternate.as_str()),
+        Alternate::None => None,
+    }
+}
+
+fn write_sqlite(mut database: Database, memberships: &[Membership]) -> Database {
+    for membership in memberships {
+        let radical = add_radical(&mut database, &membership.radical);
+        for kanji in &membership.kanji {
+            let kanji = database.kanji(kanji);
+            database.pair(kanji, radical);
         }
     }
-    lines.push("]".to_owned());
-    lines.join("\n")
+    database
 }
 
-fn to_unicode(expansions: &[Membership]) -> String {
-    let lines: Vec<_> = expansions
-        .iter()
-        .map(|expansion| {
-            let kanji = expansion.kanji.join(" ");
-            let radical = &expansion.radical;
-            format!("{} {} : {}", radical.glyph, radical.strokes, kanji)
-        })
-        .collect();
-    lines.join("\n")
+pub fn add_radical(database: &mut Database, radical: &Radical) -> u16 {
+    database.radical(
+        radical.glyph.jis,
+        &radical.glyph.display,
+        Some(radical.strokes),
+        alternate(radical),
+    )
 }
 
-fn to_rust(expansions: &[Membership]) -> String {
-    let mut lines = vec![
-        "use super::Membership;".to_string(),
-        "".to_string(),
-        "/// For each radical, a list of which kanji contain it from the `radkfile`".to_string(),
-        "pub const MEMBERSHIPS: &[Membership] = &[".to_string(),
-    ];
+fn write_unicode(writer: &mut dyn Write, expansions: &[Membership]) -> Result<(), RadkError> {
+    for (i, expansion) in expansions.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer)?;
+        }
+        let kanji = expansion.kanji.join(" ");
+        write!(
+            writer,
+            "{} : {}",
+            unicode_radical(&expansion.radical),
+            kanji
+        )?;
+    }
+    Ok(())
+}
+
+// The glyph, stroke count, and alternate of a radical
+pub fn unicode_radical(radical: &Radical) -> String {
+    match alternate(radical) {
+        Some(alternate) => format!("{} {} {}", radical.glyph, radical.strokes, alternate),
+        None => format!("{} {}", radical.glyph, radical.strokes),
+    }
+}
+
+fn write_rust(writer: &mut dyn Write, memberships: &[Membership]) -> Result<(), RadkError> {
+    writeln!(writer, "use super::{{Alternate, Membership}};")?;
+    writeln!(writer)?;
+    write_rust_const(writer, memberships)
+}
+
+pub fn write_rust_const(
+    writer: &mut dyn Write,
+    expansions: &[Membership],
+) -> Result<(), RadkError> {
+    writeln!(
+        writer,
+        "/// For each radical, a list of which kanji contain it from the `radkfile`"
+    )?;
+    writeln!(writer, "pub const MEMBERSHIPS: &[Membership] = &[")?;
     for expansion in expansions {
-        lines.push("\tMembership {".to_string());
+        writeln!(writer, "\tMembership {{")?;
         let radical = &expansion.radical;
-        lines.push(format!("\t\tradical: \'{}\',", radical.glyph));
-        lines.push(format!("\t\tstrokes: {},", radical.strokes));
-        lines.push("\t\tkanji: &[".to_string());
+        writeln!(writer, "\t\tradical: \'{}\',", radical.glyph)?;
+        writeln!(writer, "\t\tstrokes: {},", radical.strokes)?;
+        let alternate = match &radical.alternate {
+            Alternate::Image(image) => format!("Alternate::Image(\"{}\")", image),
+            Alternate::Glyph(glyph) => format!("Alternate::Glyph(\'{}\')", glyph),
+            Alternate::None => "Alternate::None".to_string(),
+        };
+        writeln!(writer, "\t\talternate: {},", alternate)?;
+        writeln!(writer, "\t\tkanji: &[")?;
         for glyph in &expansion.kanji {
-            lines.push(format!("\t\t\t\'{}\',", glyph));
+            writeln!(writer, "\t\t\t\'{}\',", glyph)?;
         }
-        lines.push("\t\t],".to_string());
-        lines.push("\t},".to_string());
+        writeln!(writer, "\t\t],")?;
+        writeln!(writer, "\t}},")?;
     }
-    lines.push("];".to_string());
-    lines.join("\n")
+    write!(writer, "];")?;
+    Ok(())
 }
 
-fn consolidate(expansions: Vec<Membership>) -> Vec<Membership> {
+pub fn consolidate(expansions: Vec<Membership>) -> Vec<Membership> {
     let mut consolidation: HashMap<Radical, HashSet<String>> = HashMap::new();
     for expansion in expansions.into_iter() {
         match consolidation.entry(expansion.radical) {
@@ -112,9 +233,128 @@ fn consolidate(expansions: Vec<Membership>) -> Vec<Membership> {
         } else if l.kanji.len() != r.kanji.len() {
             l.kanji.len().cmp(&r.kanji.len()).reverse()
         } else {
-            l.radical.glyph.cmp(&r.radical.glyph)
+            l.radical.glyph.display.cmp(&r.radical.glyph.display)
         }
     });
 
     consolidation
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opts::{FilterOpts, JsonStyle};
+
+    fn inputs() -> Vec<String> {
+        vec![
+            "../assets/edrdg_files/radkfile".to_string(),
+            "../assets/edrdg_files/radkfile2".to_string(),
+        ]
+    }
+
+    fn opts(
+        inputs: Vec<String>,
+        input_format: InputFormat,
+        output_format: OutputFormat,
+    ) -> ConvertOpts {
+        ConvertOpts {
+            output_format,
+            input_format,
+            json_style: JsonStyle::Pretty,
+            layout: Layout::Wide,
+            filter: FilterOpts::default(),
+            inputs,
+            output: None,
+        }
+    }
+
+    fn convert(opts: &ConvertOpts) -> Vec<u8> {
+        let mut out = vec![];
+        parse(opts, &Filter::default(), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn radkfilex_matches_consolidation() {
+        let written = convert(&opts(inputs(), InputFormat::Edrdg, OutputFormat::Edrdg));
+        assert!(
+            radk::parse_bytes_with_comments(&written, &ParseOptions::default())
+                .unwrap()
+                .is_radkfilex()
+        );
+        let merged = radk::parse_bytes(&written).unwrap();
+        let parsed: Result<Vec<_>, _> = inputs().iter().map(radk::parse_file).collect();
+        let parsed = parsed.unwrap().into_iter().flatten().collect();
+        assert_eq!(consolidate(merged), consolidate(parsed));
+    }
+
+    #[test]
+    fn writes_csv() {
+        let mut opts = opts(inputs(), InputFormat::Edrdg, OutputFormat::Csv);
+        let wide = String::from_utf8(convert(&opts)).unwrap();
+        let mut rows = wide.split("\r\n");
+        assert_eq!(
+            rows.next(),
+            Some("radical,strokes,alternate,kanji_count,kanji")
+        );
+        assert!(rows.any(|row| row.starts_with("⺅,2,js01,")));
+
+        opts.layout = Layout::Long;
+        let long = String::from_utf8(convert(&opts)).unwrap();
+        let mut rows = long.split("\r\n");
+        assert_eq!(rows.next(), Some("radical,strokes,alternate,kanji"));
+        assert_eq!(rows.next(), Some("一,1,,一"));
+    }
+
+    #[test]
+    fn writes_queryable_database() {
+        let path = std::env::temp_dir().join(format!("radk_{}.db", std::process::id()));
+        let mut opts = opts(inputs(), InputFormat::Edrdg, OutputFormat::Sqlite);
+        opts.output = Some(path.to_string_lossy().into_owned());
+        convert(&opts);
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        // The kanji that contain both radicals
+        let kanji: Vec<String> = connection
+            .prepare(
+                "SELECT kanji.glyph FROM kanji_radical
+                JOIN radical ON radical.jis = kanji_radical.radical_jis
+                JOIN kanji ON kanji.id = kanji_radical.kanji_id
+                WHERE radical.glyph IN ('口', '木')
+                GROUP BY kanji.id HAVING count(*) = 2
+                ORDER BY kanji.id",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(kanji.contains(&"呆".to_string()));
+        assert!(!kanji.contains(&"口".to_string()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reads_unicode_output() {
+        let inputs = vec!["../assets/outputs/radk_utf8.txt".to_string()];
+        let unicode = convert(&opts(inputs, InputFormat::Utf8, OutputFormat::Unicode));
+        let expected = std::fs::read("../assets/outputs/radk_utf8.txt").unwrap();
+        assert!(unicode == expected);
+    }
+
+    #[test]
+    fn filters_radkfilex() {
+        let opts = opts(inputs(), InputFormat::Edrdg, OutputFormat::Edrdg);
+        let filter = Filter {
+            strokes: Some(1..=1),
+            exclude_jis212: true,
+            ..Filter::default()
+        };
+        let mut out = vec![];
+        parse(&opts, &filter, &mut out).unwrap();
+        let filtered = radk::parse_bytes(&out).unwrap();
+        assert_eq!(filtered.len(), 6);
+        assert!(filtered
+            .iter()
+            .all(|membership| membership.radical.strokes == 1));
+    }
+}
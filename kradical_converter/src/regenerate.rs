@@ -0,0 +1,178 @@
+// Rebuilds the generated files of the repository from a manifest
+// listing the converter arguments for each of them
+
+use std::{
+    fs,
+    io::{ErrorKind as IoErrorKind, Write},
+    iter,
+    process::{Command as Process, Stdio},
+};
+
+use clap::{ErrorKind, Parser};
+use serde::Deserialize;
+
+use crate::{
+    convert,
+    error::ConvertError,
+    opts::{CombinedFormat, Command, Opts, OutputFormat, RegenerateOpts},
+    writes_database,
+};
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    outputs: Vec<Output>,
+}
+
+// A generated file and the subcommand that produces it, without the output.
+// Paths are relative to the working directory.
+#[derive(Debug, Deserialize)]
+struct Output {
+    path: String,
+    arguments: Vec<String>,
+}
+
+pub fn regenerate(opts: &RegenerateOpts) -> Result<(), ConvertError> {
+    let manifest: Manifest = toml::from_str(&fs::read_to_string(&opts.manifest)?)?;
+    let mut stale = vec![];
+    for output in &manifest.outputs {
+        let bytes = generate(output)?;
+        if opts.check {
+            if fs::read(&output.path).ok().as_deref() != Some(bytes.as_slice()) {
+                stale.push(output.path.clone());
+            }
+        } else {
+            fs::write(&output.path, bytes)?;
+        }
+    }
+    if stale.is_empty() {
+        Ok(())
+    } else {
+        Err(ConvertError::Stale(stale))
+    }
+}
+
+fn generate(output: &Output) -> Result<Vec<u8>, ConvertError> {
+    let command = command(output)?;
+    let mut bytes = vec![];
+    convert(&command, &mut bytes)?;
+    if is_rust(&command) {
+        rustfmt(&bytes)
+    } else {
+        Ok(bytes)
+    }
+}
+
+// Parses the arguments as if they were given on the command line
+fn command(output: &Output) -> Result<Command, ConvertError> {
+    let arguments = iter::once("kradical_converter")
+        .chain(output.arguments.iter().map(String::as_str))
+        .chain(["--output", &output.path]);
+    let command = Opts::try_parse_from(arguments)
+        .map_err(|error| ConvertError::Manifest(output.path.clone(), error))?
+        .command;
+    if let Command::Regenerate(_) = command {
+        let error = clap::Error::raw(
+            ErrorKind::InvalidSubcommand,
+            "regenerate cannot be listed in a manifest",
+        );
+        return Err(ConvertError::Manifest(output.path.clone(), error));
+    }
+    // Databases are written in place, so they could not be checked
+    if writes_database(&command) {
+        let error = clap::Error::raw(
+            ErrorKind::InvalidValue,
+            "SQLite databases cannot be listed in a manifest",
+        );
+        return Err(ConvertError::Manifest(output.path.clone(), error));
+    }
+    Ok(command)
+}
+
+fn is_rust(command: &Command) -> bool {
+    match command {
+        Command::Radk(opts) | Command::Krad(opts) => opts.output_format == OutputFormat::Rust,
+        Command::Combined(opts) => opts.output_format == CombinedFormat::Rust,
+        _ => false,
+    }
+}
+
+// The generated Rust is committed after formatting, so rustfmt must be installed.
+// Rustfmt reads all of its input before writing any output.
+fn rustfmt(bytes: &[u8]) -> Result<Vec<u8>, ConvertError> {
+    let mut child = Process::new("rustfmt")
+        .args(["--edition", "2018"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|error| match error.kind() {
+            IoErrorKind::NotFound => ConvertError::RustfmtMissing,
+            _ => error.into(),
+        })?;
+    child
+        .stdin
+        .take()
+        .expect("Rustfmt input is piped")
+        .write_all(bytes)?;
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(ConvertError::Rustfmt(output.status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(arguments: &[&str]) -> Output {
+        Output {
+            path: "radk_utf8.txt".to_string(),
+            arguments: arguments
+                .iter()
+                .map(|argument| argument.to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn generates_listed_output() {
+        let output = output(&[
+            "radk",
+            "unicode",
+            "--inputs",
+            "../assets/edrdg_files/radkfile",
+            "../assets/edrdg_files/radkfile2",
+        ]);
+        let expected = fs::read("../assets/outputs/radk_utf8.txt").unwrap();
+        assert!(generate(&output).unwrap() == expected);
+    }
+
+    #[test]
+    fn rejects_nested_regenerate() {
+        assert!(matches!(
+            generate(&output(&["regenerate"])),
+            Err(ConvertError::Manifest(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_database() {
+        let output = output(&[
+            "radk",
+            "sqlite",
+            "--inputs",
+            "../assets/edrdg_files/radkfile",
+        ]);
+        assert!(matches!(generate(&output), Err(ConvertError::Manifest(..))));
+    }
+
+    #[test]
+    fn lists_repository_outputs() {
+        let text = fs::read_to_string("../outputs.toml").unwrap();
+        let manifest: Manifest = toml::from_str(&text).unwrap();
+        for output in &manifest.outputs {
+            assert!(command(output).is_ok());
+        }
+    }
+}
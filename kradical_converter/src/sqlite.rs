@@ -0,0 +1,260 @@
+// A normalized SQLite database of kanji and radicals
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process,
+};
+
+use rusqlite::{params, Connection};
+
+use crate::error::ConvertError;
+
+// The version of the database schema, recorded in the metadata table
+const SCHEMA_VERSION: u32 = 1;
+
+// Radicals are keyed by JIS code because several of them can share a display glyph
+// depending on the radical mapping
+const SCHEMA: &str = "\
+CREATE TABLE metadata (
+    key TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE TABLE radical (
+    jis INTEGER PRIMARY KEY,
+    glyph TEXT NOT NULL,
+    strokes INTEGER,
+    alternate TEXT
+);
+CREATE TABLE kanji (
+    id INTEGER PRIMARY KEY,
+    glyph TEXT NOT NULL UNIQUE
+);
+CREATE TABLE kanji_radical (
+    kanji_id INTEGER NOT NULL REFERENCES kanji (id),
+    radical_jis INTEGER NOT NULL REFERENCES radical (jis),
+    PRIMARY KEY (kanji_id, radical_jis)
+) WITHOUT ROWID;
+";
+
+// Finding the kanji that contain every one of several radicals
+// scans the pairs by radical
+const INDEXES: &str = "\
+CREATE INDEX kanji_radical_by_radical ON kanji_radical (radical_jis, kanji_id);
+";
+
+pub struct Database {
+    sources: Vec<String>,
+    radicals: Vec<(u16, String, Option<u8>, Option<String>)>,
+    radical_indices: HashMap<u16, usize>,
+    kanji: Vec<String>,
+    kanji_ids: HashMap<String, i64>,
+    pairs: Vec<(i64, u16)>,
+}
+
+impl Database {
+    pub fn new(sources: &[String]) -> Self {
+        Self {
+            sources: sources.to_vec(),
+            radicals: Vec::new(),
+            radical_indices: HashMap::new(),
+            kanji: Vec::new(),
+            kanji_ids: HashMap::new(),
+            pairs: Vec::new(),
+        }
+    }
+
+    // Adds a radical unless one with the same JIS code was already added.
+    // Stroke counts and alternates fill in those missing from the earlier radical.
+    pub fn radical(
+        &mut self,
+        jis: u16,
+        glyph: &str,
+        strokes: Option<u8>,
+        alternate: Option<&str>,
+    ) -> u16 {
+        match self.radical_indices.get(&jis) {
+            Some(&i) => {
+                let radical = &mut self.radicals[i];
+                radical.2 = radical.2.or(strokes);
+                if radical.3.is_none() {
+                    radical.3 = alternate.map(str::to_string);
+                }
+            }
+            None => {
+                self.radical_indices.insert(jis, self.radicals.len());
+                let alternate = alternate.map(str::to_string);
+                self.radicals
+                    .push((jis, glyph.to_string(), strokes, alternate));
+            }
+        }
+        jis
+    }
+
+    // Adds a kanji unless it was already added, returning its ID either way
+    pub fn kanji(&mut self, glyph: &str) -> i64 {
+        let kanji = &mut self.kanji;
+        *self.kanji_ids.entry(glyph.to_string()).or_insert_with(|| {
+            kanji.push(glyph.to_string());
+            kanji.len() as i64
+        })
+    }
+
+    // Records that a kanji contains a radical
+    pub fn pair(&mut self, kanji_id: i64, radical_jis: u16) {
+        self.pairs.push((kanji_id, radical_jis));
+    }
+
+    // Replaces any file at the path with the database.
+    // The database is built beside the path first
+    // so that a failure leaves any existing file untouched.
+    // A database cannot be streamed, so there is no standard output.
+    pub fn write(mut self, path: Option<&str>) -> Result<(), ConvertError> {
+        let path = match path {
+            Some(path) if path != crate::stdio::STDIO => Path::new(path),
+            _ => return Err(ConvertError::SqliteOutput),
+        };
+        let temporary = temporary_path(path);
+        match fs::remove_file(&temporary) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+        match self.build(&temporary) {
+            Ok(()) => Ok(fs::rename(&temporary, path)?),
+            Err(error) => {
+                let _ = fs::remove_file(&temporary);
+                Err(error)
+            }
+        }
+    }
+
+    fn build(&mut self, path: &Path) -> Result<(), ConvertError> {
+        self.pairs.sort_unstable();
+        self.pairs.dedup();
+
+        let mut connection = Connection::open(path)?;
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(SCHEMA)?;
+        {
+            let mut metadata = transaction.prepare("INSERT INTO metadata VALUES (?1, ?2)")?;
+            metadata.execute(params!["schema_version", SCHEMA_VERSION])?;
+            for source in &self.sources {
+                metadata.execute(params!["source", source])?;
+            }
+            let mut radical = transaction.prepare("INSERT INTO radical VALUES (?1, ?2, ?3, ?4)")?;
+            for (jis, glyph, strokes, alternate) in &self.radicals {
+                radical.execute(params![jis, glyph, strokes, alternate])?;
+            }
+            let mut kanji = transaction.prepare("INSERT INTO kanji VALUES (?1, ?2)")?;
+            for (glyph, id) in self.kanji.iter().zip(1..) {
+                kanji.execute(params![id, glyph])?;
+            }
+            let mut pair = transaction.prepare("INSERT INTO kanji_radical VALUES (?1, ?2)")?;
+            for (kanji, radical) in &self.pairs {
+                pair.execute(params![kanji, radical])?;
+            }
+        }
+        transaction.execute_batch(INDEXES)?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+// A hidden file in the same directory, so that renaming it stays on one file system
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}.tmp", name, process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn path(name: &str) -> String {
+        let name = format!("kradical_converter_{}_{}.db", std::process::id(), name);
+        env::temp_dir().join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn writes_normalized_rows() {
+        let path = path("normalized");
+        let mut database = Database::new(&["radkfile".to_string()]);
+        let one = database.radical(0x306C, "一", None, None);
+        let person = database.radical(0x2F5D, "⺅", Some(2), Some("js01"));
+        assert_eq!(database.radical(0x306C, "一", Some(1), None), one);
+        let kanji = database.kanji("亻");
+        database.pair(kanji, person);
+        database.pair(kanji, person);
+        database.write(Some(&path)).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let radicals: Vec<(u16, String, Option<u8>, Option<String>)> = connection
+            .prepare("SELECT * FROM radical ORDER BY jis")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            radicals,
+            vec![
+                (0x2F5D, "⺅".to_string(), Some(2), Some("js01".to_string())),
+                (0x306C, "一".to_string(), Some(1), None),
+            ]
+        );
+        let pairs: u32 = connection
+            .query_row("SELECT count(*) FROM kanji_radical", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pairs, 1);
+        let source: String = connection
+            .query_row(
+                "SELECT value FROM metadata WHERE key = 'source'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(source, "radkfile");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replaces_existing_file() {
+        let path = path("replaced");
+        fs::write(&path, "not a database").unwrap();
+        Database::new(&[]).write(Some(&path)).unwrap();
+        let connection = Connection::open(&path).unwrap();
+        let count: u32 = connection
+            .query_row("SELECT count(*) FROM metadata", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(!temporary_path(Path::new(&path)).exists());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_existing_file_on_failure() {
+        let path = path("kept");
+        fs::write(&path, "existing").unwrap();
+        // A directory in the way of the temporary file
+        let temporary = temporary_path(Path::new(&path));
+        fs::create_dir_all(&temporary).unwrap();
+        assert!(Database::new(&[]).write(Some(&path)).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "existing");
+        fs::remove_dir(temporary).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_standard_output() {
+        let database = Database::new(&[]);
+        assert!(matches!(
+            database.write(Some("-")),
+            Err(ConvertError::SqliteOutput)
+        ));
+    }
+}
@@ -0,0 +1,37 @@
+// Standard input and output stand in for files named `-`,
+// so that the converter can be used in pipelines
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufWriter, Read, Write},
+};
+
+use kradical_parsing::decompress;
+
+pub const STDIO: &str = "-";
+
+pub fn read_stdin() -> io::Result<Vec<u8>> {
+    let mut b = vec![];
+    io::stdin().lock().read_to_end(&mut b)?;
+    Ok(b)
+}
+
+// Reads standard input as it is consumed, decompressing it if it is gzipped
+pub fn stdin_reader() -> io::Result<Box<dyn BufRead>> {
+    decompress(io::stdin().lock())
+}
+
+// Decompresses before decoding, since gzipped text is not UTF-8
+pub fn read_stdin_text() -> io::Result<String> {
+    let mut text = String::new();
+    stdin_reader()?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+// Standard output when no path is given
+pub fn open_output(path: Option<&str>) -> io::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) if path != STDIO => Box::new(BufWriter::new(File::create(path)?)),
+        _ => Box::new(BufWriter::new(io::stdout().lock())),
+    })
+}
@@ -0,0 +1,60 @@
+// Delimiter-separated values with RFC 4180 quoting,
+// which also applies to tab-separated values
+
+use std::io::{self, Write};
+
+pub struct Table<W: Write> {
+    writer: W,
+    delimiter: char,
+}
+
+impl<W: Write> Table<W> {
+    pub fn new(writer: W, delimiter: char) -> Self {
+        Self { writer, delimiter }
+    }
+
+    pub fn row<S: AsRef<str>>(&mut self, fields: &[S]) -> io::Result<()> {
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                write!(self.writer, "{}", self.delimiter)?;
+            }
+            self.field(field.as_ref())?;
+        }
+        self.writer.write_all(b"\r\n")
+    }
+
+    // Fields containing the delimiter, quotes, or line breaks are quoted,
+    // with quotes inside them doubled
+    fn field(&mut self, field: &str) -> io::Result<()> {
+        if field.contains([self.delimiter, '"', '\r', '\n']) {
+            write!(self.writer, "\"{}\"", field.replace('"', "\"\""))
+        } else {
+            self.writer.write_all(field.as_bytes())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_special_fields() {
+        let mut out = vec![];
+        let mut table = Table::new(&mut out, ',');
+        table
+            .row(&["plain", "a,b", "say \"hi\"", "two\nlines"])
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\r\n"
+        );
+    }
+
+    #[test]
+    fn quotes_tabs_in_tsv() {
+        let mut out = vec![];
+        Table::new(&mut out, '\t').row(&["a,b", "c\td"]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a,b\t\"c\td\"\r\n");
+    }
+}
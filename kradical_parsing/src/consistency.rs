@@ -0,0 +1,260 @@
+//! Cross-checks between kradfile decompositions and radkfile memberships
+
+use crate::{krad::Decomposition, mapping::RadicalGlyph, radk::Membership};
+use std::collections::{HashMap, HashSet};
+
+/// Which kind of file a record was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Source {
+    /// The kradfile decompositions
+    Krad,
+
+    /// The radkfile memberships
+    Radk,
+}
+
+/// A kanji and radical that are paired in one kind of file but not the other
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Asymmetry {
+    /// The kanji
+    pub kanji: String,
+
+    /// The radical
+    pub radical: RadicalGlyph,
+
+    /// The kind of file that pairs the kanji with the radical
+    pub listed_in: Source,
+}
+
+/// A radical used in kradfile decompositions
+/// that has no ident line in the radkfile
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UndeclaredRadical {
+    /// The radical
+    pub radical: RadicalGlyph,
+
+    /// The kanji whose decompositions include the radical
+    pub kanji: Vec<String>,
+}
+
+/// The inconsistencies found between kradfile and radkfile data
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Report {
+    /// Kanji and radicals paired in only one kind of file.
+    /// Radicals without an ident line and kanji missing from
+    /// the other kind of file are reported separately.
+    pub asymmetries: Vec<Asymmetry>,
+
+    /// Radicals used by the kradfile but never declared by the radkfile
+    pub undeclared_radicals: Vec<UndeclaredRadical>,
+
+    /// Kanji with a decomposition that belong to no radical
+    pub krad_only_kanji: Vec<String>,
+
+    /// Kanji that belong to a radical but have no decomposition
+    pub radk_only_kanji: Vec<String>,
+}
+
+impl Report {
+    /// Whether no inconsistencies were found
+    pub fn is_consistent(&self) -> bool {
+        self.asymmetries.is_empty()
+            && self.undeclared_radicals.is_empty()
+            && self.krad_only_kanji.is_empty()
+            && self.radk_only_kanji.is_empty()
+    }
+}
+
+/// Compares decompositions with memberships, which should describe
+/// the same kanji and radicals. Radicals are matched by JIS code
+/// so that the radical mapping makes no difference.
+///
+/// # Arguments
+///
+/// * `decompositions` - The decompositions from kradfile and kradfile2
+/// * `memberships` - The memberships from radkfile and radkfile2
+pub fn check(decompositions: &[Decomposition], memberships: &[Membership]) -> Report {
+    let mut radk_pairs = HashSet::new();
+    let mut radk_kanji = HashSet::new();
+    for membership in memberships {
+        for kanji in &membership.kanji {
+            radk_pairs.insert((kanji.as_str(), membership.radical.glyph.jis));
+            radk_kanji.insert(kanji.as_str());
+        }
+    }
+
+    let declared: HashSet<_> = memberships
+        .iter()
+        .map(|membership| membership.radical.glyph.jis)
+        .collect();
+    let mut krad_pairs = HashSet::new();
+    let mut report = Report::default();
+    let mut undeclared: HashMap<u16, usize> = HashMap::new();
+    for decomposition in decompositions {
+        let kanji = decomposition.kanji.as_str();
+        let in_radk = radk_kanji.contains(kanji);
+        if !in_radk {
+            report.krad_only_kanji.push(kanji.to_string());
+        }
+        for radical in &decomposition.radicals {
+            if !krad_pairs.insert((kanji, radical.jis)) {
+                continue;
+            }
+            if !declared.contains(&radical.jis) {
+                let i = *undeclared.entry(radical.jis).or_insert_with(|| {
+                    report.undeclared_radicals.push(UndeclaredRadical {
+                        radical: radical.clone(),
+                        kanji: Vec::new(),
+                    });
+                    report.undeclared_radicals.len() - 1
+                });
+                report.undeclared_radicals[i].kanji.push(kanji.to_string());
+            } else if in_radk && !radk_pairs.contains(&(kanji, radical.jis)) {
+                report.asymmetries.push(Asymmetry {
+                    kanji: kanji.to_string(),
+                    radical: radical.clone(),
+                    listed_in: Source::Krad,
+                });
+            }
+        }
+    }
+
+    let krad_kanji: HashSet<_> = decompositions
+        .iter()
+        .map(|decomposition| decomposition.kanji.as_str())
+        .collect();
+    let mut seen = HashSet::new();
+    let mut radk_only = HashSet::new();
+    for membership in memberships {
+        for kanji in &membership.kanji {
+            let pair = (kanji.as_str(), membership.radical.glyph.jis);
+            if !seen.insert(pair) {
+                continue;
+            }
+            if !krad_kanji.contains(kanji.as_str()) {
+                if radk_only.insert(kanji.as_str()) {
+                    report.radk_only_kanji.push(kanji.clone());
+                }
+            } else if !krad_pairs.contains(&pair) {
+                report.asymmetries.push(Asymmetry {
+                    kanji: kanji.clone(),
+                    radical: membership.radical.glyph.clone(),
+                    listed_in: Source::Radk,
+                });
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        krad,
+        mapping::RadicalMapping,
+        radk::{self, Alternate, Radical},
+    };
+
+    fn glyph(radical: char) -> RadicalGlyph {
+        RadicalMapping::default().radical(radical).unwrap()
+    }
+
+    fn decomposition(kanji: &str, radicals: &[char]) -> Decomposition {
+        Decomposition {
+            kanji: kanji.to_string(),
+            radicals: radicals.iter().copied().map(glyph).collect(),
+        }
+    }
+
+    fn membership(radical: char, kanji: &[&str]) -> Membership {
+        Membership {
+            radical: Radical {
+                glyph: glyph(radical),
+                strokes: 1,
+                alternate: Alternate::None,
+            },
+            kanji: kanji.iter().map(|kanji| kanji.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn finds_inconsistencies() {
+        let decompositions = [
+            decomposition("亜", &['一', '口']),
+            decomposition("唖", &['口', '邑']),
+            decomposition("娃", &['女']),
+        ];
+        let memberships = [
+            membership('一', &["亜"]),
+            membership('口', &["唖", "哀"]),
+            membership('女', &["娃"]),
+            membership('土', &["娃"]),
+        ];
+        let report = check(&decompositions, &memberships);
+        assert!(!report.is_consistent());
+        assert_eq!(
+            report.asymmetries,
+            vec![
+                Asymmetry {
+                    kanji: "亜".to_string(),
+                    radical: glyph('口'),
+                    listed_in: Source::Krad,
+                },
+                Asymmetry {
+                    kanji: "娃".to_string(),
+                    radical: glyph('土'),
+                    listed_in: Source::Radk,
+                },
+            ]
+        );
+        assert_eq!(
+            report.undeclared_radicals,
+            vec![UndeclaredRadical {
+                radical: glyph('邑'),
+                kanji: vec!["唖".to_string()],
+            }]
+        );
+        assert!(report.krad_only_kanji.is_empty());
+        assert_eq!(report.radk_only_kanji, vec!["哀".to_string()]);
+    }
+
+    #[test]
+    fn reports_missing_kanji_once() {
+        let decompositions = [decomposition("亜", &['一', '口'])];
+        let memberships = [membership('一', &["哀"]), membership('口', &["哀"])];
+        let report = check(&decompositions, &memberships);
+        assert!(report.asymmetries.is_empty());
+        assert!(report.undeclared_radicals.is_empty());
+        assert_eq!(report.krad_only_kanji, vec!["亜".to_string()]);
+        assert_eq!(report.radk_only_kanji, vec!["哀".to_string()]);
+    }
+
+    #[test]
+    fn checks_edrdg_files() {
+        let decompositions: Vec<_> = ["kradfile", "kradfile2"]
+            .iter()
+            .flat_map(|name| krad::parse_file(format!("../assets/edrdg_files/{}", name)).unwrap())
+            .collect();
+        let memberships =
+            radk::merge(["radkfile", "radkfile2"].iter().flat_map(|name| {
+                radk::parse_file(format!("../assets/edrdg_files/{}", name)).unwrap()
+            }));
+        let report = check(&decompositions, &memberships);
+        assert!(report.asymmetries.is_empty());
+        assert!(report.krad_only_kanji.is_empty());
+        assert!(report.radk_only_kanji.is_empty());
+        assert_eq!(
+            report.undeclared_radicals,
+            vec![UndeclaredRadical {
+                radical: glyph('邑'),
+                kanji: vec!["悒".to_string()],
+            }]
+        );
+    }
+}
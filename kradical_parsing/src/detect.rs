@@ -0,0 +1,467 @@
+//! Identifies which kind of radical file some bytes contain
+
+use crate::{
+    krad::{self, Decomposition, KradError},
+    radk::{self, Membership, RadkError},
+    shared::{comment_text, decompress_bytes, is_comment_or_blank},
+};
+#[cfg(feature = "json")]
+use serde_json::{Map, Value};
+use std::{
+    fmt::{self, Display, Formatter},
+    path::Path,
+};
+use thiserror::Error;
+
+// The lead byte of three-byte JIS X 0212 characters in EUC-JP
+const JIS_X_0212_LEAD: u8 = 0x8F;
+
+/// Enumerates the possible errors while parsing a file of unknown kind
+#[derive(Debug, Error)]
+pub enum ParseAnyError {
+    /// The contents did not match any known kind of file
+    #[error("Unrecognized radical file format")]
+    Unknown,
+
+    /// The kind of file was recognized but cannot be parsed,
+    /// such as combined JSON
+    #[error("Parsing {0} files is not supported")]
+    Unsupported(FileKind),
+
+    /// Error while parsing kradfile
+    #[error(transparent)]
+    Krad(#[from] KradError),
+
+    /// Error while parsing radkfile
+    #[error(transparent)]
+    Radk(#[from] RadkError),
+
+    /// Error while reading the file
+    #[error("Error while reading radical file")]
+    Io(#[from] std::io::Error),
+}
+
+/// The kinds of radical file that can be recognized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FileKind {
+    /// An EUC-JP kradfile whose kanji are all in JIS X 0208
+    Krad0208,
+
+    /// An EUC-JP kradfile with JIS X 0212 kanji, such as kradfile2
+    Krad0212,
+
+    /// An EUC-JP radkfile whose kanji are all in JIS X 0208
+    Radk,
+
+    /// An EUC-JP radkfile with JIS X 0212 kanji, such as radkfile2
+    Radk2,
+
+    /// An EUC-JP radkfilex, recognized by its title
+    /// or by having both JIS X 0208 and JIS X 0212 kanji
+    RadkX,
+
+    /// The converter's UTF-8 kradfile output
+    Utf8Krad,
+
+    /// The converter's UTF-8 radkfile output
+    Utf8Radk,
+
+    /// The converter's JSON kradfile output
+    JsonKrad,
+
+    /// The converter's JSON radkfile output
+    JsonRadk,
+
+    /// The converter's combined JSON output,
+    /// which is written but not read by this crate
+    JsonCombined,
+
+    /// None of the above
+    Unknown,
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileKind::Krad0208 => "kradfile",
+            FileKind::Krad0212 => "kradfile2",
+            FileKind::Radk => "radkfile",
+            FileKind::Radk2 => "radkfile2",
+            FileKind::RadkX => "radkfilex",
+            FileKind::Utf8Krad => "UTF-8 kradfile",
+            FileKind::Utf8Radk => "UTF-8 radkfile",
+            FileKind::JsonKrad => "JSON kradfile",
+            FileKind::JsonRadk => "JSON radkfile",
+            FileKind::JsonCombined => "combined JSON",
+            FileKind::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The records parsed from a file of any supported kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "kind", content = "records", rename_all = "snake_case")
+)]
+pub enum Contents {
+    /// The decompositions from a kradfile
+    Krad(Vec<Decomposition>),
+
+    /// The memberships from a radkfile
+    Radk(Vec<Membership>),
+}
+
+/// Guesses the kind of radical file from its contents,
+/// looking inside gzipped contents when the `gzip` feature is enabled.
+/// JSON is only recognized when the `json` feature is enabled.
+///
+/// # Arguments
+///
+/// * `b` - The file contents
+pub fn detect(b: &[u8]) -> FileKind {
+    match decompress_bytes(b) {
+        Ok(b) => detect_decompressed(&b),
+        Err(_) => FileKind::Unknown,
+    }
+}
+
+fn detect_decompressed(b: &[u8]) -> FileKind {
+    let start = b.iter().position(|b| !b.is_ascii_whitespace());
+    if let Some(b'[') | Some(b'{') = start.map(|i| b[i]) {
+        return detect_json(b);
+    }
+
+    let mut lines = b
+        .split(|&b| b == b'\n')
+        .filter(|line| !is_comment_or_blank(line));
+    let first = match lines.next() {
+        Some(first) => first,
+        None => return FileKind::Unknown,
+    };
+
+    if std::str::from_utf8(b).is_ok() && !b.is_ascii() {
+        detect_utf8(first)
+    } else if first.starts_with(b"$") {
+        if has_radkfilex_title(b) {
+            return FileKind::RadkX;
+        }
+        let (has_0208, has_0212) = lines
+            .filter(|line| !line.starts_with(b"$"))
+            .map(kanji_sets)
+            .fold((false, false), |(a, b), (c, d)| (a || c, b || d));
+        match (has_0208, has_0212) {
+            (true, true) => FileKind::RadkX,
+            (false, true) => FileKind::Radk2,
+            _ => FileKind::Radk,
+        }
+    } else if contains(first, b" : ") {
+        // Only the kanji at the start of each line can be JIS X 0212
+        let has_0212 = std::iter::once(first)
+            .chain(lines)
+            .any(|line| line.starts_with(&[JIS_X_0212_LEAD]));
+        if has_0212 {
+            FileKind::Krad0212
+        } else {
+            FileKind::Krad0208
+        }
+    } else {
+        FileKind::Unknown
+    }
+}
+
+// The title is among the comments before the first ident line
+fn has_radkfilex_title(b: &[u8]) -> bool {
+    b.split(|&b| b == b'\n')
+        .take_while(|line| is_comment_or_blank(line))
+        .filter_map(comment_text)
+        .any(|comment| radk::is_radkfilex_title(&comment))
+}
+
+// Whether a line of EUC-JP kanji includes JIS X 0208 and JIS X 0212 characters
+fn kanji_sets(line: &[u8]) -> (bool, bool) {
+    let (mut has_0208, mut has_0212) = (false, false);
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            JIS_X_0212_LEAD => {
+                has_0212 = true;
+                i += 3;
+            }
+            b if b.is_ascii() => i += 1,
+            _ => {
+                has_0208 = true;
+                i += 2;
+            }
+        }
+    }
+    (has_0208, has_0212)
+}
+
+// Krad lines begin with a single kanji before the separator,
+// while radk lines begin with a radical and its stroke count
+fn detect_utf8(first: &[u8]) -> FileKind {
+    let separator = first.windows(3).position(|w| w == b" : ");
+    match separator {
+        Some(i) if first[..i].contains(&b' ') => FileKind::Utf8Radk,
+        Some(_) => FileKind::Utf8Krad,
+        None => FileKind::Unknown,
+    }
+}
+
+// JSON Lines hold one record per line, so the first value is enough
+#[cfg(feature = "json")]
+fn detect_json(b: &[u8]) -> FileKind {
+    let first = serde_json::Deserializer::from_slice(b)
+        .into_iter::<Value>()
+        .next();
+    match first {
+        Some(Ok(Value::Object(object))) if object.contains_key("version") => document_kind(&object),
+        Some(Ok(Value::Object(object))) => record_kind(&object),
+        // Outputs before the first version were bare arrays of records
+        Some(Ok(Value::Array(records))) => match records.first() {
+            Some(Value::Object(object)) => record_kind(object),
+            _ => FileKind::Unknown,
+        },
+        _ => FileKind::Unknown,
+    }
+}
+
+// Telling the kinds of JSON apart requires parsing it
+#[cfg(not(feature = "json"))]
+fn detect_json(_b: &[u8]) -> FileKind {
+    FileKind::Unknown
+}
+
+#[cfg(feature = "json")]
+fn document_kind(document: &Map<String, Value>) -> FileKind {
+    let has = |key| document.contains_key(key);
+    if has("decompositions") {
+        FileKind::JsonKrad
+    } else if has("memberships") {
+        FileKind::JsonRadk
+    } else if has("radicals") && has("kanji") {
+        FileKind::JsonCombined
+    } else {
+        FileKind::Unknown
+    }
+}
+
+// Decompositions have a list of radicals and memberships a list of kanji.
+// Combined JSON Lines begin with radicals, which have neither.
+#[cfg(feature = "json")]
+fn record_kind(record: &Map<String, Value>) -> FileKind {
+    let has = |key| record.contains_key(key);
+    if has("kanji") && has("radicals") {
+        FileKind::JsonKrad
+    } else if has("radical") && has("kanji") {
+        FileKind::JsonRadk
+    } else if has("radical") && has("strokes") {
+        FileKind::JsonCombined
+    } else {
+        FileKind::Unknown
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Parses a radical file of whichever kind it is detected to be
+///
+/// # Arguments
+///
+/// * `b` - The bytes to parse
+pub fn parse_any(b: &[u8]) -> Result<Contents, ParseAnyError> {
+    let b = &decompress_bytes(b)?;
+    match detect_decompressed(b) {
+        FileKind::Krad0208 | FileKind::Krad0212 => Ok(Contents::Krad(krad::parse_bytes(b)?)),
+        FileKind::Radk | FileKind::Radk2 | FileKind::RadkX => {
+            Ok(Contents::Radk(radk::parse_bytes(b)?))
+        }
+        FileKind::Utf8Krad => Ok(Contents::Krad(krad::parse_utf8(utf8(b)?)?)),
+        FileKind::Utf8Radk => Ok(Contents::Radk(radk::parse_utf8(utf8(b)?)?)),
+        #[cfg(feature = "json")]
+        FileKind::JsonKrad => Ok(Contents::Krad(krad::parse_json(utf8(b)?)?)),
+        #[cfg(feature = "json")]
+        FileKind::JsonRadk => Ok(Contents::Radk(radk::parse_json(utf8(b)?)?)),
+        FileKind::Unknown => Err(ParseAnyError::Unknown),
+        #[cfg(not(feature = "json"))]
+        kind @ (FileKind::JsonKrad | FileKind::JsonRadk) => Err(ParseAnyError::Unsupported(kind)),
+        kind @ FileKind::JsonCombined => Err(ParseAnyError::Unsupported(kind)),
+    }
+}
+
+fn utf8(b: &[u8]) -> Result<&str, ParseAnyError> {
+    std::str::from_utf8(b).map_err(|_| ParseAnyError::Unknown)
+}
+
+/// Parses a radical file of whichever kind it is detected to be
+///
+/// # Arguments
+///
+/// * `path` - A path to the file
+pub fn parse_any_file<P: AsRef<Path>>(path: P) -> Result<Contents, ParseAnyError> {
+    parse_any_file_implementation(path.as_ref())
+}
+
+// Monomorphisation bloat avoidal splitting
+fn parse_any_file_implementation(path: &Path) -> Result<Contents, ParseAnyError> {
+    let b = std::fs::read(path)?;
+    parse_any(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_file(path: &str) -> FileKind {
+        detect(&std::fs::read(path).unwrap())
+    }
+
+    #[test]
+    fn detects_edrdg_files() {
+        assert_eq!(
+            detect_file("../assets/edrdg_files/kradfile"),
+            FileKind::Krad0208
+        );
+        assert_eq!(
+            detect_file("../assets/edrdg_files/kradfile2"),
+            FileKind::Krad0212
+        );
+        assert_eq!(
+            detect_file("../assets/edrdg_files/radkfile"),
+            FileKind::Radk
+        );
+        assert_eq!(
+            detect_file("../assets/edrdg_files/radkfile2"),
+            FileKind::Radk2
+        );
+        assert_eq!(detect_file("../assets/fixtures/radkfilex"), FileKind::RadkX);
+    }
+
+    #[test]
+    fn detects_radkfilex_by_title() {
+        let radkfile = std::fs::read("../assets/edrdg_files/radkfile").unwrap();
+        let mut titled = b"#  R A D K F I L E X\n".to_vec();
+        titled.extend(radkfile);
+        assert_eq!(detect(&titled), FileKind::RadkX);
+    }
+
+    #[test]
+    fn detects_converter_outputs() {
+        assert_eq!(
+            detect_file("../assets/outputs/krad_utf8.txt"),
+            FileKind::Utf8Krad
+        );
+        assert_eq!(
+            detect_file("../assets/outputs/radk_utf8.txt"),
+            FileKind::Utf8Radk
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(
+            detect_file("../assets/outputs/krad.json"),
+            FileKind::JsonKrad
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(
+            detect_file("../assets/outputs/radk.json"),
+            FileKind::JsonRadk
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn detects_gzipped_file() {
+        let b = crate::test_constants::gzipped("../assets/edrdg_files/radkfile2");
+        assert_eq!(detect(&b), FileKind::Radk2);
+        assert!(matches!(parse_any(&b), Ok(Contents::Radk(_))));
+    }
+
+    #[test]
+    fn detects_unknown() {
+        assert_eq!(detect(b""), FileKind::Unknown);
+        assert_eq!(detect(b"# Only a comment\n"), FileKind::Unknown);
+        assert_eq!(detect(b"Hello, world!\n"), FileKind::Unknown);
+    }
+
+    #[test]
+    fn parses_any_file() {
+        let res = parse_any_file("../assets/edrdg_files/kradfile2").unwrap();
+        assert!(matches!(res, Contents::Krad(decompositions) if decompositions.len() == 5_801));
+        let res = parse_any_file("../assets/edrdg_files/radkfile").unwrap();
+        assert!(matches!(res, Contents::Radk(memberships) if memberships.len() == 253));
+    }
+
+    #[test]
+    fn parses_converter_output() {
+        let res = parse_any_file("../assets/outputs/krad_utf8.txt").unwrap();
+        assert!(matches!(res, Contents::Krad(decompositions) if decompositions.len() == 12_156));
+        let res = parse_any_file("../assets/outputs/radk_utf8.txt").unwrap();
+        assert!(matches!(res, Contents::Radk(memberships) if memberships.len() == 253));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn parses_json_output() {
+        let res = parse_any_file("../assets/outputs/krad.json").unwrap();
+        assert!(matches!(res, Contents::Krad(decompositions) if decompositions.len() == 12_156));
+        let res = parse_any_file("../assets/outputs/radk.json").unwrap();
+        assert!(matches!(res, Contents::Radk(memberships) if memberships.len() == 253));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn detects_json_by_its_keys() {
+        let detect_str = |text: &str| detect(text.as_bytes());
+        assert_eq!(
+            detect_str(r#"{"version": 1, "memberships": []}"#),
+            FileKind::JsonRadk
+        );
+        assert_eq!(
+            detect_str(r#"[{"radical": "一", "stroke": 1, "kanji": ["亜"]}]"#),
+            FileKind::JsonRadk
+        );
+        assert_eq!(
+            detect_str(
+                "{\"kanji\":\"化\",\"radicals\":[\"⺅\"]}\n{\"kanji\":\"亜\",\"radicals\":[]}\n"
+            ),
+            FileKind::JsonKrad
+        );
+        assert_eq!(
+            detect_str(r#"{"version": 1, "sources": [], "radicals": [], "kanji": []}"#),
+            FileKind::JsonCombined
+        );
+        assert_eq!(
+            detect_str(r#"{"radical": "一", "strokes": 1, "alternate": {"type": "none"}}"#),
+            FileKind::JsonCombined
+        );
+        assert_eq!(detect_str(r#"{"note": "radicals"}"#), FileKind::Unknown);
+        assert_eq!(detect_str(r#"["radical"]"#), FileKind::Unknown);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn rejects_combined_json() {
+        let text = r#"{"version": 1, "sources": [], "radicals": [], "kanji": []}"#;
+        assert!(matches!(
+            parse_any(text.as_bytes()),
+            Err(ParseAnyError::Unsupported(FileKind::JsonCombined))
+        ));
+    }
+
+    #[cfg(not(feature = "json"))]
+    #[test]
+    fn rejects_json_without_feature() {
+        assert_eq!(
+            detect(&std::fs::read("../assets/outputs/krad.json").unwrap()),
+            FileKind::Unknown
+        );
+        let res = parse_any_file("../assets/outputs/krad.json");
+        assert!(matches!(res, Err(ParseAnyError::Unknown)));
+    }
+}
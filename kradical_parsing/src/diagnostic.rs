@@ -0,0 +1,282 @@
+//! Location information for parse failures
+
+use encoding::{codec::japanese::EUCJPEncoding, DecoderTrap, Encoding};
+use nom::{
+    error::{ErrorKind, FromExternalError, ParseError},
+    Err, IResult,
+};
+use std::fmt::{self, Display, Formatter};
+
+// The most bytes of an offending token to keep around
+const MAX_TOKEN_LENGTH: usize = 16;
+
+/// The part of a line that was being parsed when a failure occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Context {
+    /// A kanji, either at the start of a kradfile line
+    /// or in the kanji lines of a radkfile
+    Kanji,
+
+    /// The ` : ` between a kanji and its radicals
+    Separator,
+
+    /// A radical, either in a kradfile line or a radkfile ident line
+    Radical,
+
+    /// The `$` that begins a radkfile ident line
+    IdentLine,
+
+    /// The stroke count of a radkfile ident line
+    Strokes,
+
+    /// The alternate representation in a radkfile ident line
+    Alternate,
+
+    /// The failure could not be attributed to a particular parser
+    Unknown,
+}
+
+impl Display for Context {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Context::Kanji => "kanji",
+            Context::Separator => "separator",
+            Context::Radical => "radical",
+            Context::IdentLine => "ident line",
+            Context::Strokes => "stroke count",
+            Context::Alternate => "alternate",
+            Context::Unknown => "line",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Describes where in the input a parse failure occurred
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    /// The number of bytes from the start of the input to the failure
+    pub offset: usize,
+
+    /// The one-based line number of the failure
+    pub line: usize,
+
+    /// The one-based column of the failure, counted in bytes
+    pub column: usize,
+
+    /// The raw bytes of the token that could not be parsed
+    pub bytes: Vec<u8>,
+
+    /// Which parser failed
+    pub context: Context,
+
+    /// The raw bytes of the line containing the failure,
+    /// excluding the line break
+    pub source_line: Vec<u8>,
+}
+
+impl Diagnostic {
+    /// Locates a failure given the input to the parser
+    /// and the remaining input at the point of failure
+    pub(crate) fn new(input: &[u8], remaining: &[u8], context: Context) -> Self {
+        let offset = input.len().saturating_sub(remaining.len());
+        let before = &input[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let line_end = input[offset..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(input.len(), |i| offset + i);
+        // The offending token runs until the next whitespace
+        // unless the whitespace itself is what was unexpected
+        let rest = &input[offset..line_end];
+        let token_length = match rest.first() {
+            Some(b) if b.is_ascii_whitespace() => 1,
+            _ => rest
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len())
+                .min(MAX_TOKEN_LENGTH),
+        };
+        Self {
+            offset,
+            line: before.iter().filter(|&&b| b == b'\n').count() + 1,
+            column: offset - line_start + 1,
+            bytes: rest[..token_length].to_vec(),
+            context,
+            source_line: input[line_start..line_end].to_vec(),
+        }
+    }
+
+    /// Locates the failure from a nom error
+    pub(crate) fn from_err(input: &[u8], err: Err<Failure>) -> Self {
+        match err {
+            Err::Error(failure) | Err::Failure(failure) => Self::new(
+                input,
+                failure.input,
+                failure.context.unwrap_or(Context::Unknown),
+            ),
+            Err::Incomplete(_) => Self::new(input, &[], Context::Unknown),
+        }
+    }
+
+    /// Shifts the location of a failure found in a slice
+    /// that starts partway through the input
+    pub(crate) fn offset_by(mut self, offset: usize, lines: usize) -> Self {
+        self.offset += offset;
+        self.line += lines;
+        self
+    }
+
+    /// The offending bytes formatted as space-separated hexadecimal
+    pub fn hex(&self) -> String {
+        let bytes: Vec<_> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        bytes.join(" ")
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid {} at line {}, column {} (byte {})",
+            self.context, self.line, self.column, self.offset
+        )?;
+        if self.bytes.is_empty() {
+            writeln!(f, ": unexpected end of line")?;
+        } else {
+            writeln!(f, ": {}", self.hex())?;
+        }
+
+        let column = self.column.saturating_sub(1).min(self.source_line.len());
+        let indent = display_width(&self.source_line[..column]);
+        let carets = display_width(&self.bytes).max(1);
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, decode_lossy(&self.source_line))?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(indent),
+            "^".repeat(carets)
+        )
+    }
+}
+
+// Best-effort decoding, since the line may be UTF-8
+// or contain any mixture of JIS X 0208, JIS X 0212, and junk
+fn decode_lossy(b: &[u8]) -> String {
+    EUCJPEncoding
+        .decode(b, DecoderTrap::Strict)
+        .or_else(|_| String::from_utf8(b.to_vec()))
+        .or_else(|_| EUCJPEncoding.decode(b, DecoderTrap::Replace))
+        .unwrap_or_else(|_| String::from_utf8_lossy(b).into_owned())
+}
+
+// Japanese characters are assumed to be full width
+fn display_width(b: &[u8]) -> usize {
+    decode_lossy(b)
+        .chars()
+        .map(|c| if c.is_ascii() { 1 } else { 2 })
+        .sum()
+}
+
+/// The nom error type used by the parsers,
+/// which remembers the innermost parser context
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Failure<'a> {
+    pub input: &'a [u8],
+    pub context: Option<Context>,
+}
+
+impl<'a> ParseError<&'a [u8]> for Failure<'a> {
+    fn from_error_kind(input: &'a [u8], _kind: ErrorKind) -> Self {
+        Self {
+            input,
+            context: None,
+        }
+    }
+
+    fn append(_input: &'a [u8], _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'a, E> FromExternalError<&'a [u8], E> for Failure<'a> {
+    fn from_external_error(input: &'a [u8], kind: ErrorKind, _e: E) -> Self {
+        Self::from_error_kind(input, kind)
+    }
+}
+
+pub(crate) type ParseResult<'a, O> = IResult<&'a [u8], O, Failure<'a>>;
+
+/// Attributes failures of the given parser to a context
+/// unless a more specific context was already provided
+pub(crate) fn context<'a, O, F>(
+    context: Context,
+    mut parser: F,
+) -> impl FnMut(&'a [u8]) -> ParseResult<'a, O>
+where
+    F: FnMut(&'a [u8]) -> ParseResult<'a, O>,
+{
+    move |b| {
+        parser(b).map_err(|err| {
+            err.map(|mut failure| {
+                failure.context.get_or_insert(context);
+                failure
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "亜 : ｜ 一 ~~\n唖 : ｜ 一 口"
+    const TWO_LINES: &[u8] = &[
+        0xB0, 0xA1, 0x20, 0x3A, 0x20, 0xA1, 0xC3, 0x20, 0xB0, 0xEC, 0x20, 0x7E, 0x7E, 0x0A, 0xB0,
+        0xA2, 0x20, 0x3A, 0x20, 0xA1, 0xC3, 0x20, 0xB0, 0xEC, 0x20, 0xB8, 0xFD,
+    ];
+
+    #[test]
+    fn locates_failure() {
+        let diagnostic = Diagnostic::new(TWO_LINES, &TWO_LINES[11..], Context::Radical);
+        assert_eq!(diagnostic.offset, 11);
+        assert_eq!(diagnostic.line, 1);
+        assert_eq!(diagnostic.column, 12);
+        assert_eq!(diagnostic.bytes, b"~~");
+        assert_eq!(diagnostic.source_line, &TWO_LINES[..13]);
+    }
+
+    #[test]
+    fn locates_failure_on_later_line() {
+        let diagnostic = Diagnostic::new(TWO_LINES, &TWO_LINES[19..], Context::Radical);
+        assert_eq!(diagnostic.line, 2);
+        assert_eq!(diagnostic.column, 6);
+        assert_eq!(diagnostic.hex(), "A1 C3");
+        assert_eq!(diagnostic.source_line, &TWO_LINES[14..]);
+    }
+
+    #[test]
+    fn displays_snippet() {
+        let diagnostic = Diagnostic::new(TWO_LINES, &TWO_LINES[11..], Context::Radical);
+        let expected = "invalid radical at line 1, column 12 (byte 11): 7E 7E\n  \
+                        |\n\
+                        1 | 亜 : ｜ 一 ~~\n  \
+                        |            ^^";
+        assert_eq!(diagnostic.to_string(), expected);
+    }
+
+    #[test]
+    fn displays_zero_column() {
+        let mut diagnostic = Diagnostic::new(TWO_LINES, &TWO_LINES[11..], Context::Radical);
+        diagnostic.column = 0;
+        assert!(diagnostic.to_string().ends_with("| ^^"));
+    }
+}
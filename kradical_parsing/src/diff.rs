@@ -0,0 +1,239 @@
+//! Semantic differences between two releases of the EDRDG files
+
+use crate::{
+    invert::invert_memberships,
+    krad::Decomposition,
+    mapping::RadicalGlyph,
+    radk::{Membership, Radical},
+};
+use std::collections::{HashMap, HashSet};
+
+/// The radicals added to or removed from the decomposition of a kanji
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KanjiChange {
+    /// The kanji
+    pub kanji: String,
+
+    /// Radicals of the kanji only in the new release
+    pub added: Vec<RadicalGlyph>,
+
+    /// Radicals of the kanji only in the old release
+    pub removed: Vec<RadicalGlyph>,
+}
+
+/// A radical whose stroke count or alternate changed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RadicalChange {
+    /// The radical in the old release
+    pub old: Radical,
+
+    /// The radical in the new release
+    pub new: Radical,
+}
+
+/// The differences between two releases.
+/// Added and changed records follow the order of the new release,
+/// removed records that of the old one, and radicals are matched by JIS code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diff {
+    /// Kanji only in the new release
+    pub added_kanji: Vec<String>,
+
+    /// Kanji only in the old release
+    pub removed_kanji: Vec<String>,
+
+    /// Kanji in both releases with different radicals
+    pub changed_kanji: Vec<KanjiChange>,
+
+    /// Radicals only in the new radkfile
+    pub added_radicals: Vec<Radical>,
+
+    /// Radicals only in the old radkfile
+    pub removed_radicals: Vec<Radical>,
+
+    /// Radicals in both radkfiles with a different stroke count or alternate
+    pub changed_radicals: Vec<RadicalChange>,
+}
+
+impl Diff {
+    /// Whether the releases are equivalent
+    pub fn is_empty(&self) -> bool {
+        self.added_kanji.is_empty()
+            && self.removed_kanji.is_empty()
+            && self.changed_kanji.is_empty()
+            && self.added_radicals.is_empty()
+            && self.removed_radicals.is_empty()
+            && self.changed_radicals.is_empty()
+    }
+}
+
+/// Compares the decompositions of two kradfile releases.
+/// The radical fields of the result are always empty
+/// since kradfiles do not describe radicals.
+///
+/// # Arguments
+///
+/// * `old` - The decompositions of the old release
+/// * `new` - The decompositions of the new release
+pub fn diff_decompositions(old: &[Decomposition], new: &[Decomposition]) -> Diff {
+    let mut diff = Diff::default();
+    let old_radicals = radicals_by_kanji(old);
+    let new_radicals = radicals_by_kanji(new);
+    for decomposition in new {
+        let kanji = decomposition.kanji.as_str();
+        match old_radicals.get(kanji) {
+            None => diff.added_kanji.push(kanji.to_string()),
+            Some(previous) => {
+                let added = difference(&decomposition.radicals, previous);
+                let removed = difference(previous, &decomposition.radicals);
+                if !added.is_empty() || !removed.is_empty() {
+                    diff.changed_kanji.push(KanjiChange {
+                        kanji: kanji.to_string(),
+                        added,
+                        removed,
+                    });
+                }
+            }
+        }
+    }
+    diff.removed_kanji = old
+        .iter()
+        .filter(|decomposition| !new_radicals.contains_key(decomposition.kanji.as_str()))
+        .map(|decomposition| decomposition.kanji.clone())
+        .collect();
+    diff
+}
+
+/// Compares the memberships of two radkfile releases,
+/// both by kanji and by radical
+///
+/// # Arguments
+///
+/// * `old` - The memberships of the old release
+/// * `new` - The memberships of the new release
+pub fn diff_memberships(old: &[Membership], new: &[Membership]) -> Diff {
+    let mut diff = diff_decompositions(&invert_memberships(old), &invert_memberships(new));
+    let old_radicals: HashMap<_, _> = old
+        .iter()
+        .map(|membership| (membership.radical.glyph.jis, &membership.radical))
+        .collect();
+    let new_jis: HashSet<_> = new
+        .iter()
+        .map(|membership| membership.radical.glyph.jis)
+        .collect();
+    let mut seen = HashSet::new();
+    for membership in new {
+        let radical = &membership.radical;
+        if !seen.insert(radical.glyph.jis) {
+            continue;
+        }
+        match old_radicals.get(&radical.glyph.jis) {
+            None => diff.added_radicals.push(radical.clone()),
+            Some(&previous) => {
+                if previous.strokes != radical.strokes || previous.alternate != radical.alternate {
+                    diff.changed_radicals.push(RadicalChange {
+                        old: previous.clone(),
+                        new: radical.clone(),
+                    });
+                }
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    diff.removed_radicals = old
+        .iter()
+        .map(|membership| &membership.radical)
+        .filter(|radical| !new_jis.contains(&radical.glyph.jis) && seen.insert(radical.glyph.jis))
+        .cloned()
+        .collect();
+    diff
+}
+
+// Kradfile and kradfile2 have no kanji in common,
+// so the first decomposition of each kanji is the only one
+fn radicals_by_kanji(decompositions: &[Decomposition]) -> HashMap<&str, &[RadicalGlyph]> {
+    let mut radicals = HashMap::new();
+    for decomposition in decompositions {
+        radicals
+            .entry(decomposition.kanji.as_str())
+            .or_insert(decomposition.radicals.as_slice());
+    }
+    radicals
+}
+
+// The radicals of the left without the JIS codes of the right
+fn difference(left: &[RadicalGlyph], right: &[RadicalGlyph]) -> Vec<RadicalGlyph> {
+    left.iter()
+        .filter(|radical| !right.iter().any(|other| other.jis == radical.jis))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        krad,
+        mapping::RadicalMapping,
+        radk::{self, Alternate},
+    };
+
+    fn glyph(radical: char) -> RadicalGlyph {
+        RadicalMapping::default().radical(radical).unwrap()
+    }
+
+    #[test]
+    fn finds_no_differences() {
+        let decompositions = krad::parse_file("../assets/edrdg_files/kradfile").unwrap();
+        assert!(diff_decompositions(&decompositions, &decompositions).is_empty());
+        let memberships = radk::parse_file("../assets/edrdg_files/radkfile").unwrap();
+        assert!(diff_memberships(&memberships, &memberships).is_empty());
+    }
+
+    #[test]
+    fn diffs_decompositions() {
+        let old = krad::parse_file("../assets/edrdg_files/kradfile").unwrap();
+        let mut new = old.clone();
+        let removed = new.remove(0);
+        new[0].radicals.retain(|radical| radical.original != '一');
+        new[0].radicals.push(glyph('女'));
+        new.push(krad::parse_file("../assets/edrdg_files/kradfile2").unwrap()[0].clone());
+
+        let diff = diff_decompositions(&old, &new);
+        assert_eq!(diff.added_kanji, vec![new.last().unwrap().kanji.clone()]);
+        assert_eq!(diff.removed_kanji, vec![removed.kanji]);
+        assert_eq!(
+            diff.changed_kanji,
+            vec![KanjiChange {
+                kanji: "唖".to_string(),
+                added: vec![glyph('女')],
+                removed: vec![glyph('一')],
+            }]
+        );
+    }
+
+    #[test]
+    fn diffs_memberships() {
+        let old = radk::parse_file("../assets/edrdg_files/radkfile").unwrap();
+        let mut new = old.clone();
+        let removed = new.pop().unwrap();
+        new[0].radical.strokes = 2;
+        new[1].radical.alternate = Alternate::Glyph("乚".to_string());
+        new[0].kanji.retain(|kanji| kanji != "亜");
+
+        let diff = diff_memberships(&old, &new);
+        assert!(diff.added_radicals.is_empty());
+        assert_eq!(diff.removed_radicals, vec![removed.radical]);
+        assert_eq!(diff.changed_radicals.len(), 2);
+        assert_eq!(diff.changed_radicals[0].new.strokes, 2);
+        assert_eq!(diff.changed_radicals[1].old, old[1].radical);
+        assert_eq!(diff.changed_kanji[0].kanji, "亜");
+        assert_eq!(
+            diff.changed_kanji[0].removed,
+            vec![old[0].radical.glyph.clone()]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Subsets of the decompositions and memberships,
+//! such as the radicals of the Jōyō kanji alone
+
+use crate::{
+    invert::RadicalTable,
+    krad::Decomposition,
+    radk::{Membership, Radical},
+    shared::encode_jis_kanji,
+};
+use std::{collections::HashSet, ops::RangeInclusive};
+
+/// Which kanji and radicals to keep.
+/// The default filter keeps everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// The kanji to keep, or all of them if `None`
+    pub kanji: Option<HashSet<String>>,
+
+    /// The stroke counts of the radicals to keep, or all of them if `None`
+    pub strokes: Option<RangeInclusive<u8>>,
+
+    /// The JIS X 0208 codes of the radicals to keep, or all of them if `None`
+    pub radicals: Option<HashSet<u16>>,
+
+    /// Whether to leave out the JIS X 0212 kanji,
+    /// which are those of kradfile2 and radkfile2
+    pub exclude_jis212: bool,
+}
+
+impl Filter {
+    /// Whether the filter keeps every kanji and radical
+    pub fn keeps_everything(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the filter keeps the given kanji
+    ///
+    /// # Arguments
+    ///
+    /// * `kanji` - The kanji
+    pub fn keeps_kanji(&self, kanji: &str) -> bool {
+        self.kanji.as_ref().is_none_or(|kept| kept.contains(kanji))
+            && !(self.exclude_jis212 && is_jis212(kanji))
+    }
+
+    /// Whether the filter keeps the given radical
+    ///
+    /// # Arguments
+    ///
+    /// * `radical` - The radical
+    pub fn keeps_radical(&self, radical: &Radical) -> bool {
+        self.strokes
+            .as_ref()
+            .is_none_or(|strokes| strokes.contains(&radical.strokes))
+            && self.keeps_jis(radical.glyph.jis)
+    }
+
+    /// Filters kradfile decompositions, keeping the kanji and radicals
+    /// that pass and dropping kanji left without any radicals.
+    /// The table provides the stroke counts of the radicals,
+    /// so radicals missing from it are dropped when filtering by stroke count.
+    ///
+    /// # Arguments
+    ///
+    /// * `decompositions` - The decompositions to filter
+    /// * `table` - The radicals of a radkfile
+    pub fn decompositions(
+        &self,
+        decompositions: Vec<Decomposition>,
+        table: &RadicalTable,
+    ) -> Vec<Decomposition> {
+        decompositions
+            .into_iter()
+            .filter(|decomposition| self.keeps_kanji(&decomposition.kanji))
+            .filter_map(|mut decomposition| {
+                decomposition.radicals.retain(|radical| {
+                    let strokes_kept = match &self.strokes {
+                        Some(strokes) => table
+                            .get(radical.jis)
+                            .is_some_and(|radical| strokes.contains(&radical.strokes)),
+                        None => true,
+                    };
+                    strokes_kept && self.keeps_jis(radical.jis)
+                });
+                if decomposition.radicals.is_empty() {
+                    None
+                } else {
+                    Some(decomposition)
+                }
+            })
+            .collect()
+    }
+
+    /// Filters radkfile memberships, keeping the radicals and kanji
+    /// that pass and dropping radicals left without any kanji
+    ///
+    /// # Arguments
+    ///
+    /// * `memberships` - The memberships to filter
+    pub fn memberships(&self, memberships: Vec<Membership>) -> Vec<Membership> {
+        memberships
+            .into_iter()
+            .filter(|membership| self.keeps_radical(&membership.radical))
+            .filter_map(|mut membership| {
+                membership.kanji.retain(|kanji| self.keeps_kanji(kanji));
+                if membership.kanji.is_empty() {
+                    None
+                } else {
+                    Some(membership)
+                }
+            })
+            .collect()
+    }
+
+    fn keeps_jis(&self, jis: u16) -> bool {
+        self.radicals
+            .as_ref()
+            .is_none_or(|radicals| radicals.contains(&jis))
+    }
+}
+
+// JIS X 0212 kanji take three bytes in EUC-JP, starting with the SS3 byte
+fn is_jis212(kanji: &str) -> bool {
+    matches!(encode_jis_kanji(kanji).as_deref(), Some([0x8F, _, _]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{krad, mapping::RadicalMapping, radk};
+
+    fn kradfiles() -> Vec<Decomposition> {
+        ["kradfile", "kradfile2"]
+            .iter()
+            .flat_map(|name| krad::parse_file(format!("../assets/edrdg_files/{}", name)).unwrap())
+            .collect()
+    }
+
+    fn radkfiles() -> Vec<Membership> {
+        ["radkfile", "radkfile2"]
+            .iter()
+            .flat_map(|name| radk::parse_file(format!("../assets/edrdg_files/{}", name)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_everything_by_default() {
+        let filter = Filter::default();
+        assert!(filter.keeps_everything());
+        let decompositions = kradfiles();
+        let table = RadicalTable::default();
+        assert_eq!(
+            filter.decompositions(decompositions.clone(), &table),
+            decompositions
+        );
+    }
+
+    #[test]
+    fn excludes_jis212_kanji() {
+        let filter = Filter {
+            exclude_jis212: true,
+            ..Filter::default()
+        };
+        let table = RadicalTable::default();
+        assert_eq!(filter.decompositions(kradfiles(), &table).len(), 6_355);
+        let memberships = filter.memberships(radkfiles());
+        assert!(memberships.iter().all(|membership| membership
+            .kanji
+            .iter()
+            .all(|kanji| encode_jis_kanji(kanji).unwrap().len() == 2)));
+    }
+
+    #[test]
+    fn prunes_memberships_consistently() {
+        let mapping = RadicalMapping::default();
+        let filter = Filter {
+            kanji: Some(["亜", "唖", "娃"].iter().map(|k| k.to_string()).collect()),
+            strokes: Some(1..=2),
+            ..Filter::default()
+        };
+        let table = RadicalTable::from_memberships(&radkfiles());
+        let decompositions = filter.decompositions(kradfiles(), &table);
+        let memberships = filter.memberships(radkfiles());
+        let expected = vec![
+            mapping.radical('｜').unwrap(),
+            mapping.radical('一').unwrap(),
+        ];
+        assert_eq!(decompositions.len(), 2);
+        assert!(decompositions
+            .iter()
+            .all(|decomposition| decomposition.radicals == expected));
+        assert_eq!(memberships.len(), 2);
+        for membership in &memberships {
+            assert!(expected.contains(&membership.radical.glyph));
+            assert_eq!(membership.kanji, vec!["亜", "唖"]);
+        }
+    }
+
+    #[test]
+    fn keeps_chosen_radicals() {
+        let mapping = RadicalMapping::default();
+        let mouth = mapping.radical('口').unwrap();
+        let filter = Filter {
+            radicals: Some([mouth.jis].iter().copied().collect()),
+            ..Filter::default()
+        };
+        let memberships = filter.memberships(radkfiles());
+        assert!(memberships
+            .iter()
+            .all(|membership| membership.radical.glyph == mouth));
+        let decompositions = filter.decompositions(kradfiles(), &RadicalTable::default());
+        assert!(decompositions.iter().all(|decomposition| decomposition
+            .radicals
+            .iter()
+            .all(|radical| radical.jis == mouth.jis)));
+    }
+}
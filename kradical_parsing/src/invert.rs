@@ -0,0 +1,216 @@
+//! Conversions between kradfile decompositions and radkfile memberships
+
+use crate::{
+    krad::Decomposition,
+    radk::{Membership, Radical},
+    shared::encode_jis_kanji,
+};
+use std::collections::{HashMap, HashSet};
+
+/// The radicals of a radkfile, which provide the stroke counts
+/// and alternates that a kradfile lacks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RadicalTable {
+    radicals: Vec<Radical>,
+    // The position of the first radical with each JIS code
+    indices: HashMap<u16, usize>,
+}
+
+impl RadicalTable {
+    /// Creates a table of radicals in the order
+    /// their memberships should be listed
+    ///
+    /// # Arguments
+    ///
+    /// * `radicals` - The radicals
+    pub fn new(radicals: Vec<Radical>) -> Self {
+        let mut indices = HashMap::new();
+        for (i, radical) in radicals.iter().enumerate() {
+            indices.entry(radical.glyph.jis).or_insert(i);
+        }
+        Self { radicals, indices }
+    }
+
+    /// Creates a table from the ident lines of a radkfile
+    ///
+    /// # Arguments
+    ///
+    /// * `memberships` - The memberships of a radkfile
+    pub fn from_memberships(memberships: &[Membership]) -> Self {
+        Self::new(
+            memberships
+                .iter()
+                .map(|membership| membership.radical.clone())
+                .collect(),
+        )
+    }
+
+    /// Finds the radical with the given JIS code
+    ///
+    /// # Arguments
+    ///
+    /// * `jis` - The JIS X 0208 code of the radical
+    pub fn get(&self, jis: u16) -> Option<&Radical> {
+        self.indices.get(&jis).map(|&i| &self.radicals[i])
+    }
+
+    /// Iterates over the radicals in order
+    pub fn iter(&self) -> impl Iterator<Item = &Radical> {
+        self.radicals.iter()
+    }
+}
+
+/// Derives kradfile decompositions from radkfile memberships.
+/// The kanji are ordered by their JIS code as in the kradfile,
+/// and the radicals of each kanji follow the order of the memberships.
+///
+/// # Arguments
+///
+/// * `memberships` - The memberships to invert
+pub fn invert_memberships(memberships: &[Membership]) -> Vec<Decomposition> {
+    let mut decompositions: Vec<Decomposition> = Vec::new();
+    let mut indices = HashMap::new();
+    for membership in memberships {
+        for kanji in &membership.kanji {
+            let i = *indices.entry(kanji.as_str()).or_insert_with(|| {
+                decompositions.push(Decomposition {
+                    kanji: kanji.clone(),
+                    radicals: Vec::new(),
+                });
+                decompositions.len() - 1
+            });
+            let radicals = &mut decompositions[i].radicals;
+            if !radicals.contains(&membership.radical.glyph) {
+                radicals.push(membership.radical.glyph.clone());
+            }
+        }
+    }
+    decompositions.sort_by_cached_key(|decomposition| jis_order(&decomposition.kanji));
+    decompositions
+}
+
+/// Derives radkfile memberships from kradfile decompositions,
+/// with one membership for each radical in the table.
+/// When the table has several radicals with the same JIS code, only the first is used.
+/// The kanji of each membership follow the order of the decompositions.
+/// Radicals that are missing from the table are skipped.
+///
+/// # Arguments
+///
+/// * `decompositions` - The decompositions to invert
+/// * `table` - The radicals to list memberships for
+pub fn invert_decompositions(
+    decompositions: &[Decomposition],
+    table: &RadicalTable,
+) -> Vec<Membership> {
+    let mut kanji: HashMap<u16, Vec<String>> = HashMap::new();
+    let mut seen = HashSet::new();
+    for decomposition in decompositions {
+        for radical in &decomposition.radicals {
+            if seen.insert((radical.jis, decomposition.kanji.as_str())) {
+                let list = kanji.entry(radical.jis).or_default();
+                list.push(decomposition.kanji.clone());
+            }
+        }
+    }
+    // Only the first radical with each code, as in RadicalTable::get
+    table
+        .radicals
+        .iter()
+        .enumerate()
+        .filter(|&(i, radical)| table.indices[&radical.glyph.jis] == i)
+        .map(|(_i, radical)| Membership {
+            radical: radical.clone(),
+            kanji: kanji.remove(&radical.glyph.jis).unwrap_or_default(),
+        })
+        .collect()
+}
+
+// JIS X 0208 before JIS X 0212, then anything unencodable
+fn jis_order(kanji: &str) -> (usize, Vec<u8>, String) {
+    match encode_jis_kanji(kanji) {
+        Some(b) => (b.len(), b, String::new()),
+        None => (usize::MAX, Vec::new(), kanji.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{krad, radk};
+
+    fn radical_sets(decompositions: &[Decomposition]) -> HashMap<String, HashSet<u16>> {
+        decompositions
+            .iter()
+            .map(|decomposition| {
+                let jis = decomposition.radicals.iter().map(|radical| radical.jis);
+                (decomposition.kanji.clone(), jis.collect())
+            })
+            .collect()
+    }
+
+    // A few radkfile blocks list the same kanji more than once
+    fn deduplicated(mut memberships: Vec<Membership>) -> Vec<Membership> {
+        for membership in &mut memberships {
+            let mut seen = HashSet::new();
+            membership.kanji.retain(|kanji| seen.insert(kanji.clone()));
+        }
+        memberships
+    }
+
+    #[test]
+    fn round_trips_memberships() {
+        let memberships = radk::parse_file("../assets/edrdg_files/radkfile").unwrap();
+        let table = RadicalTable::from_memberships(&memberships);
+        let decompositions = invert_memberships(&memberships);
+        assert_eq!(decompositions.len(), 6_355);
+        assert_eq!(
+            invert_decompositions(&decompositions, &table),
+            deduplicated(memberships)
+        );
+    }
+
+    #[test]
+    fn round_trips_decompositions() {
+        let decompositions = krad::parse_file("../assets/edrdg_files/kradfile2").unwrap();
+        let memberships = radk::parse_file("../assets/edrdg_files/radkfile2").unwrap();
+        let table = RadicalTable::from_memberships(&memberships);
+        let inverted = invert_memberships(&invert_decompositions(&decompositions, &table));
+        let kanji: Vec<_> = inverted.iter().map(|d| &d.kanji).collect();
+        let expected: Vec<_> = decompositions.iter().map(|d| &d.kanji).collect();
+        assert_eq!(kanji, expected);
+        assert_eq!(radical_sets(&inverted), radical_sets(&decompositions));
+    }
+
+    #[test]
+    fn skips_radicals_missing_from_table() {
+        let decompositions = krad::parse_file("../assets/edrdg_files/kradfile").unwrap();
+        let memberships = invert_decompositions(&decompositions, &RadicalTable::default());
+        assert!(memberships.is_empty());
+    }
+
+    #[test]
+    fn skips_duplicate_codes_in_table() {
+        let decompositions = krad::parse_file("../assets/edrdg_files/kradfile").unwrap();
+        let radkfile = radk::parse_file("../assets/edrdg_files/radkfile").unwrap();
+        let radkfile2 = radk::parse_file("../assets/edrdg_files/radkfile2").unwrap();
+        let table = RadicalTable::from_memberships(&[radkfile.clone(), radkfile2].concat());
+        let single = RadicalTable::from_memberships(&radkfile);
+        let memberships = invert_decompositions(&decompositions, &table);
+        assert_eq!(memberships.len(), 253);
+        assert_eq!(memberships, invert_decompositions(&decompositions, &single));
+    }
+
+    #[test]
+    fn finds_first_radical_with_code() {
+        let radkfile = radk::parse_file("../assets/edrdg_files/radkfile").unwrap();
+        let radkfile2 = radk::parse_file("../assets/edrdg_files/radkfile2").unwrap();
+        let table = RadicalTable::from_memberships(&[radkfile.clone(), radkfile2].concat());
+        assert_eq!(table.iter().count(), 253 * 2);
+        for (membership, first) in radkfile.iter().zip(table.iter()) {
+            let radical = table.get(membership.radical.glyph.jis).unwrap();
+            assert!(std::ptr::eq(radical, first));
+        }
+        assert_eq!(table.get(0), None);
+    }
+}
@@ -0,0 +1,373 @@
+//! The JSON format written by the converter, which names radicals
+//! by their display glyphs rather than their JIS codes.
+//!
+//! A document is an object holding the schema `version` and either the
+//! `decompositions` or the `memberships`. In JSON Lines, each line holds a
+//! single record instead. The JSON Schemas for each version are published
+//! in the `assets/schemas` directory of the repository.
+
+use crate::{consistency::Source, krad, radk};
+use serde::{de, Deserialize, Serialize};
+use std::io::Write;
+
+/// The version of the JSON schema written by this crate
+pub const VERSION: u32 = 1;
+
+/// The layout of the written JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Style {
+    /// An indented document
+    Pretty,
+
+    /// A document without whitespace
+    Compact,
+
+    /// One record per line without the enclosing document
+    Lines,
+}
+
+/// A decomposition as written to JSON
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Decomposition {
+    pub kanji: String,
+    pub radicals: Vec<String>,
+}
+
+/// A membership as written to JSON
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Membership {
+    pub radical: String,
+
+    // Outputs before the first version misspelled the field
+    #[serde(alias = "stroke")]
+    pub strokes: u8,
+
+    #[serde(default = "no_alternate")]
+    pub alternate: radk::Alternate,
+
+    pub kanji: Vec<String>,
+}
+
+/// A radical as written to combined JSON
+#[derive(Debug, Serialize)]
+struct Radical {
+    radical: String,
+    strokes: u8,
+    alternate: radk::Alternate,
+}
+
+/// A file that combined JSON was written from
+#[derive(Debug, Serialize)]
+struct SourceFile<'a> {
+    path: &'a str,
+    kind: Source,
+}
+
+fn no_alternate() -> radk::Alternate {
+    radk::Alternate::None
+}
+
+#[derive(Serialize)]
+struct Decompositions<'a> {
+    version: u32,
+    decompositions: &'a [Decomposition],
+}
+
+#[derive(Serialize)]
+struct Memberships<'a> {
+    version: u32,
+    memberships: &'a [Membership],
+}
+
+#[derive(Serialize)]
+struct Combined<'a> {
+    version: u32,
+    sources: &'a [SourceFile<'a>],
+    radicals: &'a [Radical],
+    kanji: &'a [Decomposition],
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum CombinedRecord<'a> {
+    Radical(&'a Radical),
+    Kanji(&'a Decomposition),
+}
+
+// Any of the layouts that have been written,
+// including the bare arrays from before the first version
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Input<T> {
+    Document {
+        version: u32,
+        #[serde(alias = "decompositions", alias = "memberships")]
+        records: Vec<T>,
+    },
+    Records(Vec<T>),
+    Record(T),
+}
+
+/// Reads the records of a document, an array, or JSON Lines
+pub(crate) fn read<'a, T: Deserialize<'a>>(text: &'a str) -> serde_json::Result<Vec<T>> {
+    let mut records = Vec::new();
+    for input in serde_json::Deserializer::from_str(text).into_iter::<Input<T>>() {
+        match input? {
+            Input::Document { version, .. } if version > VERSION => {
+                return Err(de::Error::custom(format!(
+                    "unsupported schema version {}",
+                    version
+                )));
+            }
+            Input::Document { records: more, .. } | Input::Records(more) => records.extend(more),
+            Input::Record(record) => records.push(record),
+        }
+    }
+    Ok(records)
+}
+
+/// Writes decompositions as JSON
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the JSON
+/// * `decompositions` - The decompositions to write
+/// * `style` - The layout of the JSON
+pub fn write_decompositions<W: Write>(
+    writer: W,
+    decompositions: &[krad::Decomposition],
+    style: Style,
+) -> serde_json::Result<()> {
+    let decompositions: Vec<_> = decompositions.iter().map(decomposition).collect();
+    let document = Decompositions {
+        version: VERSION,
+        decompositions: &decompositions,
+    };
+    write(writer, &document, &decompositions, style)
+}
+
+/// Writes memberships as JSON
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the JSON
+/// * `memberships` - The memberships to write
+/// * `style` - The layout of the JSON
+pub fn write_memberships<W: Write>(
+    writer: W,
+    memberships: &[radk::Membership],
+    style: Style,
+) -> serde_json::Result<()> {
+    let memberships: Vec<_> = memberships
+        .iter()
+        .map(|membership| Membership {
+            radical: membership.radical.glyph.display.clone(),
+            strokes: membership.radical.strokes,
+            alternate: membership.radical.alternate.clone(),
+            kanji: membership.kanji.clone(),
+        })
+        .collect();
+    let document = Memberships {
+        version: VERSION,
+        memberships: &memberships,
+    };
+    write(writer, &document, &memberships, style)
+}
+
+/// Writes the radicals of radkfiles and the decompositions of kradfiles
+/// as a single JSON document. With JSON Lines, the radicals come before
+/// the decompositions and the sources are left out.
+/// Combined JSON is only written, for other programs to read;
+/// this crate has no parser for it.
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the JSON
+/// * `sources` - The paths of the files the records were read from, along with their kind
+/// * `radicals` - The radicals to write
+/// * `decompositions` - The decompositions to write
+/// * `style` - The layout of the JSON
+pub fn write_combined<W: Write>(
+    writer: W,
+    sources: &[(String, Source)],
+    radicals: &[radk::Radical],
+    decompositions: &[krad::Decomposition],
+    style: Style,
+) -> serde_json::Result<()> {
+    let sources: Vec<_> = sources
+        .iter()
+        .map(|(path, kind)| SourceFile { path, kind: *kind })
+        .collect();
+    let radicals: Vec<_> = radicals
+        .iter()
+        .map(|radical| Radical {
+            radical: radical.glyph.display.clone(),
+            strokes: radical.strokes,
+            alternate: radical.alternate.clone(),
+        })
+        .collect();
+    let kanji: Vec<_> = decompositions.iter().map(decomposition).collect();
+    let records: Vec<_> = radicals
+        .iter()
+        .map(CombinedRecord::Radical)
+        .chain(kanji.iter().map(CombinedRecord::Kanji))
+        .collect();
+    let document = Combined {
+        version: VERSION,
+        sources: &sources,
+        radicals: &radicals,
+        kanji: &kanji,
+    };
+    write(writer, &document, &records, style)
+}
+
+fn decomposition(decomposition: &krad::Decomposition) -> Decomposition {
+    Decomposition {
+        kanji: decomposition.kanji.clone(),
+        radicals: decomposition
+            .radicals
+            .iter()
+            .map(|radical| radical.display.clone())
+            .collect(),
+    }
+}
+
+fn write<W: Write, D: Serialize, T: Serialize>(
+    mut writer: W,
+    document: &D,
+    records: &[T],
+    style: Style,
+) -> serde_json::Result<()> {
+    match style {
+        Style::Pretty => serde_json::to_writer_pretty(&mut writer, document)?,
+        Style::Compact => serde_json::to_writer(&mut writer, document)?,
+        Style::Lines => {
+            for record in records {
+                serde_json::to_writer(&mut writer, record)?;
+                writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+            }
+            return Ok(());
+        }
+    }
+    writer.write_all(b"\n").map_err(serde_json::Error::io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping::RadicalMapping;
+
+    fn decompositions() -> Vec<krad::Decomposition> {
+        let mapping = RadicalMapping::default();
+        vec![krad::Decomposition {
+            kanji: "化".to_string(),
+            radicals: vec![
+                mapping.radical('化').unwrap(),
+                mapping.radical('匕').unwrap(),
+            ],
+        }]
+    }
+
+    fn written(style: Style) -> String {
+        let mut out = vec![];
+        write_decompositions(&mut out, &decompositions(), style).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_each_style() {
+        assert_eq!(
+            written(Style::Compact),
+            "{\"version\":1,\"decompositions\":[{\"kanji\":\"化\",\"radicals\":[\"⺅\",\"匕\"]}]}\n"
+        );
+        assert_eq!(
+            written(Style::Lines),
+            "{\"kanji\":\"化\",\"radicals\":[\"⺅\",\"匕\"]}\n"
+        );
+        assert!(written(Style::Pretty).starts_with("{\n  \"version\": 1,\n"));
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let mut decompositions = decompositions();
+        decompositions[0].kanji = "\"\\".to_string();
+        let mut out = vec![];
+        write_decompositions(&mut out, &decompositions, Style::Lines).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with(r#"{"kanji":"\"\\","#));
+    }
+
+    #[test]
+    fn reads_each_style() {
+        for style in [Style::Pretty, Style::Compact, Style::Lines] {
+            let text = written(style);
+            let records: Vec<Decomposition> = read(&text).unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].radicals, vec!["⺅", "匕"]);
+        }
+    }
+
+    #[test]
+    fn reads_unversioned_array() {
+        let text = r#"[{"radical": "一", "stroke": 1, "kanji": ["亜"]}]"#;
+        let records: Vec<Membership> = read(text).unwrap();
+        assert_eq!(records[0].strokes, 1);
+        assert_eq!(records[0].alternate, radk::Alternate::None);
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let text = r#"{"version": 2, "decompositions": []}"#;
+        assert!(read::<Decomposition>(text).is_err());
+    }
+
+    #[test]
+    fn writes_combined() {
+        let radicals = [radk::Radical {
+            glyph: RadicalMapping::default().radical('化').unwrap(),
+            strokes: 2,
+            alternate: radk::Alternate::Image("js01".to_string()),
+        }];
+        let sources = [("kradfile".to_string(), Source::Krad)];
+        let mut out = vec![];
+        write_combined(
+            &mut out,
+            &sources,
+            &radicals,
+            &decompositions(),
+            Style::Compact,
+        )
+        .unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(document["version"], VERSION);
+        assert_eq!(document["sources"][0]["kind"], "krad");
+        assert_eq!(document["radicals"][0]["alternate"]["value"], "js01");
+        assert_eq!(document["kanji"][0]["radicals"][0], "⺅");
+
+        let mut out = vec![];
+        write_combined(
+            &mut out,
+            &sources,
+            &radicals,
+            &decompositions(),
+            Style::Lines,
+        )
+        .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"radical":"⺅","strokes":2,"#));
+        assert!(lines[1].starts_with(r#"{"kanji":"化","#));
+    }
+
+    #[test]
+    fn publishes_current_schemas() {
+        for name in ["krad", "radk", "combined"] {
+            let path = format!("../assets/schemas/{}-v{}.schema.json", name, VERSION);
+            let schema: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            assert_eq!(schema["properties"]["version"]["const"], VERSION);
+        }
+    }
+}
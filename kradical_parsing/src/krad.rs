@@ -1,18 +1,34 @@
 //! Parser for `kradfile` and `kradfile2`.
+//!
+//! The `parse_*` functions decompress gzipped input
+//! such as `kradfile.gz` when the `gzip` feature is enabled.
 
-use crate::shared::{comments, decode_jis_kanji, decode_jis_radical};
-use nom::{
-    bytes::{
-        complete::{tag, take_until},
-        streaming::is_not,
+#[cfg(feature = "json")]
+use crate::json;
+use crate::{
+    diagnostic::{context, Context, Diagnostic, ParseResult},
+    mapping::{RadicalGlyph, RadicalMapping},
+    metadata::{Commented, Revision},
+    options::{self, ParseOptions, Parsed},
+    shared::{
+        comment_text, comments, decode_jis_kanji, decode_jis_radical, decompress, encode_comment,
+        encode_jis_kanji, encode_jis_radical, is_comment_or_blank, open_file, read_line,
     },
+    utf8,
+};
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag},
     character::complete::char,
-    combinator::{map, map_res, opt},
+    combinator::{cut, eof, map, map_res, not, opt, peek},
     multi::separated_list1,
-    sequence::separated_pair,
-    IResult,
+    sequence::{preceded, separated_pair, terminated},
+};
+use std::{
+    io::{BufRead, Read, Write},
+    iter,
+    path::Path,
 };
-use std::path::Path;
 use thiserror::Error;
 
 #[cfg(test)]
@@ -22,24 +38,60 @@ mod tests;
 #[derive(Error, Debug)]
 pub enum KradError {
     /// Error while parsing kradfile
-    #[error("Error while parsing kradfile")]
-    Parse,
+    #[error("Error while parsing kradfile: {0}")]
+    Parse(Diagnostic),
 
-    /// Error while reading kradfile
-    #[error("Error while reading kradfile")]
+    /// Error while reading or writing kradfile
+    #[error("Error while reading or writing kradfile")]
     Io(#[from] std::io::Error),
+
+    /// A character could not be represented in the kradfile encoding
+    #[error("No JIS encoding for {0}")]
+    Encode(String),
+
+    /// A radical glyph could not be matched with a JIS X 0208 character
+    #[error("No JIS X 0208 character for radical {0}")]
+    Radical(String),
+
+    /// Error while parsing JSON
+    #[cfg(feature = "json")]
+    #[error("Error while parsing JSON")]
+    Json(#[from] serde_json::Error),
 }
 
 const SEPARATOR: &[u8] = " : ".as_bytes();
 
 /// A decomposition of a kanji into its constituent radicals
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Decomposition {
-    /// The kanji character
+    /// The kanji character, which is never replaced by the radical mapping
     pub kanji: String,
 
-    /// A list of characters representing the radicals in the kanji
-    pub radicals: Vec<String>,
+    /// The radicals in the kanji, each with both its original
+    /// JIS character and the glyph that replaces it
+    pub radicals: Vec<RadicalGlyph>,
+}
+
+/// The contents of a kradfile or kradfile2 including its comments
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KradFile {
+    /// The comment lines before the first decomposition
+    pub header: Vec<String>,
+
+    /// The latest date mentioned in the header,
+    /// which identifies the release of the file
+    pub revision: Option<Revision>,
+
+    /// The decompositions along with any comments between them
+    pub decompositions: Vec<Commented<Decomposition>>,
+
+    /// The comment lines after the last decomposition
+    pub footer: Vec<String>,
+
+    /// A diagnostic for each decomposition skipped while recovering
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 type KradResult = Result<Vec<Decomposition>, KradError>;
@@ -56,9 +108,7 @@ pub fn parse_file<P: AsRef<Path>>(path: P) -> KradResult {
 
 // Monomorphisation bloat avoidal splitting
 fn parse_file_implementation(path: &Path) -> KradResult {
-    std::fs::read(path)
-        .map_err(|err| err.into())
-        .and_then(|b| parse_bytes(&b))
+    Decompositions::new(open_file(path)?).collect()
 }
 
 /// Parses the contents of a kradfile or kradfile2 and returns
@@ -68,35 +118,485 @@ fn parse_file_implementation(path: &Path) -> KradResult {
 ///
 /// * `b` - The bytes to parse
 pub fn parse_bytes(b: &[u8]) -> KradResult {
-    lines(b).map(|(_i, o)| o).map_err(|_err| KradError::Parse)
+    Decompositions::new(decompress(b)?).collect()
+}
+
+/// Parses a kradfile or kradfile2 according to the given options,
+/// returning the decoded records along with diagnostics
+/// for any records that were skipped
+///
+/// # Arguments
+///
+/// * `path` - A path to the kradfile
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<Parsed<Decomposition>, KradError> {
+    parse_file_with_options_implementation(path.as_ref(), options)
+}
+
+// Monomorphisation bloat avoidal splitting
+fn parse_file_with_options_implementation(
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<Parsed<Decomposition>, KradError> {
+    let decompositions = Decompositions::with_mapping(open_file(path)?, options.mapping.clone());
+    options::collect(decompositions, options, into_diagnostic)
+}
+
+/// Parses the contents of a kradfile or kradfile2 according to the given options,
+/// returning the decoded records along with diagnostics
+/// for any records that were skipped
+///
+/// # Arguments
+///
+/// * `b` - The bytes to parse
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_bytes_with_options(
+    b: &[u8],
+    options: &ParseOptions,
+) -> Result<Parsed<Decomposition>, KradError> {
+    let decompositions = Decompositions::with_mapping(decompress(b)?, options.mapping.clone());
+    options::collect(decompositions, options, into_diagnostic)
+}
+
+fn into_diagnostic(err: KradError) -> Result<Diagnostic, KradError> {
+    match err {
+        KradError::Parse(diagnostic) => Ok(diagnostic),
+        err => Err(err),
+    }
+}
+
+/// An iterator over the decompositions in a kradfile or kradfile2
+/// that only reads as many lines as needed to produce the next one
+///
+/// After a line fails to parse, iteration continues from the following line.
+pub struct Decompositions<R> {
+    reader: R,
+    mapping: RadicalMapping,
+    offset: usize,
+    line: usize,
+
+    // Comments after the last decomposition
+    trailing_comments: Vec<String>,
+    // Comments before decompositions that failed to parse,
+    // carried over to the next one
+    skipped_comments: Vec<String>,
+}
+
+impl<R: BufRead> Decompositions<R> {
+    /// Creates an iterator over the decompositions read from `reader`
+    /// using the default radical replacements
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the kradfile contents
+    pub fn new(reader: R) -> Self {
+        Self::with_mapping(reader, RadicalMapping::default())
+    }
+
+    /// Creates an iterator over the decompositions read from `reader`
+    /// using the given radical replacements
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the kradfile contents
+    /// * `mapping` - The replacements for radicals without a JIS X 0208 character
+    pub fn with_mapping(reader: R, mapping: RadicalMapping) -> Self {
+        Self {
+            reader,
+            mapping,
+            offset: 0,
+            line: 0,
+            trailing_comments: Vec::new(),
+            skipped_comments: Vec::new(),
+        }
+    }
+
+    // Reads any comments along with the kanji line that follows them
+    fn next_record(&mut self) -> Result<Option<Commented<Vec<u8>>>, KradError> {
+        let mut comments = Vec::new();
+        let mut record = Vec::new();
+        while let Some(line) = read_line(&mut self.reader)? {
+            record.extend_from_slice(&line);
+            if !is_comment_or_blank(&line) {
+                return Ok(Some(Commented { comments, record }));
+            }
+            comments.extend(comment_text(&line));
+        }
+        self.trailing_comments = comments;
+        Ok(None)
+    }
+
+    fn next_commented(&mut self) -> Option<Result<Commented<Decomposition>, KradError>> {
+        let Commented { comments, record } = match self.next_record() {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                self.skipped_comments.append(&mut self.trailing_comments);
+                self.trailing_comments = std::mem::take(&mut self.skipped_comments);
+                return None;
+            }
+            Err(err) => return Some(Err(err)),
+        };
+        let (offset, line) = (self.offset, self.line);
+        self.offset += record.len();
+        self.line += record.iter().filter(|&&b| b == b'\n').count();
+        let parsed = match next_kanji(&self.mapping)(&record) {
+            Ok((_i, record)) => {
+                let mut skipped = std::mem::take(&mut self.skipped_comments);
+                skipped.extend(comments);
+                Ok(Commented {
+                    comments: skipped,
+                    record,
+                })
+            }
+            Err(err) => {
+                self.skipped_comments.extend(comments);
+                let diagnostic = Diagnostic::from_err(&record, err).offset_by(offset, line);
+                Err(KradError::Parse(diagnostic))
+            }
+        };
+        Some(parsed)
+    }
+}
+
+impl<R: BufRead> Iterator for Decompositions<R> {
+    type Item = Result<Decomposition, KradError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_commented()
+            .map(|parsed| parsed.map(|commented| commented.record))
+    }
+}
+
+/// Parses a kradfile or kradfile2, keeping its comments
+/// and identifying its revision
+///
+/// # Arguments
+///
+/// * `path` - A path to the kradfile
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_file_with_comments<P: AsRef<Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<KradFile, KradError> {
+    parse_file_with_comments_implementation(path.as_ref(), options)
+}
+
+// Monomorphisation bloat avoidal splitting
+fn parse_file_with_comments_implementation(
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<KradFile, KradError> {
+    let decompositions = Decompositions::with_mapping(open_file(path)?, options.mapping.clone());
+    parse_with_comments(decompositions, options)
+}
+
+/// Parses the contents of a kradfile or kradfile2, keeping its comments
+/// and identifying its revision
+///
+/// # Arguments
+///
+/// * `b` - The bytes to parse
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_bytes_with_comments(b: &[u8], options: &ParseOptions) -> Result<KradFile, KradError> {
+    let decompositions = Decompositions::with_mapping(decompress(b)?, options.mapping.clone());
+    parse_with_comments(decompositions, options)
+}
+
+fn parse_with_comments<R: BufRead>(
+    mut decompositions: Decompositions<R>,
+    options: &ParseOptions,
+) -> Result<KradFile, KradError> {
+    let Parsed {
+        mut records,
+        diagnostics,
+    } = options::collect(
+        iter::from_fn(|| decompositions.next_commented()),
+        options,
+        into_diagnostic,
+    )?;
+    // Everything before the first record belongs to the file as a whole
+    let header = match records.first_mut() {
+        Some(first) => std::mem::take(&mut first.comments),
+        None => std::mem::take(&mut decompositions.trailing_comments),
+    };
+    Ok(KradFile {
+        revision: Revision::from_comments(&header),
+        header,
+        decompositions: records,
+        footer: decompositions.trailing_comments,
+        diagnostics,
+    })
+}
+
+/// Parses the UTF-8 text written by the converter,
+/// with one `kanji : radical radical ...` line per decomposition.
+/// Radicals are matched against their display glyphs
+/// using the default radical replacements.
+///
+/// # Arguments
+///
+/// * `text` - The text to parse
+pub fn parse_utf8(text: &str) -> KradResult {
+    utf8_decompositions(text, &RadicalMapping::default()).collect()
+}
+
+/// Parses a UTF-8 text file written by the converter
+///
+/// # Arguments
+///
+/// * `path` - A path to the text file
+pub fn parse_utf8_file<P: AsRef<Path>>(path: P) -> KradResult {
+    parse_utf8_file_implementation(path.as_ref())
+}
+
+// Monomorphisation bloat avoidal splitting
+fn parse_utf8_file_implementation(path: &Path) -> KradResult {
+    let mut text = String::new();
+    open_file(path)?.read_to_string(&mut text)?;
+    parse_utf8(&text)
+}
+
+/// Parses the UTF-8 text written by the converter according to the given options,
+/// returning the decoded records along with diagnostics
+/// for any lines that were skipped
+///
+/// # Arguments
+///
+/// * `text` - The text to parse
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_utf8_with_options(
+    text: &str,
+    options: &ParseOptions,
+) -> Result<Parsed<Decomposition>, KradError> {
+    let decompositions = utf8_decompositions(text, &options.mapping);
+    options::collect(decompositions, options, into_diagnostic)
+}
+
+fn utf8_decompositions<'a>(
+    text: &'a str,
+    mapping: &'a RadicalMapping,
+) -> impl Iterator<Item = Result<Decomposition, KradError>> + 'a {
+    utf8::lines(text).map(move |line| {
+        utf8_decomposition(line.text, mapping)
+            .map_err(|failure| KradError::Parse(utf8::diagnostic(text, &line, failure)))
+    })
+}
+
+fn utf8_decomposition(
+    line: &str,
+    mapping: &RadicalMapping,
+) -> Result<Decomposition, (usize, Context)> {
+    let (kanji, start, radicals) = utf8::split_separator(line)?;
+    if kanji.is_empty() || kanji.contains(' ') {
+        return Err((0, Context::Kanji));
+    }
+    let radicals = utf8::tokens(radicals)
+        .map(|(offset, radical)| {
+            mapping
+                .from_display(radical)
+                .ok_or((start + offset, Context::Radical))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if radicals.is_empty() {
+        return Err((start, Context::Radical));
+    }
+    Ok(Decomposition {
+        kanji: kanji.to_string(),
+        radicals,
+    })
+}
+
+/// Parses the JSON written by the converter in any [`json::Style`],
+/// matching radicals against their display glyphs
+/// using the default radical replacements
+///
+/// # Arguments
+///
+/// * `text` - The JSON to parse
+#[cfg(feature = "json")]
+pub fn parse_json(text: &str) -> KradResult {
+    parse_json_with_mapping(text, &RadicalMapping::default())
+}
+
+/// Parses a JSON file written by the converter
+///
+/// # Arguments
+///
+/// * `path` - A path to the JSON file
+#[cfg(feature = "json")]
+pub fn parse_json_file<P: AsRef<Path>>(path: P) -> KradResult {
+    parse_json_file_implementation(path.as_ref())
+}
+
+// Monomorphisation bloat avoidal splitting
+#[cfg(feature = "json")]
+fn parse_json_file_implementation(path: &Path) -> KradResult {
+    let mut text = String::new();
+    open_file(path)?.read_to_string(&mut text)?;
+    parse_json(&text)
+}
+
+/// Parses the JSON written by the converter,
+/// matching radicals against their display glyphs using the given replacements
+///
+/// # Arguments
+///
+/// * `text` - The JSON to parse
+/// * `mapping` - The replacements that were used to write the JSON
+#[cfg(feature = "json")]
+pub fn parse_json_with_mapping(text: &str, mapping: &RadicalMapping) -> KradResult {
+    let decompositions: Vec<json::Decomposition> = json::read(text)?;
+    decompositions
+        .into_iter()
+        .map(|decomposition| {
+            let radicals = decomposition
+                .radicals
+                .iter()
+                .map(|radical| {
+                    mapping
+                        .from_display(radical)
+                        .ok_or_else(|| KradError::Radical(radical.clone()))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Decomposition {
+                kanji: decomposition.kanji,
+                radicals,
+            })
+        })
+        .collect()
+}
+
+/// Writes decompositions in the kradfile format.
+/// Kanji outside of JIS X 0208 are written in JIS X 0212 as in kradfile2,
+/// and radicals are written as their original JIS characters.
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the kradfile contents
+/// * `decompositions` - The decompositions to write
+pub fn write_kradfile<W: Write>(
+    mut writer: W,
+    decompositions: &[Decomposition],
+) -> Result<(), KradError> {
+    write_kradfile_implementation(&mut writer, decompositions)
 }
 
-fn lines(b: &[u8]) -> IResult<&[u8], Vec<Decomposition>> {
-    separated_list1(char('\n'), next_kanji)(b)
+// Monomorphisation bloat avoidal splitting
+fn write_kradfile_implementation(
+    writer: &mut dyn Write,
+    decompositions: &[Decomposition],
+) -> Result<(), KradError> {
+    for decomposition in decompositions {
+        writer.write_all(&kanji_line_bytes(decomposition)?)?;
+    }
+    Ok(())
 }
 
-fn next_kanji(b: &[u8]) -> IResult<&[u8], Decomposition> {
+/// Writes a kradfile including its comments,
+/// reproducing the original file for unmodified contents
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the kradfile contents
+/// * `file` - The kradfile contents to write
+pub fn write_kradfile_with_comments<W: Write>(
+    mut writer: W,
+    file: &KradFile,
+) -> Result<(), KradError> {
+    write_kradfile_with_comments_implementation(&mut writer, file)
+}
+
+// Monomorphisation bloat avoidal splitting
+fn write_kradfile_with_comments_implementation(
+    writer: &mut dyn Write,
+    file: &KradFile,
+) -> Result<(), KradError> {
+    write_comments(writer, &file.header)?;
+    for commented in &file.decompositions {
+        write_comments(writer, &commented.comments)?;
+        writer.write_all(&kanji_line_bytes(&commented.record)?)?;
+    }
+    write_comments(writer, &file.footer)
+}
+
+fn write_comments(writer: &mut dyn Write, comments: &[String]) -> Result<(), KradError> {
+    for comment in comments {
+        writer.write_all(&encode(comment, encode_comment)?)?;
+    }
+    Ok(())
+}
+
+fn kanji_line_bytes(decomposition: &Decomposition) -> Result<Vec<u8>, KradError> {
+    let mut line = encode(&decomposition.kanji, encode_jis_kanji)?;
+    line.extend_from_slice(SEPARATOR);
+    for (i, radical) in decomposition.radicals.iter().enumerate() {
+        if i > 0 {
+            line.push(b' ');
+        }
+        line.extend(encode_jis_radical(radical));
+    }
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn encode(glyph: &str, encoder: fn(&str) -> Option<Vec<u8>>) -> Result<Vec<u8>, KradError> {
+    encoder(glyph).ok_or_else(|| KradError::Encode(glyph.to_string()))
+}
+
+// Once past the comments, anything other than
+// the end of the file must be a valid kanji line
+fn next_kanji<'a>(
+    mapping: &'a RadicalMapping,
+) -> impl FnMut(&'a [u8]) -> ParseResult<'a, Decomposition> {
     map(
-        separated_pair(comments, opt(char('\n')), kanji_line),
+        separated_pair(
+            comments,
+            opt(char('\n')),
+            preceded(not(eof), cut(kanji_line(mapping))),
+        ),
         |(_comments, kanji)| kanji,
-    )(b)
+    )
 }
 
-fn kanji_line(b: &[u8]) -> IResult<&[u8], Decomposition> {
+fn kanji_line<'a>(
+    mapping: &'a RadicalMapping,
+) -> impl FnMut(&'a [u8]) -> ParseResult<'a, Decomposition> {
     map(
-        separated_pair(kanji, tag(SEPARATOR), radicals),
+        terminated(
+            separated_pair(kanji, separator, radicals(mapping)),
+            line_end,
+        ),
         |(kanji, radicals)| Decomposition { kanji, radicals },
-    )(b)
+    )
+}
+
+fn kanji(b: &[u8]) -> ParseResult<'_, String> {
+    context(Context::Kanji, map_res(is_not(" \n"), decode_jis_kanji))(b)
+}
+
+fn separator(b: &[u8]) -> ParseResult<'_, &[u8]> {
+    context(Context::Separator, tag(SEPARATOR))(b)
 }
 
-fn kanji(b: &[u8]) -> IResult<&[u8], String> {
-    map_res(take_until(" "), decode_jis_kanji)(b)
+fn radicals<'a>(
+    mapping: &'a RadicalMapping,
+) -> impl FnMut(&'a [u8]) -> ParseResult<'a, Vec<RadicalGlyph>> {
+    separated_list1(char(' '), cut(radical(mapping)))
 }
 
-fn radicals(b: &[u8]) -> IResult<&[u8], Vec<String>> {
-    separated_list1(char(' '), radical)(b)
+fn radical<'a>(
+    mapping: &'a RadicalMapping,
+) -> impl FnMut(&'a [u8]) -> ParseResult<'a, RadicalGlyph> {
+    context(
+        Context::Radical,
+        map_res(is_not(" \n"), move |b| decode_jis_radical(b, mapping)),
+    )
 }
 
-fn radical(b: &[u8]) -> IResult<&[u8], String> {
-    map_res(is_not(" \n"), decode_jis_radical)(b)
+// Anything trailing the radicals is treated as a malformed radical
+fn line_end(b: &[u8]) -> ParseResult<'_, &[u8]> {
+    context(Context::Radical, peek(alt((tag("\n"), eof))))(b)
 }
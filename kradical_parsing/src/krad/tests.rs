@@ -1,5 +1,11 @@
 use super::*;
+use crate::diagnostic::Context;
+use crate::mapping::{Preset, RadicalGlyph, RadicalMapping};
 use crate::test_constants::*;
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+};
 
 // JIS213
 // "亜 : ｜ 一 口\n"
@@ -17,62 +23,76 @@ const KANJI_LINE2: &[u8] = &[
 // "｜ 一 口\n"
 const RADICALS: &[u8] = &[0xA1, 0xC3, 0x20, 0xB0, 0xEC, 0x20, 0xB8, 0xFD, 0x0A];
 
+fn glyphs(displays: &[&str]) -> Vec<RadicalGlyph> {
+    let mapping = RadicalMapping::default();
+    displays
+        .iter()
+        .map(|display| mapping.from_display(display).unwrap())
+        .collect()
+}
+
 fn parsed_kanji() -> Decomposition {
     Decomposition {
         kanji: "亜".to_string(),
-        radicals: vec!["｜".to_string(), "一".to_string(), "口".to_string()],
+        radicals: glyphs(&["｜", "一", "口"]),
     }
 }
 
 fn parsed_kanji_2() -> Decomposition {
     Decomposition {
         kanji: "丂".to_string(),
-        radicals: vec!["一".to_string(), "勹".to_string()],
+        radicals: glyphs(&["一", "勹"]),
     }
 }
 
 #[test]
 fn parses_radical() {
-    let res = radical(RADICALS);
-    assert_eq!(res, Ok((&RADICALS[2..], "｜".to_string())));
+    let mapping = RadicalMapping::default();
+    let res = radical(&mapping)(RADICALS);
+    assert_eq!(res, Ok((&RADICALS[2..], glyphs(&["｜"])[0].clone())));
 }
 
 #[test]
 fn parses_radicals() {
-    let res = radicals(RADICALS);
+    let mapping = RadicalMapping::default();
+    let res = radicals(&mapping)(RADICALS);
     assert_eq!(res, Ok((NEWLINE, parsed_kanji().radicals)));
 }
 
 #[test]
 fn parses_kanji() {
-    let res = kanji_line(KANJI_LINE);
+    let mapping = RadicalMapping::default();
+    let res = kanji_line(&mapping)(KANJI_LINE);
     assert_eq!(res, Ok((NEWLINE, parsed_kanji())));
 }
 
 #[test]
 fn parses_kanji_2() {
-    let res = kanji_line(KANJI_LINE2);
+    let mapping = RadicalMapping::default();
+    let res = kanji_line(&mapping)(KANJI_LINE2);
     assert_eq!(res, Ok((NEWLINE, parsed_kanji_2())));
 }
 
 #[test]
 fn parses_line_as_kanji() {
-    let res = next_kanji(KANJI_LINE);
+    let mapping = RadicalMapping::default();
+    let res = next_kanji(&mapping)(KANJI_LINE);
     assert_eq!(res, Ok((NEWLINE, parsed_kanji())));
 }
 
 #[test]
 fn ignores_comment() {
     let line = vec![COMMENT_LINE, KANJI_LINE].join(EMPTY);
-    let res = next_kanji(&line);
+    let mapping = RadicalMapping::default();
+    let res = next_kanji(&mapping)(&line);
     assert_eq!(res, Ok((NEWLINE, parsed_kanji())));
 }
 
 #[test]
 fn parses_lines() {
     let line = vec![KANJI_LINE, COMMENT_LINE, KANJI_LINE].join(EMPTY);
-    let res = lines(&line);
-    assert_eq!(res, Ok((NEWLINE, vec![parsed_kanji(), parsed_kanji()])));
+    let res: Result<Vec<_>, _> = Decompositions::new(&line[..]).collect();
+    assert_eq!(res.unwrap(), vec![parsed_kanji(), parsed_kanji()]);
 }
 
 #[test]
@@ -88,3 +108,388 @@ fn works_on_actual_file_2() {
     assert_eq!(res.is_ok(), true);
     assert_eq!(res.unwrap().len(), 5_801);
 }
+
+fn diagnostic(res: KradResult) -> Diagnostic {
+    match res {
+        Err(KradError::Parse(diagnostic)) => diagnostic,
+        _ => panic!("Expected a parse error, got {:?}", res),
+    }
+}
+
+#[test]
+fn locates_invalid_radical() {
+    // "亜 : ｜ \xFF\xFF\n"
+    const BAD_RADICAL: &[u8] = &[
+        0xB0, 0xA1, 0x20, 0x3A, 0x20, 0xA1, 0xC3, 0x20, 0xFF, 0xFF, 0x0A,
+    ];
+    let file = [COMMENT_LINE, KANJI_LINE, BAD_RADICAL].join(EMPTY);
+    let res = diagnostic(parse_bytes(&file));
+    assert_eq!(res.context, Context::Radical);
+    assert_eq!(res.line, 3);
+    assert_eq!(res.column, 9);
+    assert_eq!(res.offset, COMMENT_LINE.len() + KANJI_LINE.len() + 8);
+    assert_eq!(res.bytes, &[0xFF, 0xFF]);
+}
+
+#[test]
+fn locates_missing_separator() {
+    // "亜 ｜\n"
+    const BAD_SEPARATOR: &[u8] = &[0xB0, 0xA1, 0x20, 0xA1, 0xC3, 0x0A];
+    let file = [KANJI_LINE, BAD_SEPARATOR].join(EMPTY);
+    let res = diagnostic(parse_bytes(&file));
+    assert_eq!(res.context, Context::Separator);
+    assert_eq!(res.line, 2);
+    assert_eq!(res.column, 3);
+    assert_eq!(res.bytes, b" ");
+}
+
+#[test]
+fn streams_concatenated_files() {
+    let kradfile = File::open("../assets/edrdg_files/kradfile").unwrap();
+    let kradfile2 = File::open("../assets/edrdg_files/kradfile2").unwrap();
+    let reader = BufReader::new(kradfile.chain(kradfile2));
+    let res: Result<Vec<_>, _> = Decompositions::new(reader).collect();
+    assert_eq!(res.unwrap().len(), 6_355 + 5_801);
+}
+
+#[test]
+fn continues_after_invalid_line() {
+    // "亜 \xFF\xFF\n"
+    const BAD_LINE: &[u8] = &[0xB0, 0xA1, 0x20, 0xFF, 0xFF, 0x0A];
+    let file = [COMMENT_LINE, BAD_LINE, KANJI_LINE2].join(EMPTY);
+    let mut decompositions = Decompositions::new(&file[..]);
+    let first = diagnostic(decompositions.next().unwrap().map(|d| vec![d]));
+    assert_eq!(first.line, 2);
+    assert_eq!(first.offset, COMMENT_LINE.len() + 2);
+    assert_eq!(decompositions.next().unwrap().unwrap(), parsed_kanji_2());
+    assert!(decompositions.next().is_none());
+}
+
+#[test]
+fn recovers_from_invalid_line() {
+    let mut file = std::fs::read("../assets/edrdg_files/kradfile").unwrap();
+    // Corrupt the first radical of 亜 on line 101
+    let line_101 = file.windows(KANJI_LINE.len()).position(|w| w == KANJI_LINE);
+    let radical = line_101.unwrap() + 5;
+    file[radical] = 0xFF;
+    file[radical + 1] = 0xFF;
+
+    let res = parse_bytes_with_options(&file, &ParseOptions::lenient()).unwrap();
+    assert_eq!(res.records.len(), 6_354);
+    assert_eq!(res.diagnostics.len(), 1);
+    assert_eq!(res.diagnostics[0].line, 101);
+    assert_eq!(res.diagnostics[0].offset, radical);
+
+    let res = parse_bytes_with_options(&file, &ParseOptions::default());
+    assert_eq!(diagnostic(res.map(|parsed| parsed.records)).line, 101);
+}
+
+#[test]
+fn recovers_with_comments() {
+    let mut invalid = KANJI_LINE.to_vec();
+    invalid[5] = 0xFF;
+    invalid[6] = 0xFF;
+    let file = [COMMENT_LINE, &invalid, KANJI_LINE, COMMENT_LINE].join(EMPTY);
+    let res = parse_bytes_with_comments(&file, &ParseOptions::lenient()).unwrap();
+    // The header stays with the file even though the first line was skipped
+    assert_eq!(res.header, vec![" September 2007".to_string()]);
+    assert_eq!(res.revision.unwrap().year, 2007);
+    assert_eq!(res.decompositions.len(), 1);
+    assert_eq!(res.footer, vec![" September 2007".to_string()]);
+    assert_eq!(res.diagnostics.len(), 1);
+    assert_eq!(res.diagnostics[0].line, 2);
+
+    assert!(parse_bytes_with_comments(&file, &ParseOptions::default()).is_err());
+}
+
+#[test]
+fn parses_with_mapping() {
+    // 化 : 化 匕
+    const LINE: &[u8] = &[
+        0xB2, 0xBD, 0x20, 0x3A, 0x20, 0xB2, 0xBD, 0x20, 0xD2, 0xB8, 0x0A,
+    ];
+    let radicals = |preset| {
+        let options = ParseOptions {
+            mapping: RadicalMapping::preset(preset),
+            ..ParseOptions::default()
+        };
+        let res = parse_bytes_with_options(LINE, &options).unwrap();
+        let radicals = res.records[0].radicals.iter();
+        radicals
+            .map(|radical| radical.display.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(radicals(Preset::Raw), vec!["化", "匕"]);
+    assert_eq!(radicals(Preset::JishoStyle), vec!["\u{2E85}", "匕"]);
+}
+
+#[test]
+fn keeps_comments_with_mapping() {
+    // 化 : 化 匕
+    const LINE: &[u8] = &[
+        0xB2, 0xBD, 0x20, 0x3A, 0x20, 0xB2, 0xBD, 0x20, 0xD2, 0xB8, 0x0A,
+    ];
+    let file = [COMMENT_LINE, LINE].join(EMPTY);
+    let options = ParseOptions {
+        mapping: RadicalMapping::preset(Preset::Raw),
+        ..ParseOptions::default()
+    };
+    let res = parse_bytes_with_comments(&file, &options).unwrap();
+    assert_eq!(res.header, vec![" September 2007".to_string()]);
+    assert_eq!(res.decompositions[0].record.radicals[0].display, "化");
+}
+
+#[test]
+fn parses_with_overrides() {
+    let mut mapping = RadicalMapping::default();
+    mapping.load_overrides_str("个 ㅅ\n").unwrap();
+    let file = File::open("../assets/edrdg_files/kradfile").unwrap();
+    let decompositions = Decompositions::with_mapping(BufReader::new(file), mapping);
+    let radicals: Vec<_> = decompositions
+        .flat_map(|decomposition| decomposition.unwrap().radicals)
+        .collect();
+    assert!(radicals.iter().any(|radical| radical.display == "ㅅ"));
+    assert!(!radicals
+        .iter()
+        .any(|radical| radical.display == "\u{201A2}"));
+}
+
+#[test]
+fn writes_kanji_line() {
+    let mut written = vec![];
+    write_kradfile(&mut written, &[parsed_kanji(), parsed_kanji_2()]).unwrap();
+    assert_eq!(written, [KANJI_LINE, KANJI_LINE2].join(EMPTY));
+}
+
+#[test]
+fn writes_remapped_radical() {
+    let decompositions = vec![Decomposition {
+        kanji: "化".to_string(),
+        radicals: glyphs(&["\u{2E85}", "匕"]),
+    }];
+    let mut written = vec![];
+    write_kradfile(&mut written, &decompositions).unwrap();
+    // 化 : 化 匕
+    assert_eq!(
+        written,
+        &[0xB2, 0xBD, 0x20, 0x3A, 0x20, 0xB2, 0xBD, 0x20, 0xD2, 0xB8, 0x0A]
+    );
+    assert_eq!(parse_bytes(&written).unwrap(), decompositions);
+}
+
+#[test]
+fn replaces_radicals_but_not_kanji() {
+    for preset in [Preset::EdrdgSuggested, Preset::JishoStyle] {
+        let mapping = RadicalMapping::preset(preset);
+        for (original, replacement) in mapping.iter() {
+            // "X : X"
+            let glyph = encode_jis_kanji(&original.to_string()).unwrap();
+            let line = [&glyph, SEPARATOR, &glyph, b"\n"].concat();
+            let mut decompositions = Decompositions::with_mapping(&line[..], mapping.clone());
+            let decomposition = decompositions.next().unwrap().unwrap();
+            assert_eq!(decomposition.kanji, original.to_string());
+            assert_eq!(decomposition.radicals[0].original, original);
+            assert_eq!(decomposition.radicals[0].display, replacement);
+        }
+    }
+}
+
+#[test]
+fn replaces_radicals_in_actual_file() {
+    let decompositions = parse_file("../assets/edrdg_files/kradfile").unwrap();
+    let decomposition = decompositions.iter().find(|d| d.kanji == "化").unwrap();
+    let radicals: Vec<_> = decomposition.radicals.iter().map(|r| &r.display).collect();
+    assert_eq!(radicals, vec!["\u{2E85}", "匕"]);
+}
+
+#[test]
+fn keeps_original_radical() {
+    // 个 : 个
+    const LINE: &[u8] = &[0xD0, 0xA4, 0x20, 0x3A, 0x20, 0xD0, 0xA4, 0x0A];
+    let decompositions = parse_bytes(LINE).unwrap();
+    let radical = &decompositions[0].radicals[0];
+    assert_eq!(radical.jis, 0x5024);
+    assert_eq!(radical.original, '个');
+    assert_eq!(radical.display, "\u{201A2}");
+
+    // Edited display glyphs are still written as the original
+    let mut edited = decompositions.clone();
+    edited[0].radicals[0].display = "ㅅ".to_string();
+    let mut written = vec![];
+    write_kradfile(&mut written, &edited).unwrap();
+    assert_eq!(written, LINE);
+}
+
+#[test]
+fn rejects_unencodable_kanji() {
+    let decomposition = Decomposition {
+        kanji: "😀".to_string(),
+        radicals: glyphs(&["一"]),
+    };
+    let res = write_kradfile(vec![], &[decomposition]);
+    assert!(matches!(res, Err(KradError::Encode(glyph)) if glyph == "😀"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serializes_decomposition() {
+    let json = serde_json::to_value(parsed_kanji_2()).unwrap();
+    let expected = serde_json::json!({
+        "kanji": "丂",
+        "radicals": [
+            { "jis": 0x306C, "original": "一", "display": "一" },
+            { "jis": 0x5231, "original": "勹", "display": "勹" },
+        ],
+    });
+    assert_eq!(json, expected);
+    let decomposition: Decomposition = serde_json::from_value(json).unwrap();
+    assert_eq!(decomposition, parsed_kanji_2());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn parses_gzipped_file() {
+    let kradfile = gzipped_copy("../assets/edrdg_files/kradfile");
+    assert_eq!(parse_file(kradfile).unwrap().len(), 6_355);
+    let kradfile2 = gzipped("../assets/edrdg_files/kradfile2");
+    assert_eq!(parse_bytes(&kradfile2).unwrap().len(), 5_801);
+}
+
+fn round_trip(path: &str) {
+    let original = std::fs::read(path).unwrap();
+    let parsed = parse_bytes(&original).unwrap();
+    let mut written = vec![];
+    write_kradfile(&mut written, &parsed).unwrap();
+    assert!(written == without_header(&original));
+    assert_eq!(parse_bytes(&written).unwrap(), parsed);
+}
+
+#[test]
+fn round_trips_actual_file() {
+    round_trip("../assets/edrdg_files/kradfile");
+}
+
+#[test]
+fn round_trips_actual_file_2() {
+    round_trip("../assets/edrdg_files/kradfile2");
+}
+
+#[test]
+fn keeps_comments() {
+    let file = [
+        COMMENT_LINE,
+        KANJI_LINE,
+        COMMENT_LINE,
+        KANJI_LINE2,
+        COMMENT_LINE,
+    ]
+    .join(EMPTY);
+    let res = parse_bytes_with_comments(&file, &ParseOptions::default()).unwrap();
+    let comment = vec![" September 2007".to_string()];
+    assert_eq!(res.header, comment);
+    assert_eq!(
+        res.revision,
+        Some(Revision {
+            year: 2007,
+            month: 9
+        })
+    );
+    assert_eq!(
+        res.decompositions,
+        vec![
+            Commented {
+                comments: vec![],
+                record: parsed_kanji(),
+            },
+            Commented {
+                comments: comment.clone(),
+                record: parsed_kanji_2(),
+            },
+        ]
+    );
+    assert_eq!(res.footer, comment);
+
+    let mut written = vec![];
+    write_kradfile_with_comments(&mut written, &res).unwrap();
+    assert_eq!(written, file);
+}
+
+fn round_trip_with_comments(path: &str, revision: Revision) {
+    let original = std::fs::read(path).unwrap();
+    let parsed = parse_bytes_with_comments(&original, &ParseOptions::default()).unwrap();
+    assert_eq!(parsed.revision, Some(revision));
+    let mut written = vec![];
+    write_kradfile_with_comments(&mut written, &parsed).unwrap();
+    assert!(written == original);
+}
+
+#[test]
+fn round_trips_actual_file_with_comments() {
+    let revision = Revision {
+        year: 2020,
+        month: 8,
+    };
+    round_trip_with_comments("../assets/edrdg_files/kradfile", revision);
+}
+
+#[test]
+fn round_trips_actual_file_2_with_comments() {
+    let revision = Revision {
+        year: 2007,
+        month: 9,
+    };
+    round_trip_with_comments("../assets/edrdg_files/kradfile2", revision);
+}
+
+#[test]
+fn parses_utf8() {
+    let res = parse_utf8("# Edited by hand\n亜 : ｜ 一 口\n唖 : ｜ 一 口");
+    assert_eq!(
+        res.unwrap(),
+        vec![
+            Decomposition {
+                kanji: "亜".to_string(),
+                radicals: glyphs(&["｜", "一", "口"]),
+            },
+            Decomposition {
+                kanji: "唖".to_string(),
+                radicals: glyphs(&["｜", "一", "口"]),
+            },
+        ]
+    );
+}
+
+#[test]
+fn parses_utf8_output() {
+    let mut expected = parse_file("../assets/edrdg_files/kradfile").unwrap();
+    expected.extend(parse_file("../assets/edrdg_files/kradfile2").unwrap());
+    let res = parse_utf8_file("../assets/outputs/krad_utf8.txt").unwrap();
+    assert!(res == expected);
+}
+
+#[test]
+fn locates_invalid_utf8_radical() {
+    let text = "亜 : ｜ 一 口\n唖 : ｜ x 口";
+    let err = parse_utf8(text).unwrap_err();
+    match err {
+        KradError::Parse(diagnostic) => {
+            assert_eq!(diagnostic.line, 2);
+            assert_eq!(diagnostic.column, 11);
+            assert_eq!(diagnostic.bytes, b"x");
+            assert_eq!(diagnostic.context, Context::Radical);
+        }
+        _ => panic!("Expected a parse error"),
+    }
+
+    let res = parse_utf8_with_options(text, &ParseOptions::lenient()).unwrap();
+    assert_eq!(res.records.len(), 1);
+    assert_eq!(res.diagnostics.len(), 1);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn parses_json_output() {
+    let expected = parse_utf8_file("../assets/outputs/krad_utf8.txt").unwrap();
+    let res = parse_json_file("../assets/outputs/krad.json").unwrap();
+    assert!(res == expected);
+}
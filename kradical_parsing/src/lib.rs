@@ -1,9 +1,28 @@
 //! Contains parsers for `kradfile` and `radkfile`
 
+// The original tests predate these lints
+#![cfg_attr(test, allow(clippy::bool_assert_comparison, clippy::useless_vec))]
+
 #[cfg(test)]
 mod test_constants;
 
 mod shared;
+mod utf8;
 
+pub mod consistency;
+pub mod detect;
+pub mod diagnostic;
+pub mod diff;
+pub mod filter;
+pub mod invert;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod krad;
+pub mod mapping;
+pub mod metadata;
+pub mod options;
 pub mod radk;
+
+pub use detect::{detect, parse_any, FileKind};
+pub use invert::{invert_decompositions, invert_memberships, RadicalTable};
+pub use shared::decompress;
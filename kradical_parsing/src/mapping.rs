@@ -0,0 +1,470 @@
+//! Replacements for the radicals that the EDRDG files
+//! can only represent with a kanji containing them
+//!
+//! Since JIS X 0208 lacks many radicals, the files use a kanji that
+//! contains the radical in its place, such as 化 for ⺅. A [`RadicalMapping`]
+//! decides which Unicode glyph each of these is replaced with while parsing.
+//!
+//! Replacements only ever apply to characters used as radicals, which are
+//! represented by [`RadicalGlyph`]. Characters used as kanji, whether at the
+//! start of a kradfile line or in the kanji lines of a radkfile, are kept as
+//! they are. The decomposition of 化 therefore keeps 化 as its kanji
+//! while listing ⺅ among its radicals.
+
+use crate::shared::jis_code;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    path::Path,
+};
+use thiserror::Error;
+
+/// Enumerates the possible errors while loading mapping overrides
+#[derive(Debug, Error)]
+pub enum MappingError {
+    /// A line did not contain a radical and at most one replacement
+    #[error("Invalid radical mapping on line {0}")]
+    Parse(usize),
+
+    /// The replacement on a line already replaces another radical
+    #[error("Replacement {1} on line {0} already replaces {2}")]
+    Duplicate(usize, String, char),
+
+    /// Error while reading the overrides file
+    #[error("Error while reading radical mapping")]
+    Io(#[from] std::io::Error),
+}
+
+/// A built-in set of radical replacements
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Preset {
+    /// No replacements, keeping the JIS X 0208 characters from the files
+    Raw,
+
+    /// The replacements suggested in the kradfile header
+    EdrdgSuggested,
+
+    /// The replacements used by Jisho, which correct some of
+    /// the suggestions and add a few more
+    JishoStyle,
+}
+
+/// A radical as it appears in the EDRDG files
+/// along with the glyph that replaces it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RadicalGlyph {
+    /// The JIS X 0208 code of the original character, such as `0x323D` for 化.
+    /// The EUC-JP encoding sets the high bit of each byte.
+    pub jis: u16,
+
+    /// The JIS X 0208 character used in the EDRDG files,
+    /// which other EDRDG tools also use
+    pub original: char,
+
+    /// The glyph to display, which is the original character
+    /// unless the radical mapping replaced it
+    pub display: String,
+}
+
+impl RadicalGlyph {
+    /// Whether the radical mapping replaced the original character
+    pub fn is_replaced(&self) -> bool {
+        let mut chars = self.display.chars();
+        (chars.next(), chars.next()) != (Some(self.original), None)
+    }
+}
+
+impl Display for RadicalGlyph {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.display)
+    }
+}
+
+/// Maps the JIS X 0208 characters that stand in for radicals
+/// to the glyphs that replace them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RadicalMapping {
+    replacements: HashMap<char, String>,
+    // The reverse of the replacements, since no two radicals share one
+    originals: HashMap<String, char>,
+}
+
+impl RadicalMapping {
+    /// Creates a mapping from a built-in set of replacements
+    ///
+    /// # Arguments
+    ///
+    /// * `preset` - The replacements to use
+    pub fn preset(preset: Preset) -> Self {
+        let table = match preset {
+            Preset::Raw => &[],
+            Preset::EdrdgSuggested => EDRDG_SUGGESTED,
+            Preset::JishoStyle => JISHO_STYLE,
+        };
+        let mut mapping = Self {
+            replacements: HashMap::new(),
+            originals: HashMap::new(),
+        };
+        for &(radical, replacement) in table {
+            mapping.insert(radical, replacement);
+        }
+        mapping
+    }
+
+    /// The replacement for a radical, if it has one
+    ///
+    /// # Arguments
+    ///
+    /// * `radical` - The JIS X 0208 character used for the radical
+    pub fn get(&self, radical: char) -> Option<&str> {
+        self.replacements.get(&radical).map(|s| s.as_str())
+    }
+
+    /// Replaces a radical, returning the previous replacement.
+    /// Any other radical with the same replacement stops being replaced
+    /// so that each glyph maps back to a single radical.
+    ///
+    /// # Arguments
+    ///
+    /// * `radical` - The JIS X 0208 character used for the radical
+    /// * `replacement` - The glyph to use instead
+    pub fn insert<S: Into<String>>(&mut self, radical: char, replacement: S) -> Option<String> {
+        let replacement = replacement.into();
+        let previous = self.remove(radical);
+        if let Some(other) = self.originals.insert(replacement.clone(), radical) {
+            self.replacements.remove(&other);
+        }
+        self.replacements.insert(radical, replacement);
+        previous
+    }
+
+    /// Stops replacing a radical, returning the previous replacement
+    ///
+    /// # Arguments
+    ///
+    /// * `radical` - The JIS X 0208 character used for the radical
+    pub fn remove(&mut self, radical: char) -> Option<String> {
+        let previous = self.replacements.remove(&radical)?;
+        self.originals.remove(&previous);
+        Some(previous)
+    }
+
+    /// Iterates over each radical and its replacement in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (char, &str)> {
+        self.replacements
+            .iter()
+            .map(|(&radical, replacement)| (radical, replacement.as_str()))
+    }
+
+    /// The JIS X 0208 character that was replaced with the given glyph
+    ///
+    /// # Arguments
+    ///
+    /// * `replacement` - A glyph produced by the mapping
+    pub fn original(&self, replacement: &str) -> Option<char> {
+        self.originals.get(replacement).copied()
+    }
+
+    /// Creates a radical from the character used in the EDRDG files,
+    /// or `None` if it has no JIS X 0208 code
+    ///
+    /// # Arguments
+    ///
+    /// * `original` - The JIS X 0208 character used for the radical
+    pub fn radical(&self, original: char) -> Option<RadicalGlyph> {
+        jis_code(original).map(|jis| self.glyph(jis, original))
+    }
+
+    /// Creates a radical from the glyph it is displayed as,
+    /// or `None` if it has no JIS X 0208 code
+    ///
+    /// # Arguments
+    ///
+    /// * `display` - Either a replacement glyph or an unreplaced original character
+    pub fn from_display(&self, display: &str) -> Option<RadicalGlyph> {
+        let original = self.original(display).or_else(|| single_char(display))?;
+        Some(RadicalGlyph {
+            jis: jis_code(original)?,
+            original,
+            display: display.to_string(),
+        })
+    }
+
+    pub(crate) fn glyph(&self, jis: u16, original: char) -> RadicalGlyph {
+        RadicalGlyph {
+            jis,
+            original,
+            display: self
+                .get(original)
+                .map_or_else(|| original.to_string(), |s| s.to_string()),
+        }
+    }
+
+    /// Applies overrides from a UTF-8 file.
+    /// See [`RadicalMapping::load_overrides_str`] for the format.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path to the overrides file
+    pub fn load_overrides<P: AsRef<Path>>(&mut self, path: P) -> Result<(), MappingError> {
+        let text = std::fs::read_to_string(path)?;
+        self.load_overrides_str(&text)
+    }
+
+    /// Applies overrides, one per line. Each line holds a radical
+    /// followed by whitespace and its replacement, or a radical on its own
+    /// to keep it unchanged. Blank lines and lines starting with `#` are ignored.
+    /// A replacement may not be shared with another radical.
+    /// Nothing is applied unless every line is valid.
+    ///
+    /// ```text
+    /// # Use the WWWJDIC image glyph
+    /// 个 ㅅ
+    /// # Keep 并 as-is
+    /// 并
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The overrides
+    pub fn load_overrides_str(&mut self, text: &str) -> Result<(), MappingError> {
+        let mut mapping = self.clone();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let radical = fields.next().and_then(single_char);
+            let replacement = fields.next();
+            match (radical, replacement, fields.next()) {
+                (Some(radical), Some(replacement), None) => match mapping.original(replacement) {
+                    Some(other) if other != radical => {
+                        let replacement = replacement.to_string();
+                        return Err(MappingError::Duplicate(i + 1, replacement, other));
+                    }
+                    _ => {
+                        mapping.insert(radical, replacement);
+                    }
+                },
+                (Some(radical), None, None) => {
+                    mapping.remove(radical);
+                }
+                _ => return Err(MappingError::Parse(i + 1)),
+            }
+        }
+        *self = mapping;
+        Ok(())
+    }
+}
+
+impl Default for RadicalMapping {
+    fn default() -> Self {
+        Self::preset(Preset::JishoStyle)
+    }
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// Taken verbatim from kradfile lines 45-65
+const EDRDG_SUGGESTED: &[(char, &str)] = &[
+    ('化', "\u{2E85}"),
+    ('个', "\u{2F09}"),
+    ('刈', "\u{2E89}"),
+    ('込', "\u{2ECC}"),
+    ('尚', "\u{2E8C}"),
+    ('忙', "\u{2E96}"),
+    ('扎', "\u{2E97}"),
+    ('汁', "\u{2EA1}"),
+    ('犯', "\u{2EA8}"),
+    ('艾', "\u{2EBE}"),
+    ('邦', "\u{2ECF}"),
+    ('阡', "\u{2ED9}"),
+    ('老', "\u{2EB9}"),
+    ('杰', "\u{2EA3}"),
+    ('礼', "\u{2EAD}"),
+    ('疔', "\u{2F67}"),
+    ('禹', "\u{2F71}"),
+    ('初', "\u{2EC2}"),
+    ('買', "\u{2EB2}"),
+    ('滴', "\u{5547}"),
+];
+
+// Remappings adapted from kradfile lines 45-65
+const JISHO_STYLE: &[(char, &str)] = &[
+    // 化 -> ⺅
+    ('化', "\u{2E85}"),
+    // # D0 A4  2F09
+    // 个 -> ⼉
+    // Ignoring this one because it makes zero sense.
+    // Maybe the authors had a typo.
+    // ('个', "\u{2F09}"),
+    // This is the replacement used by Jisho.
+    // 个 -> 𠆢
+    ('个', "\u{201A2}"),
+    // # D6 F5  none available - upside-down A5 CF
+    // D6F5 -> 并
+    // A5CF -> ハ
+    // ('并', "\u{30CF}"),
+    // The authors suggest a vertically-flipped ハ
+    // like the Wanikani horns radical
+    // https://www.wanikani.com/radicals/horns
+    // I found an alternate glyph that isn't
+    // semantically a Japanese radical
+    // (it's a kwukyel ideograph)
+    // but it looks correct.
+    // 并 -> 丷
+    ('并', "\u{4E37}"),
+    // 刈 -> ⺉
+    ('刈', "\u{2E89}"),
+    // 込 -> ⻌
+    ('込', "\u{2ECC}"),
+    // 尚 -> ⺌
+    ('尚', "\u{2E8C}"),
+    // 忙 -> ⺖
+    ('忙', "\u{2E96}"),
+    // The suggested replacement is not correct.
+    // 扎 -> ⺗
+    // ('扎', "\u{2E97}"),
+    // This is what appears on the WWWJDIC server
+    // 扎 -> 扌
+    ('扎', "\u{624C}"),
+    // 汁 -> ⺡
+    ('汁', "\u{2EA1}"),
+    // 犯 -> ⺨
+    ('犯', "\u{2EA8}"),
+    // 艾 -> ⺾
+    ('艾', "\u{2EBE}"),
+    // 邦 -> ⻏
+    ('邦', "\u{2ECF}"),
+    // 阡 -> ⻙
+    // ('阡', "\u{2ED9}"),
+    // The above must have been another
+    // mistake because there's a way better choice.
+    // 阡 -> ⻖
+    ('阡', "\u{2ED6}"),
+    // 老 -> ⺹
+    ('老', "\u{2EB9}"),
+    // 杰 -> ⺣
+    ('杰', "\u{2EA3}"),
+    // 礼 -> ⺭
+    ('礼', "\u{2EAD}"),
+    // 疔 -> ⽧
+    ('疔', "\u{2F67}"),
+    // 禹 -> ⽱
+    ('禹', "\u{2F71}"),
+    // 初 -> ⻂
+    ('初', "\u{2EC2}"),
+    // 買 -> ⺲
+    ('買', "\u{2EB2}"),
+    // 滴 -> 啇
+    ('滴', "\u{5547}"),
+    // Adding another of my own not from the
+    // kradfile suggestions. This is the replacement
+    // used by Jisho.
+    // 乞 -> 𠂉
+    ('乞', "\u{20089}"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(mapping: &RadicalMapping, original: char) -> String {
+        mapping.radical(original).unwrap().display
+    }
+
+    #[test]
+    fn applies_preset() {
+        let mapping = RadicalMapping::default();
+        assert_eq!(display(&mapping, '化'), "\u{2E85}");
+        assert_eq!(display(&mapping, '个'), "\u{201A2}");
+        assert_eq!(display(&mapping, '一'), "一");
+        assert_eq!(mapping.original("\u{2E85}"), Some('化'));
+    }
+
+    #[test]
+    fn keeps_original_glyph() {
+        let mapping = RadicalMapping::default();
+        let radical = mapping.radical('化').unwrap();
+        assert_eq!(radical.jis, 0x323D);
+        assert_eq!(radical.original, '化');
+        assert_eq!(radical.display, "\u{2E85}");
+        assert!(radical.is_replaced());
+        assert_eq!(mapping.from_display("\u{2E85}"), Some(radical));
+
+        let radical = mapping.from_display("一").unwrap();
+        assert_eq!(radical.jis, 0x306C);
+        assert!(!radical.is_replaced());
+        assert_eq!(radical.to_string(), "一");
+    }
+
+    #[test]
+    fn raw_has_no_replacements() {
+        let mapping = RadicalMapping::preset(Preset::Raw);
+        assert_eq!(display(&mapping, '化'), "化");
+        assert_eq!(mapping.iter().count(), 0);
+    }
+
+    #[test]
+    fn edrdg_suggested_differs_from_jisho() {
+        let mapping = RadicalMapping::preset(Preset::EdrdgSuggested);
+        assert_eq!(display(&mapping, '个'), "\u{2F09}");
+        assert_eq!(display(&mapping, '扎'), "\u{2E97}");
+        assert_eq!(display(&mapping, '并'), "并");
+    }
+
+    #[test]
+    fn loads_overrides() {
+        let mut mapping = RadicalMapping::default();
+        let overrides = "# Comment\n\n个 ㅅ\n并\n  尚\t⺌  \n";
+        mapping.load_overrides_str(overrides).unwrap();
+        assert_eq!(mapping.get('个'), Some("ㅅ"));
+        assert_eq!(mapping.get('并'), None);
+        assert_eq!(mapping.get('尚'), Some("\u{2E8C}"));
+        assert_eq!(mapping.get('化'), Some("\u{2E85}"));
+    }
+
+    #[test]
+    fn rejects_invalid_overrides() {
+        let mut mapping = RadicalMapping::default();
+        let res = mapping.load_overrides_str("个 ㅅ\n化化 ⺅\n");
+        assert!(matches!(res, Err(MappingError::Parse(2))));
+        let res = mapping.load_overrides_str("个 ㅅ ⼉\n");
+        assert!(matches!(res, Err(MappingError::Parse(1))));
+        assert_eq!(mapping, RadicalMapping::default());
+    }
+
+    #[test]
+    fn rejects_duplicate_overrides() {
+        let mut mapping = RadicalMapping::default();
+        let res = mapping.load_overrides_str("# Comment\n刈 \u{2E85}\n");
+        assert!(matches!(
+            res,
+            Err(MappingError::Duplicate(2, replacement, '化')) if replacement == "\u{2E85}"
+        ));
+        mapping.load_overrides_str("化\n刈 \u{2E85}\n").unwrap();
+        assert_eq!(mapping.original("\u{2E85}"), Some('刈'));
+    }
+
+    #[test]
+    fn maps_replacements_back_to_one_radical() {
+        let mut mapping = RadicalMapping::preset(Preset::JishoStyle);
+        assert_eq!(mapping.iter().count(), JISHO_STYLE.len());
+        mapping.insert('刈', "\u{2E85}");
+        assert_eq!(mapping.original("\u{2E85}"), Some('刈'));
+        assert_eq!(mapping.get('化'), None);
+        assert_eq!(mapping.original("\u{2E89}"), None);
+        mapping.remove('刈');
+        assert_eq!(mapping.original("\u{2E85}"), None);
+    }
+}
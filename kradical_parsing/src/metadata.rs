@@ -0,0 +1,135 @@
+//! Comments and release information from the EDRDG files
+
+use std::fmt::{self, Display, Formatter};
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// A record along with the comment lines that precede it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Commented<T> {
+    /// The text of each comment line following the `#`
+    pub comments: Vec<String>,
+
+    /// The record
+    pub record: T,
+}
+
+/// The month and year of a release of an EDRDG file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Revision {
+    /// The year of the release
+    pub year: u16,
+
+    /// The one-based month of the release
+    pub month: u8,
+}
+
+impl Revision {
+    /// Finds the latest date mentioned in the comments, such as the
+    /// `Melbourne, Oct  2013` signature or an `Aug 2020 - ...` change log entry
+    ///
+    /// # Arguments
+    ///
+    /// * `comments` - The comment lines to search
+    pub fn from_comments<S: AsRef<str>>(comments: &[S]) -> Option<Self> {
+        comments
+            .iter()
+            .flat_map(|comment| dates(comment.as_ref()))
+            .max()
+    }
+}
+
+impl Display for Revision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+// Month names or abbreviations immediately followed by a four-digit year
+fn dates(comment: &str) -> Vec<Revision> {
+    let words: Vec<_> = comment
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '-')
+        .filter(|word| !word.is_empty())
+        .collect();
+    words
+        .windows(2)
+        .filter_map(|pair| {
+            let month = month(pair[0])?;
+            let year = pair[1];
+            if year.len() != 4 {
+                return None;
+            }
+            let year = year.parse().ok()?;
+            Some(Revision { year, month })
+        })
+        .collect()
+}
+
+// Full month names or abbreviations of at least three letters
+fn month(word: &str) -> Option<u8> {
+    let word = word.to_ascii_lowercase();
+    if word.len() < 3 {
+        return None;
+    }
+    MONTHS
+        .iter()
+        .position(|month| month.starts_with(&word))
+        .map(|i| i as u8 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_signature_date() {
+        let comments = [
+            " Jim Breen, Tokyo, January 2001",
+            "            Melbourne, Oct  2013",
+        ];
+        let revision = Revision::from_comments(&comments);
+        assert_eq!(
+            revision,
+            Some(Revision {
+                year: 2013,
+                month: 10
+            })
+        );
+    }
+
+    #[test]
+    fn finds_change_log_date() {
+        let comments = [
+            " Jun 2015 - added 乞 to 179 kanji as suggested by Ben Bullock",
+            " Aug 2020 - changed 夂 to 攵 for 徽, 務 and 霧",
+            " Sep 2007 - made sure all the 糸 indices also had 幺 and 小",
+        ];
+        let revision = Revision::from_comments(&comments).unwrap();
+        assert_eq!(revision.to_string(), "2020-08");
+    }
+
+    #[test]
+    fn ignores_other_words() {
+        let comments = [
+            " done in 1998/9 at the suggestion of",
+            " Mayhem 2021",
+            " May 20",
+        ];
+        assert_eq!(Revision::from_comments(&comments), None);
+    }
+}
@@ -0,0 +1,62 @@
+//! Options shared by the kradfile and radkfile parsers
+
+use crate::{diagnostic::Diagnostic, mapping::RadicalMapping};
+
+/// Controls how the parsers handle malformed records
+/// and which replacements they make for radicals
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Whether to skip records that fail to parse,
+    /// keeping a diagnostic for each, rather than failing outright
+    pub recover: bool,
+
+    /// The replacements for radicals without a JIS X 0208 character
+    pub mapping: RadicalMapping,
+}
+
+impl ParseOptions {
+    /// Options that skip over malformed records
+    pub fn lenient() -> Self {
+        Self {
+            recover: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// The records that parsed successfully
+/// along with diagnostics for any that were skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parsed<T> {
+    /// The successfully parsed records in file order
+    pub records: Vec<T>,
+
+    /// A diagnostic for each skipped record in file order
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Drains an iterator of parse results, either stopping at the first error
+/// or setting aside parse errors as diagnostics depending on the options
+pub(crate) fn collect<T, E, I, F>(
+    results: I,
+    options: &ParseOptions,
+    into_diagnostic: F,
+) -> Result<Parsed<T>, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+    F: Fn(E) -> Result<Diagnostic, E>,
+{
+    let mut parsed = Parsed {
+        records: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    for result in results {
+        match result {
+            Ok(record) => parsed.records.push(record),
+            Err(err) if options.recover => parsed.diagnostics.push(into_diagnostic(err)?),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(parsed)
+}
@@ -1,20 +1,44 @@
-//! Parser for `radkfile` and `radkfile2`.
-
-use crate::shared::{comments, decode_jis_radical};
+//! Parser for `radkfile`, `radkfile2`, and the combined `radkfilex`.
+//!
+//! The `parse_*` functions decompress gzipped input
+//! such as `radkfile.gz` when the `gzip` feature is enabled.
+
+#[cfg(feature = "json")]
+use crate::json;
+use crate::{
+    diagnostic::{context, Context, Diagnostic, Failure, ParseResult},
+    mapping::{RadicalGlyph, RadicalMapping},
+    metadata::{Commented, Revision},
+    options::{self, ParseOptions, Parsed},
+    shared::{
+        comment_text, comments, decode_jis_radical, decompress, encode_comment, encode_eucjp,
+        encode_jis212, encode_jis_radical, is_comment_or_blank, open_file, read_line,
+    },
+    utf8,
+};
 use encoding::{codec::japanese::EUCJPEncoding, DecoderTrap, Encoding};
 use kradical_jis::jis212_to_utf8;
 use nom::{
     branch::alt,
     bytes::complete::{tag, take, take_while, take_while1, take_while_m_n},
-    character::{complete::space0, is_alphanumeric, is_digit},
-    combinator::{eof, map, map_res, success, value},
-    multi::many_till,
+    character::{
+        complete::{multispace1, space0},
+        is_alphanumeric, is_digit,
+    },
+    combinator::{eof, map, map_res, peek, success, value},
+    error::{ErrorKind, ParseError},
+    multi::fold_many0,
     sequence::{pair, separated_pair, terminated, tuple},
-    IResult,
+    Err,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, Read, Write},
+    iter,
+    path::Path,
+    string::FromUtf8Error,
 };
-use std::{path::Path, string::FromUtf8Error};
 use thiserror::Error;
-use unicode_segmentation::UnicodeSegmentation;
 
 #[cfg(test)]
 mod tests;
@@ -34,20 +58,35 @@ pub enum RadkError {
     #[error("Invalid kanji line")]
     EucJp,
 
-    /// Error while parsing kradfile
-    #[error("Error while parsing kradfile")]
-    Parse,
+    /// Error while parsing radkfile
+    #[error("Error while parsing radkfile: {0}")]
+    Parse(Diagnostic),
 
-    /// Error while reading kradfile
-    #[error("Error while reading kradfile")]
+    /// Error while reading or writing radkfile
+    #[error("Error while reading or writing radkfile")]
     Io(#[from] std::io::Error),
+
+    /// A character could not be represented in the radkfile encoding
+    #[error("No JIS encoding for {0}")]
+    Encode(String),
+
+    /// A radical glyph could not be matched with a JIS X 0208 character
+    #[error("No JIS X 0208 character for radical {0}")]
+    Radical(String),
+
+    /// Error while parsing JSON
+    #[cfg(feature = "json")]
+    #[error("Error while parsing JSON")]
+    Json(#[from] serde_json::Error),
 }
 
 /// Information about a kanji radical
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Radical {
     /// The UTF-8 character most closely matching the radical
-    pub glyph: String,
+    /// along with the original JIS character
+    pub glyph: RadicalGlyph,
 
     /// The number of strokes used to draw the radical
     pub strokes: u8,
@@ -58,16 +97,26 @@ pub struct Radical {
 
 /// Describes which kanji a given radical belongs to
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Membership {
     /// The radical
     pub radical: Radical,
 
-    /// The kanji containing the radical
+    /// The kanji containing the radical,
+    /// which are never replaced by the radical mapping
     pub kanji: Vec<String>,
 }
 
 /// Alternate representations for a radical other than the UTF-8 glyph
+///
+/// With the `serde` feature, this is tagged as `{"type": "image", "value": "js02"}`,
+/// `{"type": "glyph", "value": "忄"}`, or `{"type": "none"}`.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
 pub enum Alternate {
     /// The name of an image from the WWWJDIC website
     Image(String),
@@ -79,9 +128,119 @@ pub enum Alternate {
     None,
 }
 
+impl Alternate {
+    /// The Unicode glyph that best depicts the radical,
+    /// looking up image names in a built-in table
+    pub fn resolve(&self) -> Option<&str> {
+        match self {
+            Alternate::Image(name) => resolve_image(name),
+            Alternate::Glyph(glyph) => Some(glyph),
+            Alternate::None => None,
+        }
+    }
+}
+
+/// The Unicode glyph for an image from the WWWJDIC website,
+/// if it is one of the images used by the EDRDG radkfiles
+///
+/// # Arguments
+///
+/// * `name` - The name of the image, such as `js01`
+pub fn resolve_image(name: &str) -> Option<&'static str> {
+    IMAGE_GLYPHS
+        .iter()
+        .find(|(image, _)| *image == name)
+        .map(|(_, glyph)| *glyph)
+}
+
+// The images are at http://nihongo.monash.edu/gif212/<name>.png
+const IMAGE_GLYPHS: &[(&str, &str)] = &[
+    // 化, keeping the left part
+    ("js01", "\u{2E85}"),
+    // 个, keeping the top part
+    // https://www.wanikani.com/radicals/hat
+    // Possible alternatives: ^ へ ヘ ㅅ 𠆢
+    ("js02", "\u{201A2}"),
+    // 艾, keeping the top part
+    // https://www.wanikani.com/radicals/flowers
+    ("js03", "\u{8279}"),
+    // 尚, keeping the horns at the top
+    // https://www.wanikani.com/radicals/triceratops
+    ("js04", "\u{2E8C}"),
+    // 老, keeping the swoosh and above
+    // https://www.wanikani.com/radicals/coffin
+    ("js05", "\u{8002}"),
+    // 并, keeping the horns
+    // https://www.wanikani.com/radicals/horns
+    ("js07", "\u{4E37}"),
+    // 乞, keeping the top part
+    // https://www.wanikani.com/radicals/gun
+    // Possible alternatives: ⟝ 𠂉
+    ("js10", "\u{20089}"),
+    // 阡, keeping the left part
+    // https://www.wanikani.com/radicals/building
+    ("kozatoL", "\u{2ED6}"),
+    // 邦, keeping the right part
+    // https://www.wanikani.com/radicals/building
+    ("kozatoR", "\u{2ECF}"),
+];
+
+/// The contents of a radkfile, radkfile2, or radkfilex including its comments
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RadkFile {
+    /// The comment lines before the first membership
+    pub header: Vec<String>,
+
+    /// The latest date mentioned in the header,
+    /// which identifies the release of the file
+    pub revision: Option<Revision>,
+
+    /// The memberships along with any comments between them
+    pub memberships: Vec<Commented<Membership>>,
+
+    /// The comment lines after the last membership
+    pub footer: Vec<String>,
+
+    /// A diagnostic for each membership skipped while recovering
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl RadkFile {
+    /// Whether the header has the title of a radkfilex,
+    /// as written by [`merge_files`]
+    pub fn is_radkfilex(&self) -> bool {
+        self.header
+            .iter()
+            .any(|comment| is_radkfilex_title(comment))
+    }
+}
+
+// The number of kanji on each full line following an ident line
+const KANJI_PER_LINE: usize = 36;
+
+// Spaced out and centered like the titles of radkfile and radkfile2
+const RADKFILEX_TITLE: &str = "R A D K F I L E X";
+
+// The comment lines introducing a radkfilex, before the headers of the files it combines
+const RADKFILEX_HEADER: &[&str] = &[
+    "",
+    "                           R A D K F I L E X",
+    "",
+    " The radkfile and radkfile2 combined. Each element lists the JIS X 0208",
+    " kanji of the radkfile in JIS order, followed by the JIS X 0212 kanji",
+    " of radkfile2 in JIS order.",
+    "",
+];
+
+// Whether the text of a header comment is the radkfilex title
+pub(crate) fn is_radkfilex_title(comment: &str) -> bool {
+    comment.trim() == RADKFILEX_TITLE
+}
+
 type RadkResult = Result<Vec<Membership>, RadkError>;
 
-/// Parses a radkfile or radkfile2 and returns
+/// Parses a radkfile, radkfile2, or radkfilex and returns
 /// the list of kanji radical memberships
 ///
 /// # Arguments
@@ -93,74 +252,660 @@ pub fn parse_file<P: AsRef<Path>>(path: P) -> RadkResult {
 
 // Monomorphisation bloat avoidal splitting
 fn parse_file_implementation(path: &Path) -> RadkResult {
-    std::fs::read(path)
-        .map_err(|err| err.into())
-        .and_then(|b| parse_bytes(&b))
+    Memberships::new(open_file(path)?).collect()
 }
 
-/// Parses the contents of a radkfile or radkfile2 and returns
+/// Parses the contents of a radkfile, radkfile2, or radkfilex and returns
 /// the list of kanji radical memberships
 ///
 /// # Arguments
 ///
 /// * `b` - The bytes to parse
 pub fn parse_bytes(b: &[u8]) -> RadkResult {
-    lines(b).map(|(_i, o)| o).map_err(|_err| RadkError::Parse)
+    Memberships::new(decompress(b)?).collect()
+}
+
+/// Parses a radkfile, radkfile2, or radkfilex according to the given options,
+/// returning the decoded records along with diagnostics
+/// for any records that were skipped
+///
+/// # Arguments
+///
+/// * `path` - A path to the radkfile
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<Parsed<Membership>, RadkError> {
+    parse_file_with_options_implementation(path.as_ref(), options)
+}
+
+// Monomorphisation bloat avoidal splitting
+fn parse_file_with_options_implementation(
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<Parsed<Membership>, RadkError> {
+    let memberships = Memberships::with_mapping(open_file(path)?, options.mapping.clone());
+    options::collect(memberships, options, into_diagnostic)
+}
+
+/// Parses the contents of a radkfile, radkfile2, or radkfilex according to the given options,
+/// returning the decoded records along with diagnostics
+/// for any records that were skipped
+///
+/// # Arguments
+///
+/// * `b` - The bytes to parse
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_bytes_with_options(
+    b: &[u8],
+    options: &ParseOptions,
+) -> Result<Parsed<Membership>, RadkError> {
+    let memberships = Memberships::with_mapping(decompress(b)?, options.mapping.clone());
+    options::collect(memberships, options, into_diagnostic)
+}
+
+fn into_diagnostic(err: RadkError) -> Result<Diagnostic, RadkError> {
+    match err {
+        RadkError::Parse(diagnostic) => Ok(diagnostic),
+        err => Err(err),
+    }
+}
+
+/// An iterator over the memberships in a radkfile, radkfile2, or radkfilex
+/// that only reads as many lines as needed to produce the next one
+///
+/// After a radical fails to parse, iteration continues from the next ident line.
+pub struct Memberships<R> {
+    reader: R,
+    mapping: RadicalMapping,
+    offset: usize,
+    line: usize,
+
+    // The first line of the next record,
+    // read while looking for the end of the current one
+    pending: Option<Vec<u8>>,
+
+    // Comments after the last membership
+    trailing_comments: Vec<String>,
+    // Comments before memberships that failed to parse,
+    // carried over to the next one
+    skipped_comments: Vec<String>,
 }
 
-fn lines(b: &[u8]) -> IResult<&[u8], Vec<Membership>> {
-    map(many_till(kanji, eof), |(kanji, _)| kanji)(b)
+impl<R: BufRead> Memberships<R> {
+    /// Creates an iterator over the memberships read from `reader`
+    /// using the default radical replacements
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the radkfile contents
+    pub fn new(reader: R) -> Self {
+        Self::with_mapping(reader, RadicalMapping::default())
+    }
+
+    /// Creates an iterator over the memberships read from `reader`
+    /// using the given radical replacements
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the radkfile contents
+    /// * `mapping` - The replacements for radicals without a JIS X 0208 character
+    pub fn with_mapping(reader: R, mapping: RadicalMapping) -> Self {
+        Self {
+            reader,
+            mapping,
+            offset: 0,
+            line: 0,
+            pending: None,
+            trailing_comments: Vec::new(),
+            skipped_comments: Vec::new(),
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<Vec<u8>>, RadkError> {
+        match self.pending.take() {
+            Some(line) => Ok(Some(line)),
+            None => Ok(read_line(&mut self.reader)?),
+        }
+    }
+
+    // Reads any comments, the ident line,
+    // and the kanji lines that follow it
+    fn next_record(&mut self) -> Result<Option<Commented<Vec<u8>>>, RadkError> {
+        let mut comments = Vec::new();
+        let mut record = Vec::new();
+        let mut has_ident = false;
+        let mut has_stray_kanji = false;
+        while let Some(line) = self.next_line()? {
+            let is_ident = line.starts_with(b"$");
+            if has_ident && (is_ident || line.starts_with(b"#")) {
+                self.pending = Some(line);
+                break;
+            }
+            record.extend_from_slice(&line);
+            if !has_ident {
+                comments.extend(comment_text(&line));
+            }
+            if is_ident {
+                has_ident = true;
+            } else if !has_ident && !is_comment_or_blank(&line) {
+                // Kanji without a radical, left for the parser to report
+                has_stray_kanji = true;
+                break;
+            }
+        }
+
+        if has_ident || has_stray_kanji {
+            Ok(Some(Commented { comments, record }))
+        } else {
+            self.trailing_comments = comments;
+            Ok(None)
+        }
+    }
+
+    fn next_commented(&mut self) -> Option<Result<Commented<Membership>, RadkError>> {
+        let Commented { comments, record } = match self.next_record() {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                self.skipped_comments.append(&mut self.trailing_comments);
+                self.trailing_comments = std::mem::take(&mut self.skipped_comments);
+                return None;
+            }
+            Err(err) => return Some(Err(err)),
+        };
+        let (offset, line) = (self.offset, self.line);
+        self.offset += record.len();
+        self.line += record.iter().filter(|&&b| b == b'\n').count();
+        let parsed = match kanji(&self.mapping)(&record) {
+            Ok((_i, record)) => {
+                let mut skipped = std::mem::take(&mut self.skipped_comments);
+                skipped.extend(comments);
+                Ok(Commented {
+                    comments: skipped,
+                    record,
+                })
+            }
+            Err(err) => {
+                self.skipped_comments.extend(comments);
+                let diagnostic = Diagnostic::from_err(&record, err).offset_by(offset, line);
+                Err(RadkError::Parse(diagnostic))
+            }
+        };
+        Some(parsed)
+    }
+}
+
+impl<R: BufRead> Iterator for Memberships<R> {
+    type Item = Result<Membership, RadkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_commented()
+            .map(|parsed| parsed.map(|commented| commented.record))
+    }
+}
+
+/// Parses a radkfile, radkfile2, or radkfilex, keeping its comments
+/// and identifying its revision
+///
+/// # Arguments
+///
+/// * `path` - A path to the radkfile
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_file_with_comments<P: AsRef<Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<RadkFile, RadkError> {
+    parse_file_with_comments_implementation(path.as_ref(), options)
+}
+
+// Monomorphisation bloat avoidal splitting
+fn parse_file_with_comments_implementation(
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<RadkFile, RadkError> {
+    let memberships = Memberships::with_mapping(open_file(path)?, options.mapping.clone());
+    parse_with_comments(memberships, options)
+}
+
+/// Parses the contents of a radkfile, radkfile2, or radkfilex, keeping its comments
+/// and identifying its revision
+///
+/// # Arguments
+///
+/// * `b` - The bytes to parse
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_bytes_with_comments(b: &[u8], options: &ParseOptions) -> Result<RadkFile, RadkError> {
+    let memberships = Memberships::with_mapping(decompress(b)?, options.mapping.clone());
+    parse_with_comments(memberships, options)
+}
+
+fn parse_with_comments<R: BufRead>(
+    mut memberships: Memberships<R>,
+    options: &ParseOptions,
+) -> Result<RadkFile, RadkError> {
+    let Parsed {
+        mut records,
+        diagnostics,
+    } = options::collect(
+        iter::from_fn(|| memberships.next_commented()),
+        options,
+        into_diagnostic,
+    )?;
+    // Everything before the first record belongs to the file as a whole
+    let header = match records.first_mut() {
+        Some(first) => std::mem::take(&mut first.comments),
+        None => std::mem::take(&mut memberships.trailing_comments),
+    };
+    Ok(RadkFile {
+        revision: Revision::from_comments(&header),
+        header,
+        memberships: records,
+        footer: memberships.trailing_comments,
+        diagnostics,
+    })
+}
+
+/// Parses the UTF-8 text written by the converter,
+/// with one `radical strokes [alternate] : kanji kanji ...` line per membership.
+/// Radicals are matched against their display glyphs
+/// using the default radical replacements.
+///
+/// # Arguments
+///
+/// * `text` - The text to parse
+pub fn parse_utf8(text: &str) -> RadkResult {
+    utf8_memberships(text, &RadicalMapping::default()).collect()
+}
+
+/// Parses a UTF-8 text file written by the converter
+///
+/// # Arguments
+///
+/// * `path` - A path to the text file
+pub fn parse_utf8_file<P: AsRef<Path>>(path: P) -> RadkResult {
+    parse_utf8_file_implementation(path.as_ref())
+}
+
+// Monomorphisation bloat avoidal splitting
+fn parse_utf8_file_implementation(path: &Path) -> RadkResult {
+    let mut text = String::new();
+    open_file(path)?.read_to_string(&mut text)?;
+    parse_utf8(&text)
 }
 
-fn kanji(b: &[u8]) -> IResult<&[u8], Membership> {
+/// Parses the UTF-8 text written by the converter according to the given options,
+/// returning the decoded records along with diagnostics
+/// for any lines that were skipped
+///
+/// # Arguments
+///
+/// * `text` - The text to parse
+/// * `options` - Controls radical replacements and the handling of malformed records
+pub fn parse_utf8_with_options(
+    text: &str,
+    options: &ParseOptions,
+) -> Result<Parsed<Membership>, RadkError> {
+    let memberships = utf8_memberships(text, &options.mapping);
+    options::collect(memberships, options, into_diagnostic)
+}
+
+fn utf8_memberships<'a>(
+    text: &'a str,
+    mapping: &'a RadicalMapping,
+) -> impl Iterator<Item = Result<Membership, RadkError>> + 'a {
+    utf8::lines(text).map(move |line| {
+        utf8_membership(line.text, mapping)
+            .map_err(|failure| RadkError::Parse(utf8::diagnostic(text, &line, failure)))
+    })
+}
+
+fn utf8_membership(line: &str, mapping: &RadicalMapping) -> Result<Membership, (usize, Context)> {
+    let (ident, _, kanji) = utf8::split_separator(line)?;
+    let mut tokens = utf8::tokens(ident);
+    let glyph = match tokens.next() {
+        Some((offset, radical)) => mapping
+            .from_display(radical)
+            .ok_or((offset, Context::Radical))?,
+        None => return Err((0, Context::Radical)),
+    };
+    let strokes = match tokens.next() {
+        Some((offset, strokes)) => strokes.parse().map_err(|_| (offset, Context::Strokes))?,
+        None => return Err((ident.len(), Context::Strokes)),
+    };
+    let alternate = match tokens.next() {
+        Some((_, image)) if image.bytes().all(|b| b.is_ascii_alphanumeric()) => {
+            Alternate::Image(image.to_string())
+        }
+        Some((_, glyph)) => Alternate::Glyph(glyph.to_string()),
+        None => Alternate::None,
+    };
+    if let Some((offset, _)) = tokens.next() {
+        return Err((offset, Context::Alternate));
+    }
+    Ok(Membership {
+        radical: Radical {
+            glyph,
+            strokes,
+            alternate,
+        },
+        kanji: utf8::tokens(kanji)
+            .map(|(_, kanji)| kanji.to_string())
+            .collect(),
+    })
+}
+
+/// Parses the JSON written by the converter in any [`json::Style`],
+/// matching radicals against their display glyphs
+/// using the default radical replacements
+///
+/// # Arguments
+///
+/// * `text` - The JSON to parse
+#[cfg(feature = "json")]
+pub fn parse_json(text: &str) -> RadkResult {
+    parse_json_with_mapping(text, &RadicalMapping::default())
+}
+
+/// Parses a JSON file written by the converter
+///
+/// # Arguments
+///
+/// * `path` - A path to the JSON file
+#[cfg(feature = "json")]
+pub fn parse_json_file<P: AsRef<Path>>(path: P) -> RadkResult {
+    parse_json_file_implementation(path.as_ref())
+}
+
+// Monomorphisation bloat avoidal splitting
+#[cfg(feature = "json")]
+fn parse_json_file_implementation(path: &Path) -> RadkResult {
+    let mut text = String::new();
+    open_file(path)?.read_to_string(&mut text)?;
+    parse_json(&text)
+}
+
+/// Parses the JSON written by the converter,
+/// matching radicals against their display glyphs using the given replacements
+///
+/// # Arguments
+///
+/// * `text` - The JSON to parse
+/// * `mapping` - The replacements that were used to write the JSON
+#[cfg(feature = "json")]
+pub fn parse_json_with_mapping(text: &str, mapping: &RadicalMapping) -> RadkResult {
+    let memberships: Vec<json::Membership> = json::read(text)?;
+    memberships
+        .into_iter()
+        .map(|membership| {
+            let glyph = mapping
+                .from_display(&membership.radical)
+                .ok_or_else(|| RadkError::Radical(membership.radical.clone()))?;
+            Ok(Membership {
+                radical: Radical {
+                    glyph,
+                    strokes: membership.strokes,
+                    alternate: membership.alternate,
+                },
+                kanji: membership.kanji,
+            })
+        })
+        .collect()
+}
+
+/// Combines the memberships of several radkfiles, such as radkfile and radkfile2,
+/// into those of a radkfilex. Each radical has a single membership,
+/// with the radicals in stroke order and otherwise in the order they first appear.
+/// The kanji of each radical are in JIS order, with the JIS X 0208 kanji
+/// before the JIS X 0212 kanji.
+///
+/// # Arguments
+///
+/// * `memberships` - The memberships of each file in turn
+pub fn merge<I: IntoIterator<Item = Membership>>(memberships: I) -> Vec<Membership> {
+    let commented = memberships.into_iter().map(|record| Commented {
+        comments: Vec::new(),
+        record,
+    });
+    merge_commented(commented)
+        .into_iter()
+        .map(|commented| commented.record)
+        .collect()
+}
+
+/// Combines several radkfiles, such as radkfile and radkfile2, into a radkfilex.
+/// The header is the radkfilex title followed by the header of each file in turn,
+/// and the memberships are combined as with [`merge`].
+/// The footers and diagnostics of the files are kept in turn.
+///
+/// # Arguments
+///
+/// * `files` - The files to combine
+pub fn merge_files(files: &[RadkFile]) -> RadkFile {
+    let mut header: Vec<_> = RADKFILEX_HEADER.iter().map(|s| s.to_string()).collect();
+    for file in files {
+        header.extend(file.header.iter().cloned());
+    }
+    let memberships = files
+        .iter()
+        .flat_map(|file| file.memberships.iter().cloned());
+    RadkFile {
+        header,
+        revision: files.iter().filter_map(|file| file.revision).max(),
+        memberships: merge_commented(memberships),
+        footer: files.iter().flat_map(|file| file.footer.clone()).collect(),
+        diagnostics: files
+            .iter()
+            .flat_map(|file| file.diagnostics.clone())
+            .collect(),
+    }
+}
+
+// Two-byte JIS X 0208 kanji come before three-byte JIS X 0212 kanji,
+// then either kind is in the order of its code
+fn radkfilex_order(kanji: &str) -> (usize, Vec<u8>) {
+    match encode_eucjp(kanji) {
+        Some(code) => (code.len(), code),
+        None => (usize::MAX, Vec::new()),
+    }
+}
+
+// Memberships are matched by the JIS code of their radical
+// and keep the comments and ident line of their first appearance
+fn merge_commented<I>(memberships: I) -> Vec<Commented<Membership>>
+where
+    I: IntoIterator<Item = Commented<Membership>>,
+{
+    let mut merged: Vec<Commented<Membership>> = Vec::new();
+    let mut seen: Vec<HashSet<String>> = Vec::new();
+    let mut indices = HashMap::new();
+    for commented in memberships {
+        let Commented {
+            comments,
+            record: Membership { radical, kanji },
+        } = commented;
+        let i = match indices.get(&radical.glyph.jis) {
+            Some(&i) => i,
+            None => {
+                indices.insert(radical.glyph.jis, merged.len());
+                let record = Membership {
+                    radical,
+                    kanji: Vec::new(),
+                };
+                merged.push(Commented { comments, record });
+                seen.push(HashSet::new());
+                merged.len() - 1
+            }
+        };
+        for kanji in kanji {
+            if seen[i].insert(kanji.clone()) {
+                merged[i].record.kanji.push(kanji);
+            }
+        }
+    }
+    merged.sort_by_key(|commented| commented.record.radical.strokes);
+    for commented in &mut merged {
+        commented
+            .record
+            .kanji
+            .sort_by_cached_key(|kanji| radkfilex_order(kanji));
+    }
+    merged
+}
+
+/// Writes memberships in the radkfile format.
+/// Radicals are written as their original JIS characters
+/// and the kanji are wrapped the same way as in the EDRDG files.
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the radkfile contents
+/// * `memberships` - The memberships to write
+pub fn write_radkfile<W: Write>(
+    mut writer: W,
+    memberships: &[Membership],
+) -> Result<(), RadkError> {
+    write_radkfile_implementation(&mut writer, memberships)
+}
+
+// Monomorphisation bloat avoidal splitting
+fn write_radkfile_implementation(
+    writer: &mut dyn Write,
+    memberships: &[Membership],
+) -> Result<(), RadkError> {
+    for membership in memberships {
+        writer.write_all(&membership_bytes(membership)?)?;
+    }
+    Ok(())
+}
+
+/// Writes a radkfile including its comments,
+/// reproducing the original file for unmodified contents
+///
+/// # Arguments
+///
+/// * `writer` - The destination for the radkfile contents
+/// * `file` - The radkfile contents to write
+pub fn write_radkfile_with_comments<W: Write>(
+    mut writer: W,
+    file: &RadkFile,
+) -> Result<(), RadkError> {
+    write_radkfile_with_comments_implementation(&mut writer, file)
+}
+
+// Monomorphisation bloat avoidal splitting
+fn write_radkfile_with_comments_implementation(
+    writer: &mut dyn Write,
+    file: &RadkFile,
+) -> Result<(), RadkError> {
+    write_comments(writer, &file.header)?;
+    for commented in &file.memberships {
+        write_comments(writer, &commented.comments)?;
+        writer.write_all(&membership_bytes(&commented.record)?)?;
+    }
+    write_comments(writer, &file.footer)
+}
+
+fn write_comments(writer: &mut dyn Write, comments: &[String]) -> Result<(), RadkError> {
+    for comment in comments {
+        writer.write_all(&encode(comment, encode_comment)?)?;
+    }
+    Ok(())
+}
+
+fn membership_bytes(membership: &Membership) -> Result<Vec<u8>, RadkError> {
+    let radical = &membership.radical;
+    let mut lines = b"$ ".to_vec();
+    lines.extend(encode_jis_radical(&radical.glyph));
+    lines.extend(format!(" {}", radical.strokes).bytes());
+    match &radical.alternate {
+        Alternate::Image(name) => lines.extend(format!(" {}", name).bytes()),
+        Alternate::Glyph(glyph) => {
+            let code = encode_jis212(glyph).ok_or_else(|| RadkError::Encode(glyph.clone()))?;
+            lines.extend(format!(" {:04X}", code).bytes());
+        }
+        Alternate::None => {}
+    }
+    lines.push(b'\n');
+    for chunk in membership.kanji.chunks(KANJI_PER_LINE) {
+        for kanji in chunk {
+            lines.extend(encode(kanji, encode_eucjp)?);
+        }
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+fn encode(glyph: &str, encoder: fn(&str) -> Option<Vec<u8>>) -> Result<Vec<u8>, RadkError> {
+    encoder(glyph).ok_or_else(|| RadkError::Encode(glyph.to_string()))
+}
+
+fn kanji<'a>(mapping: &'a RadicalMapping) -> impl FnMut(&'a [u8]) -> ParseResult<'a, Membership> {
     map(
-        pair(comments, separated_pair(ident_line, tag("\n"), kanji_lines)),
+        pair(
+            comments,
+            separated_pair(
+                ident_line(mapping),
+                context(Context::IdentLine, tag("\n")),
+                kanji_lines,
+            ),
+        ),
         |(_, (ident, kanji))| Membership {
             radical: ident,
             kanji,
         },
-    )(b)
+    )
 }
 
-fn kanji_lines(b: &[u8]) -> IResult<&[u8], Vec<String>> {
-    map_res(take_while(is_eucjp_or_space), from_kanji_line)(b)
+fn kanji_lines(b: &[u8]) -> ParseResult<'_, Vec<String>> {
+    terminated(
+        fold_many0(
+            alt((value(None, multispace1), map(kanji_glyph, Some))),
+            Vec::new(),
+            |mut kanji, glyph| {
+                kanji.extend(glyph);
+                kanji
+            },
+        ),
+        context(Context::Kanji, peek(alt((tag("$"), tag("#"), eof)))),
+    )(b)
 }
 
-fn is_eucjp_or_space(b: u8) -> bool {
-    b.is_ascii_whitespace() || !b.is_ascii()
+// A single EUC-JP character, which takes three bytes for JIS X 0212.
+// Invalid characters stop the kanji lines where they begin.
+fn kanji_glyph(b: &[u8]) -> ParseResult<'_, String> {
+    let length = match b.first() {
+        Some(0x8F) => 3usize,
+        Some(lead) if !lead.is_ascii() => 2,
+        _ => return Err(Err::Error(Failure::from_error_kind(b, ErrorKind::Verify))),
+    };
+    map_res(take(length), |b| {
+        EUCJPEncoding.decode(b, DecoderTrap::Strict)
+    })(b)
 }
 
-fn from_kanji_line(b: &[u8]) -> Result<Vec<String>, RadkError> {
-    Ok(EUCJPEncoding
-        .decode(b, DecoderTrap::Replace)
-        .map_err(|_err| RadkError::EucJp)?
-        .graphemes(true)
-        .filter_map(|s| {
-            if s.chars().take(1).any(|c| c.is_ascii_whitespace()) && s.chars().count() == 1 {
-                None
-            } else {
-                Some(s.into())
-            }
-        })
-        .collect())
-}
-
-fn ident_line(b: &[u8]) -> IResult<&[u8], Radical> {
+fn ident_line<'a>(mapping: &'a RadicalMapping) -> impl FnMut(&'a [u8]) -> ParseResult<'a, Radical> {
     map(
-        tuple((ident_line_token, radical, strokes, alternate)),
+        terminated(
+            tuple((ident_line_token, radical(mapping), strokes, alternate)),
+            context(Context::Alternate, peek(alt((tag("\n"), eof)))),
+        ),
         |(_, radical, strokes, alternate)| Radical {
             glyph: radical,
             strokes,
             alternate,
         },
-    )(b)
+    )
 }
 
-fn alternate(b: &[u8]) -> IResult<&[u8], Alternate> {
-    alt((hex, image, success(Alternate::None)))(b)
+fn alternate(b: &[u8]) -> ParseResult<'_, Alternate> {
+    context(
+        Context::Alternate,
+        alt((hex, image, success(Alternate::None))),
+    )(b)
 }
 
-fn image(b: &[u8]) -> IResult<&[u8], Alternate> {
+fn image(b: &[u8]) -> ParseResult<'_, Alternate> {
     map_res(take_while1(is_alphanumeric), from_image)(b)
 }
 
@@ -168,13 +913,13 @@ fn from_image(b: &[u8]) -> Result<Alternate, FromUtf8Error> {
     String::from_utf8(b.into()).map(Alternate::Image)
 }
 
-fn hex(b: &[u8]) -> IResult<&[u8], Alternate> {
+fn hex(b: &[u8]) -> ParseResult<'_, Alternate> {
     map_res(take_while_m_n(4, 4, is_hex_digit), from_hex)(b)
 }
 
 fn from_hex(b: &[u8]) -> Result<Alternate, RadkError> {
     let s = std::str::from_utf8(b).map_err(|_| RadkError::NotGlyph)?;
-    let code = u16::from_str_radix(&s, 16).map_err(|_| RadkError::NotGlyph)?;
+    let code = u16::from_str_radix(s, 16).map_err(|_| RadkError::NotGlyph)?;
     jis212_to_utf8(code)
         .ok_or(RadkError::NotGlyph)
         .map(|s| Alternate::Glyph(s.to_string()))
@@ -182,19 +927,33 @@ fn from_hex(b: &[u8]) -> Result<Alternate, RadkError> {
 
 fn is_hex_digit(b: u8) -> bool {
     let c = b as char;
-    (c.is_ascii_uppercase() || c.is_ascii_digit()) && c.is_digit(16)
+    (c.is_ascii_uppercase() || c.is_ascii_digit()) && c.is_ascii_hexdigit()
 }
 
-fn ident_line_token(b: &[u8]) -> IResult<&[u8], ()> {
-    terminated(value((), tag("$")), space0)(b)
+fn ident_line_token(b: &[u8]) -> ParseResult<'_, ()> {
+    context(Context::IdentLine, terminated(value((), tag("$")), space0))(b)
 }
 
-fn radical(b: &[u8]) -> IResult<&[u8], String> {
-    terminated(map_res(take(2u8), decode_jis_radical), space0)(b)
+fn radical<'a>(
+    mapping: &'a RadicalMapping,
+) -> impl FnMut(&'a [u8]) -> ParseResult<'a, RadicalGlyph> {
+    terminated(
+        context(
+            Context::Radical,
+            map_res(take(2u8), move |b| decode_jis_radical(b, mapping)),
+        ),
+        space0,
+    )
 }
 
-fn strokes(b: &[u8]) -> IResult<&[u8], u8> {
-    terminated(map_res(take_while(is_digit), parse_number), space0)(b)
+fn strokes(b: &[u8]) -> ParseResult<'_, u8> {
+    terminated(
+        context(
+            Context::Strokes,
+            map_res(take_while(is_digit), parse_number),
+        ),
+        space0,
+    )(b)
 }
 
 fn parse_number(b: &[u8]) -> Result<u8, RadkError> {
@@ -1,9 +1,24 @@
-use super::{Alternate, Membership, Radical};
-use crate::test_constants::{COMMENT_LINE, EMPTY};
+use super::{Alternate, Membership, Memberships, Radical, RadkError};
+use crate::test_constants::{without_header, COMMENT_LINE, EMPTY};
+use crate::{
+    diagnostic::{Context, Diagnostic},
+    mapping::{Preset, RadicalGlyph, RadicalMapping},
+    metadata::Revision,
+    options::ParseOptions,
+    shared::encode_jis_kanji,
+};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+};
+
+fn glyph(display: &str) -> RadicalGlyph {
+    RadicalMapping::default().from_display(display).unwrap()
+}
 
 fn parsed_radical_simple() -> Radical {
     Radical {
-        glyph: "一".to_string(),
+        glyph: glyph("一"),
         strokes: 1,
         alternate: Alternate::None,
     }
@@ -22,13 +37,15 @@ const IDENT_LINE_SIMPLE: &[u8] = &[0x24, 0x20, 0xB0, 0xEC, 0x20, 0x31];
 #[test]
 fn radical() {
     let radical_and_space = &IDENT_LINE_SIMPLE[2..];
-    let res = super::radical(radical_and_space);
-    assert_eq!(res, Ok((&IDENT_LINE_SIMPLE[5..], "一".to_string())))
+    let mapping = RadicalMapping::default();
+    let res = super::radical(&mapping)(radical_and_space);
+    assert_eq!(res, Ok((&IDENT_LINE_SIMPLE[5..], glyph("一"))))
 }
 
 #[test]
 fn simple_ident_line() {
-    let res = super::ident_line(IDENT_LINE_SIMPLE);
+    let mapping = RadicalMapping::default();
+    let res = super::ident_line(&mapping)(IDENT_LINE_SIMPLE);
     assert_eq!(res, Ok((EMPTY, parsed_radical_simple())));
 }
 
@@ -68,13 +85,14 @@ fn image_ident_line() {
     const IDENT_LINE_FULL_IMG: &[u8] = &[
         0x24, 0x20, 0xD0, 0xA4, 0x20, 0x32, 0x20, 0x6A, 0x73, 0x30, 0x32,
     ];
-    let res = super::ident_line(IDENT_LINE_FULL_IMG);
+    let mapping = RadicalMapping::default();
+    let res = super::ident_line(&mapping)(IDENT_LINE_FULL_IMG);
     assert_eq!(
         res,
         Ok((
             EMPTY,
             Radical {
-                glyph: "ㅅ".to_string(),
+                glyph: glyph("\u{201A2}"),
                 strokes: 2,
                 alternate: Alternate::Image("js02".to_string()),
             }
@@ -88,13 +106,14 @@ fn glyph_ident_line() {
     const IDENT_LINE_FULL_JIS: &[u8] = &[
         0x24, 0x20, 0xCB, 0xBB, 0x20, 0x33, 0x20, 0x33, 0x44, 0x33, 0x38,
     ];
-    let res = super::ident_line(IDENT_LINE_FULL_JIS);
+    let mapping = RadicalMapping::default();
+    let res = super::ident_line(&mapping)(IDENT_LINE_FULL_JIS);
     assert_eq!(
         res,
         Ok((
             EMPTY,
             Radical {
-                glyph: "⺖".to_string(),
+                glyph: glyph("⺖"),
                 strokes: 3,
                 alternate: Alternate::Glyph("\u{5FC4}".to_string()),
             }
@@ -162,7 +181,7 @@ fn inclusion_expected() -> Membership {
     .collect();
     Membership {
         radical: Radical {
-            glyph: "⻏".to_string(),
+            glyph: glyph("⻏"),
             strokes: 3,
             alternate: Alternate::Image("kozatoR".to_string()),
         },
@@ -183,14 +202,16 @@ const FULL_KANJI: &[u8] = &[
 
 #[test]
 fn inclusion() {
-    let res = super::kanji(FULL_KANJI);
+    let mapping = RadicalMapping::default();
+    let res = super::kanji(&mapping)(FULL_KANJI);
     assert_eq!(res, Ok((EMPTY, inclusion_expected())));
 }
 
 #[test]
 fn inclusion_with_comment() {
     let lines = [COMMENT_LINE, FULL_KANJI].join("".as_bytes());
-    let res = super::kanji(&lines);
+    let mapping = RadicalMapping::default();
+    let res = super::kanji(&mapping)(&lines);
     assert_eq!(res, Ok((EMPTY, inclusion_expected())));
 }
 
@@ -215,3 +236,493 @@ fn works_on_actual_file_2() {
         assert_eq!(true, res.is_ok());
     }
 }
+
+fn diagnostic(res: Result<Vec<Membership>, RadkError>) -> Diagnostic {
+    match res {
+        Err(RadkError::Parse(diagnostic)) => diagnostic,
+        _ => panic!("Expected a parse error, got {:?}", res),
+    }
+}
+
+#[test]
+fn locates_invalid_strokes() {
+    // $ 一 x
+    const BAD_STROKES: &[u8] = &[0x24, 0x20, 0xB0, 0xEC, 0x20, 0x78, 0x0A];
+    let file = [FULL_KANJI, BAD_STROKES].join(EMPTY);
+    let res = diagnostic(super::parse_bytes(&file));
+    assert_eq!(res.context, Context::Strokes);
+    assert_eq!(res.line, 4);
+    assert_eq!(res.column, 6);
+    assert_eq!(res.bytes, b"x");
+}
+
+#[test]
+fn locates_invalid_radical() {
+    // $ \xFF\xFF 1
+    const BAD_RADICAL: &[u8] = &[0x24, 0x20, 0xFF, 0xFF, 0x20, 0x31, 0x0A];
+    let res = diagnostic(super::parse_bytes(BAD_RADICAL));
+    assert_eq!(res.context, Context::Radical);
+    assert_eq!(res.column, 3);
+    assert_eq!(res.bytes, &[0xFF, 0xFF]);
+}
+
+#[test]
+fn locates_invalid_kanji_line() {
+    // $ 一 1
+    // 亜x
+    const BAD_KANJI: &[u8] = &[
+        0x24, 0x20, 0xB0, 0xEC, 0x20, 0x31, 0x0A, 0xB0, 0xA1, 0x78, 0x0A,
+    ];
+    let res = diagnostic(super::parse_bytes(BAD_KANJI));
+    assert_eq!(res.context, Context::Kanji);
+    assert_eq!(res.line, 2);
+    assert_eq!(res.column, 3);
+}
+
+// $ 一 1
+// 亜\xA1\x20唖
+const INVALID_EUCJP: &[u8] = &[
+    0x24, 0x20, 0xB0, 0xEC, 0x20, 0x31, 0x0A, 0xB0, 0xA1, 0xA1, 0x20, 0xB0, 0xA2, 0x0A,
+];
+
+#[test]
+fn locates_invalid_eucjp_kanji() {
+    let res = diagnostic(super::parse_bytes(INVALID_EUCJP));
+    assert_eq!(res.context, Context::Kanji);
+    assert_eq!(res.line, 2);
+    assert_eq!(res.column, 3);
+    assert_eq!(res.bytes, &[0xA1]);
+}
+
+#[test]
+fn recovers_from_invalid_eucjp_kanji() {
+    let file = [INVALID_EUCJP, FULL_KANJI].join(EMPTY);
+    let res = super::parse_bytes_with_options(&file, &ParseOptions::lenient()).unwrap();
+    assert_eq!(res.records, vec![inclusion_expected()]);
+    assert_eq!(res.diagnostics.len(), 1);
+    assert_eq!(res.diagnostics[0].context, Context::Kanji);
+}
+
+#[test]
+fn streams_concatenated_files() {
+    let radkfile = File::open("../assets/edrdg_files/radkfile").unwrap();
+    let radkfile2 = File::open("../assets/edrdg_files/radkfile2").unwrap();
+    let reader = BufReader::new(radkfile.chain(radkfile2));
+    let res: Result<Vec<_>, _> = Memberships::new(reader).collect();
+    assert_eq!(res.unwrap().len(), 253 * 2);
+}
+
+#[test]
+fn continues_after_invalid_radical() {
+    // $ 一 x
+    const BAD_STROKES: &[u8] = &[0x24, 0x20, 0xB0, 0xEC, 0x20, 0x78, 0x0A];
+    let file = [BAD_STROKES, FULL_KANJI].join(EMPTY);
+    let mut memberships = Memberships::new(&file[..]);
+    let first = diagnostic(memberships.next().unwrap().map(|m| vec![m]));
+    assert_eq!(first.context, Context::Strokes);
+    assert_eq!(memberships.next().unwrap().unwrap(), inclusion_expected());
+    assert!(memberships.next().is_none());
+}
+
+#[test]
+fn stops_early() {
+    let file = File::open("../assets/edrdg_files/radkfile").unwrap();
+    let first = Memberships::new(BufReader::new(file)).next();
+    assert_eq!(first.unwrap().unwrap().radical, parsed_radical_simple());
+}
+
+#[test]
+fn recovers_from_invalid_radical() {
+    // $ 一 x
+    const BAD_STROKES: &[u8] = &[0x24, 0x20, 0xB0, 0xEC, 0x20, 0x78, 0x0A];
+    let file = [FULL_KANJI, BAD_STROKES, FULL_KANJI].join(EMPTY);
+    let res = super::parse_bytes_with_options(&file, &ParseOptions::lenient()).unwrap();
+    assert_eq!(
+        res.records,
+        vec![inclusion_expected(), inclusion_expected()]
+    );
+    assert_eq!(res.diagnostics.len(), 1);
+    assert_eq!(res.diagnostics[0].line, 4);
+    assert_eq!(res.diagnostics[0].context, Context::Strokes);
+}
+
+#[test]
+fn recovers_with_comments() {
+    // $ 一 x
+    const BAD_STROKES: &[u8] = &[0x24, 0x20, 0xB0, 0xEC, 0x20, 0x78, 0x0A];
+    let file = [COMMENT_LINE, BAD_STROKES, COMMENT_LINE, FULL_KANJI].join(EMPTY);
+    let res = super::parse_bytes_with_comments(&file, &ParseOptions::lenient()).unwrap();
+    // Comments before the skipped radical carry over to the next one
+    assert_eq!(res.header, vec![" September 2007".to_string(); 2]);
+    assert_eq!(res.memberships.len(), 1);
+    assert_eq!(res.memberships[0].record, inclusion_expected());
+    assert_eq!(res.diagnostics.len(), 1);
+    assert_eq!(res.diagnostics[0].context, Context::Strokes);
+
+    assert!(super::parse_bytes_with_comments(&file, &ParseOptions::default()).is_err());
+}
+
+#[test]
+fn parses_with_mapping() {
+    let file = std::fs::read("../assets/edrdg_files/radkfile").unwrap();
+    let glyphs = |preset| {
+        let options = ParseOptions {
+            mapping: RadicalMapping::preset(preset),
+            ..ParseOptions::default()
+        };
+        let res = super::parse_bytes_with_options(&file, &options).unwrap();
+        res.records
+            .into_iter()
+            .map(|membership| membership.radical.glyph.display)
+            .collect::<Vec<_>>()
+    };
+    let raw = glyphs(Preset::Raw);
+    let suggested = glyphs(Preset::EdrdgSuggested);
+    let jisho = glyphs(Preset::JishoStyle);
+    assert!(raw.contains(&"个".to_string()));
+    assert!(suggested.contains(&"\u{2F09}".to_string()));
+    assert!(jisho.contains(&"\u{201A2}".to_string()));
+    assert_eq!(raw.len(), jisho.len());
+}
+
+#[test]
+fn replaces_radicals_but_not_kanji() {
+    for preset in [Preset::EdrdgSuggested, Preset::JishoStyle] {
+        let mapping = RadicalMapping::preset(preset);
+        for (original, replacement) in mapping.iter() {
+            // "$ X 1\nX\n"
+            let glyph = encode_jis_kanji(&original.to_string()).unwrap();
+            let record = [b"$ ", &glyph[..], b" 1\n", &glyph, b"\n"].concat();
+            let mut memberships = Memberships::with_mapping(&record[..], mapping.clone());
+            let membership = memberships.next().unwrap().unwrap();
+            assert_eq!(membership.radical.glyph.original, original);
+            assert_eq!(membership.radical.glyph.display, replacement);
+            assert_eq!(membership.kanji, vec![original.to_string()]);
+        }
+    }
+}
+
+#[test]
+fn writes_membership() {
+    let mut written = vec![];
+    super::write_radkfile(&mut written, &[inclusion_expected()]).unwrap();
+    assert_eq!(written, FULL_KANJI);
+}
+
+#[test]
+fn writes_glyph_alternate() {
+    let membership = Membership {
+        radical: Radical {
+            glyph: glyph("⺖"),
+            strokes: 3,
+            alternate: Alternate::Glyph("\u{5FC4}".to_string()),
+        },
+        kanji: vec![],
+    };
+    let mut written = vec![];
+    super::write_radkfile(&mut written, &[membership]).unwrap();
+    assert_eq!(written, b"$ \xCB\xBB 3 3D38\n");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serializes_radical() {
+    let radical = Radical {
+        glyph: glyph("⺖"),
+        strokes: 3,
+        alternate: Alternate::Glyph("\u{5FC4}".to_string()),
+    };
+    let json = serde_json::to_value(radical.clone()).unwrap();
+    let expected = serde_json::json!({
+        "glyph": { "jis": 0x4B3B, "original": "忙", "display": "⺖" },
+        "strokes": 3,
+        "alternate": { "type": "glyph", "value": "\u{5FC4}" },
+    });
+    assert_eq!(json, expected);
+    assert_eq!(serde_json::from_value::<Radical>(json).unwrap(), radical);
+
+    let json = serde_json::to_value(Alternate::None).unwrap();
+    assert_eq!(json, serde_json::json!({ "type": "none" }));
+    let json = serde_json::to_value(Alternate::Image("js02".to_string())).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({ "type": "image", "value": "js02" })
+    );
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn parses_gzipped_file() {
+    use crate::test_constants::{gzipped, gzipped_copy};
+
+    let radkfile = gzipped_copy("../assets/edrdg_files/radkfile");
+    let res = super::parse_file_with_comments(radkfile, &ParseOptions::default()).unwrap();
+    assert_eq!(res.memberships.len(), 253);
+    assert_eq!(res.revision.unwrap().to_string(), "2013-10");
+    let radkfile2 = gzipped("../assets/edrdg_files/radkfile2");
+    assert_eq!(super::parse_bytes(&radkfile2).unwrap().len(), 253);
+}
+
+fn radkfilex() -> super::RadkFile {
+    let radkfile =
+        super::parse_file_with_comments("../assets/edrdg_files/radkfile", &ParseOptions::default())
+            .unwrap();
+    let radkfile2 = super::parse_file_with_comments(
+        "../assets/edrdg_files/radkfile2",
+        &ParseOptions::default(),
+    )
+    .unwrap();
+    super::merge_files(&[radkfile, radkfile2])
+}
+
+#[test]
+fn merges_into_radkfilex() {
+    let radkfile = super::parse_file("../assets/edrdg_files/radkfile").unwrap();
+    let radkfile2 = super::parse_file("../assets/edrdg_files/radkfile2").unwrap();
+    let merged = super::merge(radkfile.iter().chain(&radkfile2).cloned());
+    assert_eq!(merged.len(), 253);
+    for ((merged, first), second) in merged.iter().zip(&radkfile).zip(&radkfile2) {
+        assert_eq!(merged.radical, first.radical);
+        // radkfile2 lists 麬 twice under 麦
+        let mut expected = [&first.kanji[..], &second.kanji[..]].concat();
+        let mut seen = std::collections::HashSet::new();
+        expected.retain(|kanji| seen.insert(kanji.clone()));
+        assert_eq!(merged.kanji, expected);
+    }
+
+    let file = radkfilex();
+    assert!(file.is_radkfilex());
+    let radkfile =
+        super::parse_file_with_comments("../assets/edrdg_files/radkfile", &ParseOptions::default())
+            .unwrap();
+    let radkfile_header = super::RADKFILEX_HEADER.len()..;
+    assert_eq!(
+        file.header[radkfile_header][..radkfile.header.len()],
+        radkfile.header[..]
+    );
+    assert_eq!(file.revision.unwrap().to_string(), "2013-10");
+    let records: Vec<_> = file.memberships.into_iter().map(|c| c.record).collect();
+    assert_eq!(records, merged);
+}
+
+#[test]
+fn merges_repeated_kanji() {
+    let merged = super::merge(vec![inclusion_expected(), inclusion_expected()]);
+    assert_eq!(merged, vec![inclusion_expected()]);
+}
+
+#[test]
+fn merges_in_radkfilex_order() {
+    let radkfile = super::parse_file("../assets/edrdg_files/radkfile").unwrap();
+    let radkfile2 = super::parse_file("../assets/edrdg_files/radkfile2").unwrap();
+    let expected = super::merge(radkfile.iter().chain(&radkfile2).cloned());
+    // JIS X 0208 kanji come first even when radkfile2 is read first
+    let swapped = radkfile2.iter().chain(&radkfile).cloned();
+    assert_eq!(super::merge(swapped), expected);
+}
+
+#[test]
+fn parses_radkfilex() {
+    let file =
+        super::parse_file_with_comments("../assets/fixtures/radkfilex", &ParseOptions::default())
+            .unwrap();
+    assert!(file.is_radkfilex());
+    let records: Vec<_> = file.memberships.into_iter().map(|c| c.record).collect();
+    assert_eq!(records.len(), 9);
+    let radkfile = super::parse_file("../assets/edrdg_files/radkfile").unwrap();
+    let radkfile2 = super::parse_file("../assets/edrdg_files/radkfile2").unwrap();
+    let expected: Vec<_> = super::merge(radkfile.into_iter().chain(radkfile2))
+        .into_iter()
+        .filter(|membership| {
+            records
+                .iter()
+                .any(|record| record.radical == membership.radical)
+        })
+        .collect();
+    assert_eq!(records, expected);
+}
+
+#[test]
+fn round_trips_radkfilex() {
+    let file = radkfilex();
+    let mut written = vec![];
+    super::write_radkfile_with_comments(&mut written, &file).unwrap();
+    assert_eq!(crate::detect(&written), crate::FileKind::RadkX);
+    assert_eq!(
+        super::parse_bytes_with_comments(&written, &ParseOptions::default()).unwrap(),
+        file
+    );
+}
+
+fn round_trip(path: &str) {
+    let original = std::fs::read(path).unwrap();
+    let parsed = super::parse_bytes(&original).unwrap();
+    let mut written = vec![];
+    super::write_radkfile(&mut written, &parsed).unwrap();
+    assert!(written == without_header(&original));
+    assert_eq!(super::parse_bytes(&written).unwrap(), parsed);
+}
+
+#[test]
+fn round_trips_actual_file() {
+    round_trip("../assets/edrdg_files/radkfile");
+}
+
+#[test]
+fn round_trips_actual_file_2() {
+    round_trip("../assets/edrdg_files/radkfile2");
+}
+
+#[test]
+fn keeps_comments() {
+    let file = [COMMENT_LINE, FULL_KANJI, COMMENT_LINE, FULL_KANJI].join(EMPTY);
+    let res = super::parse_bytes_with_comments(&file, &ParseOptions::default()).unwrap();
+    let comment = vec![" September 2007".to_string()];
+    assert_eq!(res.header, comment);
+    assert_eq!(
+        res.revision,
+        Some(Revision {
+            year: 2007,
+            month: 9
+        })
+    );
+    assert_eq!(res.memberships[0].comments, Vec::<String>::new());
+    assert_eq!(res.memberships[1].comments, comment);
+    assert_eq!(res.memberships[1].record, inclusion_expected());
+    assert!(res.footer.is_empty());
+}
+
+fn round_trip_with_comments(path: &str, revision: Revision) {
+    let original = std::fs::read(path).unwrap();
+    let parsed = super::parse_bytes_with_comments(&original, &ParseOptions::default()).unwrap();
+    assert_eq!(parsed.revision, Some(revision));
+    let mut written = vec![];
+    super::write_radkfile_with_comments(&mut written, &parsed).unwrap();
+    assert!(written == original);
+}
+
+#[test]
+fn round_trips_actual_file_with_comments() {
+    let revision = Revision {
+        year: 2013,
+        month: 10,
+    };
+    round_trip_with_comments("../assets/edrdg_files/radkfile", revision);
+}
+
+#[test]
+fn round_trips_actual_file_2_with_comments() {
+    let revision = Revision {
+        year: 2007,
+        month: 9,
+    };
+    round_trip_with_comments("../assets/edrdg_files/radkfile2", revision);
+}
+
+#[test]
+fn parses_utf8() {
+    let res = super::parse_utf8("一 1 : 亜 唖\n⺅ 2 js01 : 化\n忙 3 忄 : ");
+    assert_eq!(
+        res.unwrap(),
+        vec![
+            Membership {
+                radical: parsed_radical_simple(),
+                kanji: vec!["亜".to_string(), "唖".to_string()],
+            },
+            Membership {
+                radical: Radical {
+                    glyph: glyph("⺅"),
+                    strokes: 2,
+                    alternate: Alternate::Image("js01".to_string()),
+                },
+                kanji: vec!["化".to_string()],
+            },
+            Membership {
+                radical: Radical {
+                    glyph: glyph("忙"),
+                    strokes: 3,
+                    alternate: Alternate::Glyph("忄".to_string()),
+                },
+                kanji: vec![],
+            },
+        ]
+    );
+}
+
+#[test]
+fn locates_invalid_utf8_strokes() {
+    let err = super::parse_utf8("一 1 : 亜\n｜ one : 亜").unwrap_err();
+    match err {
+        RadkError::Parse(diagnostic) => {
+            assert_eq!(diagnostic.line, 2);
+            assert_eq!(diagnostic.column, 5);
+            assert_eq!(diagnostic.context, Context::Strokes);
+        }
+        _ => panic!("Expected a parse error"),
+    }
+}
+
+#[test]
+fn parses_utf8_output() {
+    let res = super::parse_utf8_file("../assets/outputs/radk_utf8.txt").unwrap();
+    let expected = super::merge(
+        super::parse_file("../assets/edrdg_files/radkfile")
+            .unwrap()
+            .into_iter()
+            .chain(super::parse_file("../assets/edrdg_files/radkfile2").unwrap()),
+    );
+    assert_eq!(res.len(), expected.len());
+    for membership in &res {
+        let original = expected
+            .iter()
+            .find(|original| original.radical.glyph == membership.radical.glyph)
+            .unwrap();
+        assert_eq!(original.radical.strokes, membership.radical.strokes);
+        assert_eq!(original.kanji.len(), membership.kanji.len());
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn parses_json_output() {
+    let expected = super::parse_utf8_file("../assets/outputs/radk_utf8.txt").unwrap();
+    let res = super::parse_json_file("../assets/outputs/radk.json").unwrap();
+    assert!(res == expected);
+    assert!(res
+        .iter()
+        .any(|membership| membership.radical.alternate != Alternate::None));
+}
+
+#[test]
+fn resolves_alternates() {
+    assert_eq!(Alternate::Image("js01".to_string()).resolve(), Some("⺅"));
+    assert_eq!(
+        Alternate::Image("kozatoL".to_string()).resolve(),
+        Some("⻖")
+    );
+    assert_eq!(
+        Alternate::Image("kozatoR".to_string()).resolve(),
+        Some("⻏")
+    );
+    assert_eq!(Alternate::Glyph("忄".to_string()).resolve(), Some("忄"));
+    assert_eq!(Alternate::Image("unknown".to_string()).resolve(), None);
+    assert_eq!(Alternate::None.resolve(), None);
+}
+
+#[test]
+fn resolves_every_bundled_image() {
+    for path in [
+        "../assets/edrdg_files/radkfile",
+        "../assets/edrdg_files/radkfile2",
+    ] {
+        for membership in super::parse_file(path).unwrap() {
+            if let Alternate::Image(name) = &membership.radical.alternate {
+                assert!(
+                    membership.radical.alternate.resolve().is_some(),
+                    "Unresolved image {}",
+                    name
+                );
+            }
+        }
+    }
+}
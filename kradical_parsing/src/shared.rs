@@ -1,12 +1,24 @@
+use crate::{
+    diagnostic::ParseResult,
+    mapping::{RadicalGlyph, RadicalMapping},
+};
 use encoding::{codec::japanese::EUCJPEncoding, DecoderTrap, Encoding};
-use kradical_jis::jis213_to_utf8;
+use kradical_jis::{jis212_to_utf8, jis213_to_utf8};
 use nom::{
-    bytes::complete::take_until,
+    bytes::complete::take_till,
     character::complete::{char, multispace0},
     combinator::value,
-    multi::separated_list0,
-    sequence::{delimited, pair},
-    IResult,
+    multi::many0,
+    sequence::{pair, preceded},
+};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    ops::RangeInclusive,
+    path::Path,
+    sync::OnceLock,
 };
 use thiserror::Error;
 
@@ -25,19 +37,98 @@ pub enum SharedError {
     Unknown,
 }
 
-pub fn comments(b: &[u8]) -> IResult<&[u8], ()> {
-    value(
-        (),
-        delimited(
-            multispace0,
-            separated_list0(char('\n'), comment),
-            multispace0,
-        ),
-    )(b)
+pub fn comments(b: &[u8]) -> ParseResult<'_, ()> {
+    value((), pair(many0(preceded(multispace0, comment)), multispace0))(b)
+}
+
+fn comment(b: &[u8]) -> ParseResult<'_, ()> {
+    value((), pair(char('#'), take_till(|b| b == b'\n')))(b)
+}
+
+/// Reads the next line including its line break,
+/// or `None` at the end of the input
+pub fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    match reader.read_until(b'\n', &mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line)),
+    }
+}
+
+/// The text of a comment line following the `#`,
+/// or `None` for lines that aren't comments
+pub fn comment_text(line: &[u8]) -> Option<String> {
+    let text = line.strip_prefix(b"#")?;
+    let text = text.strip_suffix(b"\n").unwrap_or(text);
+    Some(
+        EUCJPEncoding
+            .decode(text, DecoderTrap::Replace)
+            .unwrap_or_else(|_| String::from_utf8_lossy(text).into_owned()),
+    )
+}
+
+/// Reverses comment_text, encoding a whole comment line
+pub fn encode_comment(text: &str) -> Option<Vec<u8>> {
+    let mut line = b"#".to_vec();
+    for c in text.chars() {
+        if c.is_ascii() {
+            line.push(c as u8);
+        } else {
+            line.extend(encode_eucjp(c.encode_utf8(&mut [0; 4]))?);
+        }
+    }
+    line.push(b'\n');
+    Some(line)
+}
+
+// The first bytes of a gzip stream
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+/// Opens a file for buffered reading, decompressing it if it is gzipped
+pub fn open_file(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    decompress(BufReader::new(File::open(path)?))
 }
 
-fn comment(b: &[u8]) -> IResult<&[u8], ()> {
-    value((), pair(char('#'), take_until("\n")))(b)
+/// Wraps a reader to decompress its contents if they are gzipped
+pub fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    if reader.fill_buf()?.starts_with(GZIP_MAGIC) {
+        gunzip(reader)
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// The decompressed contents if they are gzipped,
+/// or else the contents as they are
+pub fn decompress_bytes(b: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    if b.starts_with(GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        gunzip(b)?.read_to_end(&mut decompressed)?;
+        Ok(Cow::Owned(decompressed))
+    } else {
+        Ok(Cow::Borrowed(b))
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip<'a, R: BufRead + 'a>(reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let decoder = flate2::bufread::MultiGzDecoder::new(reader);
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gunzip<'a, R: BufRead + 'a>(_reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Gzipped input requires the gzip feature",
+    ))
+}
+
+pub fn is_comment_or_blank(line: &[u8]) -> bool {
+    match line.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(&b) => b == b'#',
+        None => true,
+    }
 }
 
 // Sources for Unicode radical glyphs:
@@ -46,174 +137,26 @@ fn comment(b: &[u8]) -> IResult<&[u8], ()> {
 // https://unicode-table.com/en/blocks/cjk-unified-ideographs/
 // https://shapecatcher.com/
 
-// Notes on image remappings
-//
-// Kept the left part
-// Better alternative: ⺅
-// 化 -> http://nihongo.monash.edu/gif212/js01.png
-//
-// Kept the top part
-// https://www.wanikani.com/radicals/hat
-// Possible alternatives: ^ へ ヘ ㅅ 𠆢
-// 个 -> http://nihongo.monash.edu/gif212/js02.png
-//
-// Kept the top part
-// https://www.wanikani.com/radicals/gun
-// Possible alternatives: ⟝ 𠂉
-// 乞 -> http://nihongo.monash.edu/gif212/js10.png
-//
-// Kept the horns
-// https://www.wanikani.com/radicals/horns
-// Better alternative: 丷
-// 并 -> http://nihongo.monash.edu/gif212/js07.png
-//
-// Kept the left part
-// https://www.wanikani.com/radicals/building
-// Better alternative: ⻖
-// 阡 -> http://nihongo.monash.edu/gif212/kozatoL.png
-//
-// Kept the top part
-// https://www.wanikani.com/radicals/flowers
-// Better alternative: 艹
-// 艾 -> http://nihongo.monash.edu/gif212/js03.png
-//
-// Kept the right part
-// https://www.wanikani.com/radicals/building
-// Better alternative: ⻏
-// 邦 -> http://nihongo.monash.edu/gif212/kozatoR.png
-//
-// Kept the horns at the top
-// https://www.wanikani.com/radicals/triceratops
-// Better alternative: ⺌
-// 尚 -> http://nihongo.monash.edu/gif212/js04.png
-//
-// Kept the swoosh and above
-// https://www.wanikani.com/radicals/coffin
-// Better alternative: 耂
-// 老 -> http://nihongo.monash.edu/gif212/js05.png
+// Glyphs for the images used as alternates are in the radk module
 
 // These are different characters:
 // ⻖ left  (2ED6)
 // ⻏ right (2ECF)
 
-// Todo: These should ONLY be applied to radicals, _not_ kanji.
-pub fn remap_radical(code: u32) -> Option<&'static str> {
-    // Remappings taken from kradfile lines 45-65
-    match code {
-        // 化 -> ⺅
-        0xB2BD => Some("\u{2E85}"),
-
-        // # D0 A4  2F09
-        // 个 -> ⼉
-        // Ignoring this one because it makes zero sense.
-        // Maybe the authors had a typo.
-        // 0xD0A4 => Some("\u{2F09}"),
-        // This is the replacement used by Jisho.
-        // 个 -> 𠆢
-        0xD0A4 => Some("\u{201A2}"),
-
-        // # D6 F5  none available - upside-down A5 CF
-        // D6F5 -> 并
-        // A5CF -> ハ
-        // 0xD6F5 => Some("\u{30CF}"),
-        // The authors suggest a vertically-flipped ハ
-        // like the Wanikani horns radical
-        // https://www.wanikani.com/radicals/horns
-        // I found an alternate glyph that isn't
-        // semantically a Japanese radical
-        // (it's a kwukyel ideograph)
-        // but it looks correct.
-        // 并 -> 丷
-        0xD6F5 => Some("\u{4E37}"),
-
-        // 刈 -> ⺉
-        0xB4A2 => Some("\u{2E89}"),
-
-        // 込 -> ⻌
-        0xB9FE => Some("\u{2ECC}"),
-
-        // 尚 -> ⺌
-        0xBEB0 => Some("\u{2E8C}"),
-
-        // 忙 -> ⺖
-        0xCBBB => Some("\u{2E96}"),
-
-        // The suggested replacement is not correct.
-        // 扎 -> ⺗
-        // 0xD9A9 => Some("\u{2E97}"),
-        // This is what appears on the WWWJDIC server
-        // 扎 -> 扌
-        0xD9A9 => Some("\u{624C}"),
-
-        // 汁 -> ⺡
-        0xBDC1 => Some("\u{2EA1}"),
-
-        // 犯 -> ⺨
-        0xC8C8 => Some("\u{2EA8}"),
-
-        // 艾 -> ⺾
-        0xE7E8 => Some("\u{2EBE}"),
-
-        // 邦 -> ⻏
-        0xCBAE => Some("\u{2ECF}"),
-
-        // 阡 -> ⻙
-        // 0xEFF4 => Some("\u{2ED9}"),
-        // The above must have been another
-        // mistake because there's a way better choice.
-        // 阡 -> ⻖
-        0xEFF4 => Some("\u{2ED6}"),
-
-        // 老 -> ⺹
-        0xCFB7 => Some("\u{2EB9}"),
-
-        // 杰 -> ⺣
-        0xDBBF => Some("\u{2EA3}"),
-
-        // 礼 -> ⺭
-        0xCEE9 => Some("\u{2EAD}"),
-
-        // 疔 -> ⽧
-        0xE1CB => Some("\u{2F67}"),
-
-        // 禹 -> ⽱
-        0xE3BB => Some("\u{2F71}"),
-
-        // 初 -> ⻂
-        0xBDE9 => Some("\u{2EC2}"),
-
-        // 買 -> ⺲
-        0xC7E3 => Some("\u{2EB2}"),
-
-        // 滴 -> 啇
-        0xC5A9 => Some("\u{5547}"),
-
-        // Adding another of my own not from the
-        // kradfile suggestions. This is the replacement
-        // used by Jisho.
-        // 乞 -> 𠂉
-        0xB8F0 => Some("\u{20089}"),
-
-        _ => None,
+// Radicals are always JIS X 0208 characters
+pub fn decode_jis_radical(b: &[u8], mapping: &RadicalMapping) -> Result<RadicalGlyph, SharedError> {
+    if b.len() != 2 {
+        return Err(SharedError::Unknown);
     }
-}
-
-pub fn decode_jis_radical(b: &[u8]) -> Result<String, SharedError> {
-    match b.len() {
-        2 => {
-            let code = bytes_to_u32(b);
-            remap_radical(code)
-                .or_else(|| jis213_to_utf8(code))
-                .map(|unicode| unicode.to_string())
-                .ok_or(SharedError::Jis)
-        }
-        3 => EUCJPEncoding
-            .decode(b, DecoderTrap::Strict)
-            .map_err(|_| SharedError::EucJp),
-        _ => Err(SharedError::Unknown),
+    let code = bytes_to_u32(b);
+    let mut chars = jis213_to_utf8(code).ok_or(SharedError::Jis)?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(original), None) => Ok(mapping.glyph((code & 0x7F7F) as u16, original)),
+        _ => Err(SharedError::Jis),
     }
 }
 
+// Kanji are never subject to radical replacements
 pub fn decode_jis_kanji(b: &[u8]) -> Result<String, SharedError> {
     match b.len() {
         2 => {
@@ -238,11 +181,127 @@ fn bytes_to_u32(b: &[u8]) -> u32 {
     out
 }
 
+// The range of bytes used by each half of a two-byte JIS character in EUC-JP
+const EUC_BYTES: RangeInclusive<u8> = 0xA1..=0xFE;
+
+// The lead bytes of rows 1 through 84, which hold all of JIS X 0208
+const JIS_X_0208_LEADS: RangeInclusive<u8> = 0xA1..=0xF4;
+
+// The EUC-JP bytes for a radical's JIS X 0208 code
+pub fn encode_jis_radical(radical: &RadicalGlyph) -> Vec<u8> {
+    (radical.jis | 0x8080).to_be_bytes().to_vec()
+}
+
+// The JIS X 0208 code for a character that has a two-byte encoding
+pub fn jis_code(c: char) -> Option<u16> {
+    match encode_jis_kanji(c.encode_utf8(&mut [0; 4]))?.as_slice() {
+        &[high, low] => Some(u16::from_be_bytes([high, low]) & 0x7F7F),
+        _ => None,
+    }
+}
+
+// Reverses decode_jis_kanji, preferring two-byte JIS X 0208 codes
+pub fn encode_jis_kanji(glyph: &str) -> Option<Vec<u8>> {
+    jis213_codes()
+        .get(glyph)
+        .map(|&code| u32_to_bytes(code))
+        .or_else(|| encode_eucjp(glyph))
+}
+
+// Reverses EUC-JP decoding for a single character,
+// including the three-byte JIS X 0212 characters
+pub fn encode_eucjp(glyph: &str) -> Option<Vec<u8>> {
+    eucjp_codes().get(glyph).cloned()
+}
+
+// Reverses the JIS X 0212 lookup used for radkfile alternates
+pub fn encode_jis212(glyph: &str) -> Option<u16> {
+    jis212_codes().get(glyph).copied()
+}
+
+fn jis212_codes() -> &'static HashMap<String, u16> {
+    static CODES: OnceLock<HashMap<String, u16>> = OnceLock::new();
+    CODES.get_or_init(|| {
+        let mut codes = HashMap::new();
+        for lead in 0x21..=0x7Eu16 {
+            for trail in 0x21..=0x7Eu16 {
+                let code = lead << 8 | trail;
+                if let Some(glyph) = jis212_to_utf8(code) {
+                    // Alternates are only written back with their parsed code
+                    // as long as no two codes share a glyph
+                    let previous = codes.insert(glyph.to_string(), code);
+                    debug_assert!(previous.is_none(), "{} has several codes", glyph);
+                }
+            }
+        }
+        codes
+    })
+}
+
+fn jis213_codes() -> &'static HashMap<&'static str, u32> {
+    static CODES: OnceLock<HashMap<&'static str, u32>> = OnceLock::new();
+    CODES.get_or_init(|| {
+        let mut codes = HashMap::new();
+        for lead in JIS_X_0208_LEADS {
+            for trail in EUC_BYTES {
+                // JIS X 0213 extends JIS X 0208 with characters that are
+                // written as JIS X 0212 in the EDRDG files, so skip those
+                let b = [lead, trail];
+                if EUCJPEncoding.decode(&b, DecoderTrap::Strict).is_err() {
+                    continue;
+                }
+                let code = bytes_to_u32(&b);
+                if let Some(glyph) = jis213_to_utf8(code) {
+                    codes.entry(glyph).or_insert(code);
+                }
+            }
+        }
+        codes
+    })
+}
+
+fn eucjp_codes() -> &'static HashMap<String, Vec<u8>> {
+    static CODES: OnceLock<HashMap<String, Vec<u8>>> = OnceLock::new();
+    CODES.get_or_init(|| {
+        let mut codes = HashMap::new();
+        for lead in EUC_BYTES {
+            for trail in EUC_BYTES {
+                for b in [vec![lead, trail], vec![0x8F, lead, trail]] {
+                    if let Ok(glyph) = EUCJPEncoding.decode(&b, DecoderTrap::Strict) {
+                        codes.entry(glyph).or_insert(b);
+                    }
+                }
+            }
+        }
+        codes
+    })
+}
+
+fn u32_to_bytes(code: u32) -> Vec<u8> {
+    code.to_be_bytes()
+        .iter()
+        .skip_while(|&&b| b == 0)
+        .copied()
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test_constants::*;
 
+    #[test]
+    fn encodes_every_jis212_code() {
+        for lead in 0x21..=0x7Eu16 {
+            for trail in 0x21..=0x7Eu16 {
+                let code = lead << 8 | trail;
+                if let Some(glyph) = jis212_to_utf8(code) {
+                    assert_eq!(encode_jis212(&glyph.to_string()), Some(code));
+                }
+            }
+        }
+    }
+
     #[test]
     fn is_comment() {
         let res = comment(COMMENT_LINE);
@@ -255,6 +314,25 @@ mod tests {
         assert_eq!(res, Ok((NEWLINE, ())));
     }
 
+    #[test]
+    fn encodes_every_remapped_radical() {
+        let mapping = RadicalMapping::default();
+        for (original, _) in mapping.iter() {
+            let radical = mapping.radical(original).unwrap();
+            let b = encode_jis_radical(&radical);
+            assert_eq!(b, encode_jis_kanji(&original.to_string()).unwrap());
+            assert_eq!(decode_jis_radical(&b, &mapping).unwrap(), radical);
+        }
+    }
+
+    #[test]
+    fn encodes_jis_x_0212_kanji() {
+        // 丂
+        let b = encode_jis_kanji("丂").unwrap();
+        assert_eq!(b, &[0x8F, 0xB0, 0xA1]);
+        assert_eq!(decode_jis_kanji(&b).unwrap(), "丂");
+    }
+
     #[test]
     fn multiple_comment_lines() {
         let line = vec![COMMENT_LINE, COMMENT_LINE].join("".as_bytes());
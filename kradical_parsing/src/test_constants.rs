@@ -1,3 +1,35 @@
 pub const COMMENT_LINE: &[u8] = b"# September 2007\n";
 pub const NEWLINE: &[u8] = b"\n";
 pub const EMPTY: &[u8] = b"";
+
+/// The contents of an EDRDG file following its header comments
+pub fn without_header(b: &[u8]) -> &[u8] {
+    let mut rest = b;
+    while rest.starts_with(b"#") {
+        let line_end = rest.iter().position(|&b| b == b'\n').unwrap();
+        rest = &rest[line_end + 1..];
+    }
+    rest
+}
+
+/// A gzipped copy of a file's contents
+#[cfg(feature = "gzip")]
+pub fn gzipped(path: &str) -> Vec<u8> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&std::fs::read(path).unwrap()).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Writes a gzipped copy of a file to the temporary directory
+#[cfg(feature = "gzip")]
+pub fn gzipped_copy(path: &str) -> std::path::PathBuf {
+    let name = std::path::Path::new(path).file_name().unwrap();
+    let mut copy = std::env::temp_dir().join("kradical_parsing");
+    std::fs::create_dir_all(&copy).unwrap();
+    copy.push(format!("{}.gz", name.to_string_lossy()));
+    std::fs::write(&copy, gzipped(path)).unwrap();
+    copy
+}
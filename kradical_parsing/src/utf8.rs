@@ -0,0 +1,79 @@
+//! Helpers for reading the UTF-8 text files written by the converter
+
+use crate::diagnostic::{Context, Diagnostic};
+
+/// A line of text along with its byte offset into the file
+pub struct Line<'a> {
+    pub offset: usize,
+    pub text: &'a str,
+}
+
+/// The lines that are neither blank nor comments,
+/// so that hand-edited files can be annotated
+pub fn lines(text: &str) -> impl Iterator<Item = Line<'_>> {
+    let mut offset = 0;
+    text.split('\n')
+        .map(move |line| {
+            let start = offset;
+            offset += line.len() + 1;
+            Line {
+                offset: start,
+                text: line.strip_suffix('\r').unwrap_or(line),
+            }
+        })
+        .filter(|line| {
+            let trimmed = line.text.trim_start();
+            !trimmed.is_empty() && !trimmed.starts_with('#')
+        })
+}
+
+/// The space-separated tokens of a line along with their byte offsets
+pub fn tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut offset = 0;
+    line.split(' ').filter_map(move |token| {
+        let start = offset;
+        offset += token.len() + 1;
+        if token.is_empty() {
+            None
+        } else {
+            Some((start, token))
+        }
+    })
+}
+
+/// Splits a line at the ` : ` separator
+pub fn split_separator(line: &str) -> Result<(&str, usize, &str), (usize, Context)> {
+    const SEPARATOR: &str = " : ";
+    match line.find(SEPARATOR) {
+        Some(i) => Ok((
+            &line[..i],
+            i + SEPARATOR.len(),
+            &line[i + SEPARATOR.len()..],
+        )),
+        None => Err((line.len(), Context::Separator)),
+    }
+}
+
+/// Locates a failure at the given offset into a line
+pub fn diagnostic(text: &str, line: &Line, (column, context): (usize, Context)) -> Diagnostic {
+    let b = text.as_bytes();
+    Diagnostic::new(b, &b[line.offset + column..], context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let text = "# Edited by hand\n亜 : 一\r\n\n  \n唖 : 口";
+        let lines: Vec<_> = lines(text).map(|line| (line.offset, line.text)).collect();
+        assert_eq!(lines, vec![(17, "亜 : 一"), (32, "唖 : 口")]);
+    }
+
+    #[test]
+    fn finds_token_offsets() {
+        let tokens: Vec<_> = tokens("一 1  js01").collect();
+        assert_eq!(tokens, vec![(0, "一"), (4, "1"), (7, "js01")]);
+    }
+}
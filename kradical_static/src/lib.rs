@@ -1,5 +1,8 @@
 //! Contains the contents of `kradfile`, `kradfile2`, `radkfile`, and `radkfile2`
 //! in a format that can be easily `use`d and compiled into and Rust program.
+//!
+//! With the `serde` feature, the types implement `Serialize`.
+//! They cannot implement `Deserialize` since they borrow `'static` data.
 
 mod decompositions;
 mod memberships;
@@ -8,6 +11,7 @@ pub use decompositions::*;
 pub use memberships::*;
 
 /// The constituent radicals for a kanji
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Decomposition {
     /// The kanji
     pub kanji: char,
@@ -17,6 +21,7 @@ pub struct Decomposition {
 }
 
 /// The kanji that contain a radical
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Membership {
     /// The radical
     pub radical: char,
@@ -26,4 +31,27 @@ pub struct Membership {
 
     /// The number of strokes to draw the radical
     pub strokes: u8,
+
+    /// An alternate representation of the radical
+    pub alternate: Alternate,
+}
+
+/// Alternate representations for a radical other than its glyph
+///
+/// With the `serde` feature, this is tagged as `{"type": "image", "value": "js02"}`,
+/// `{"type": "glyph", "value": "忄"}`, or `{"type": "none"}`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
+pub enum Alternate {
+    /// The name of an image from the WWWJDIC website
+    Image(&'static str),
+
+    /// Another glyph that better depicts the radical
+    Glyph(char),
+
+    /// No alternate representation provided
+    None,
 }
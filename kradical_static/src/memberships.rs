@@ -1,10 +1,11 @@
-use super::Membership;
+use super::{Alternate, Membership};
 
 /// For each radical, a list of which kanji contain it from the `radkfile`
 pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '一',
         strokes: 1,
+        alternate: Alternate::None,
         kanji: &[
             '一', '丁', '丂', '丄', '丅', '万', '丈', '三', '上', '下', '丌', '不', '与', '丐',
             '丑', '丒', '且', '丕', '世', '丘', '丙', '丞', '両', '丣', '两', '並', '丰', '丵',
@@ -205,6 +206,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: 'ノ',
         strokes: 1,
+        alternate: Alternate::None,
         kanji: &[
             '七', '万', '丈', '丌', '不', '丑', '丒', '丕', '丞', '丣', '丫', '丱', '丵', '丹',
             '丼', '丿', '乂', '乃', '乄', '久', '乇', '乍', '乎', '乏', '乑', '乕', '乖', '乗',
@@ -345,6 +347,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '｜',
         strokes: 1,
+        alternate: Alternate::None,
         kanji: &[
             '丄', '丅', '万', '下', '丌', '不', '丑', '丕', '世', '丗', '両', '丣', '並', '丨',
             '个', '丫', '中', '丮', '丯', '丰', '丱', '串', '丼', '乃', '乍', '乑', '乕', '乗',
@@ -469,6 +472,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '丶',
         strokes: 1,
+        alternate: Alternate::None,
         kanji: &[
             '丈', '不', '丒', '丕', '丫', '丱', '丵', '丶', '丸', '丹', '主', '丼', '乀', '乁',
             '乂', '乄', '之', '乏', '乑', '亙', '亥', '亯', '仗', '仞', '仢', '以', '仭', '仱',
@@ -548,6 +552,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '亅',
         strokes: 1,
+        alternate: Alternate::None,
         kanji: &[
             '丁', '丞', '丹', '乎', '乴', '亅', '了', '予', '争', '亊', '事', '亍', '于', '亦',
             '亨', '亭', '仃', '伃', '伺', '佇', '何', '余', '俄', '俆', '倚', '倫', '倳', '停',
@@ -580,6 +585,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '乙',
         strokes: 1,
+        alternate: Alternate::None,
         kanji: &[
             '七', '乇', '之', '乏', '乙', '乚', '乜', '乞', '也', '乢', '乣', '乨', '乩', '乱',
             '乳', '乴', '乵', '乹', '乾', '乿', '亀', '亂', '亳', '仛', '仡', '侘', '俋', '俺',
@@ -609,6 +615,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: 'ハ',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '乗', '乘', '亦', '介', '份', '伀', '伵', '伻', '余', '佾', '來', '侏', '供', '俆',
             '俗', '倊', '倎', '倛', '倦', '倰', '倶', '側', '偵', '傊', '傎', '傛', '傟', '債',
@@ -696,6 +703,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '二',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '三', '並', '丮', '丰', '丼', '二', '亍', '于', '云', '井', '亖', '亗', '亘', '亙',
             '些', '亝', '亞', '亟', '仁', '仨', '伕', '会', '伝', '伴', '佘', '余', '佚', '佞',
@@ -778,6 +786,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '十',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '丗', '丵', '乖', '乘', '乵', '乹', '乾', '什', '仐', '仟', '仠', '仵', '伎', '伜',
             '估', '伳', '伴', '伻', '侁', '來', '侼', '俌', '俜', '俾', '倅', '個', '倖', '倝',
@@ -847,6 +856,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '丷',
         strokes: 2,
+        alternate: Alternate::Image("js07"),
         kanji: &[
             '並', '丫', '丵', '乎', '亊', '伴', '佯', '併', '侠', '侻', '俤', '倦', '偂', '偐',
             '偙', '偽', '傍', '傞', '僐', '僔', '僕', '僖', '僜', '僧', '儀', '儈', '儯', '儵',
@@ -910,6 +920,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '亠',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '之', '乏', '亠', '亡', '亢', '交', '亥', '亦', '亨', '享', '京', '亭', '亮', '亯',
             '亰', '亳', '亶', '亹', '伉', '佼', '侅', '依', '侠', '俲', '俵', '俼', '倅', '倖',
@@ -971,6 +982,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '冂',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '丙', '両', '两', '亂', '亹', '伂', '佈', '侖', '侗', '倆', '倎', '倘', '倜', '偁',
             '偂', '偊', '偏', '偑', '偙', '偶', '傐', '僀', '僑', '僘', '儞', '儷', '兩', '冂',
@@ -1029,6 +1041,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '儿',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '亮', '价', '佻', '侁', '侊', '侻', '俊', '俒', '俛', '俿', '倥', '倪', '傀', '傹',
             '僔', '僥', '儋', '儍', '儭', '儳', '儹', '儿', '兀', '允', '元', '兄', '充', '兆',
@@ -1081,6 +1094,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '勹',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '丂', '与', '丒', '亀', '争', '仢', '佝', '佹', '佽', '侉', '侚', '侷', '俛', '俜',
             '俽', '偈', '偒', '偽', '傯', '傷', '僃', '像', '儆', '儋', '儳', '免', '兔', '冕',
@@ -1129,6 +1143,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺅',
         strokes: 2,
+        alternate: Alternate::Image("js01"),
         kanji: &[
             '什', '仁', '仂', '仃', '仆', '仇', '仍', '仏', '仔', '仕', '他', '仗', '付', '仙',
             '仛', '仞', '仟', '仠', '仡', '仢', '代', '仨', '仭', '仮', '仯', '仰', '仱', '仲',
@@ -1177,6 +1192,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '厶',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '丟', '乨', '亂', '云', '亝', '仏', '伀', '会', '伝', '佉', '侄', '侌', '侔', '俊',
             '俟', '俼', '倊', '倒', '偊', '偓', '偶', '傀', '傟', '傪', '傳', '允', '充', '兊',
@@ -1224,6 +1240,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '𠆢',
         strokes: 2,
+        alternate: Alternate::Image("js02"),
         kanji: &[
             '个', '今', '介', '仐', '仚', '令', '仱', '价', '企', '众', '会', '伱', '伶', '佘',
             '余', '佮', '佱', '佺', '侌', '侖', '俆', '俗', '倉', '倫', '倹', '偸', '傖', '傘',
@@ -1268,6 +1285,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '冖',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '亭', '亮', '亳', '亹', '侵', '侼', '偙', '停', '傍', '僀', '僢', '僾', '儋', '儚',
             '儝', '償', '優', '儳', '儻', '党', '冖', '冗', '冘', '写', '冝', '冠', '冡', '冢',
@@ -1310,6 +1328,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '人',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '丙', '两', '乞', '乾', '亥', '人', '仄', '从', '以', '伕', '众', '似', '佚', '佽',
             '侅', '來', '侠', '俎', '俠', '俸', '俽', '倅', '倆', '倝', '倹', '偈', '傘', '傷',
@@ -1351,6 +1370,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '又',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '亟', '仮', '伋', '伎', '伖', '伮', '侵', '俶', '假', '傁', '冣', '冦', '凾', '剟',
             '努', '匓', '又', '叉', '友', '双', '反', '収', '叏', '叒', '叓', '叔', '叕', '取',
@@ -1392,6 +1412,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '匕',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '七', '乖', '乘', '乜', '也', '些', '仳', '佌', '佗', '佬', '侂', '俿', '偈', '傀',
             '傎', '傾', '儗', '儳', '冀', '凝', '切', '剩', '劇', '劖', '勴', '匂', '匕', '化',
@@ -1433,6 +1454,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '厂',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '乕', '仄', '仮', '伌', '佹', '侲', '俿', '偐', '偱', '傆', '傓', '備', '儋', '儼',
             '兏', '减', '剷', '劂', '劇', '劌', '励', '劶', '勴', '勵', '卬', '卮', '危', '厂',
@@ -1469,6 +1491,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '𠂉',
         strokes: 2,
+        alternate: Alternate::Image("js10"),
         kanji: &[
             '乍', '乞', '乾', '作', '侮', '侯', '俟', '修', '候', '倣', '做', '傚', '傲', '傷',
             '儖', '儼', '凝', '勧', '医', '午', '卸', '厰', '厳', '吃', '啓', '啣', '喉', '嗷',
@@ -1503,6 +1526,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '卜',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '上', '下', '与', '乩', '仆', '佔', '俶', '俿', '倬', '偵', '兏', '冦', '劇', '勴',
             '卓', '卜', '卞', '占', '卡', '卣', '卥', '卦', '卵', '叔', '叡', '吓', '呫', '哶',
@@ -1529,6 +1553,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '冫',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '佻', '傓', '傟', '僇', '兆', '冫', '冰', '冱', '冲', '决', '冴', '况', '冶', '冷',
             '冸', '冺', '冼', '冽', '冾', '冿', '凂', '凄', '凅', '准', '凈', '凉', '凋', '凌',
@@ -1554,6 +1579,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '凵',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '傜', '儍', '兇', '凵', '凶', '凷', '凸', '凹', '函', '凾', '匈', '匋', '卋', '吨',
             '啣', '嚙', '嚥', '囓', '囤', '墼', '媱', '寚', '寶', '屆', '屯', '崗', '崫', '嵕',
@@ -1575,6 +1601,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '几',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '亢', '伉', '佩', '俿', '冗', '几', '凡', '凢', '凥', '処', '凧', '凩', '凪', '凭',
             '凮', '凰', '凱', '凲', '凳', '凴', '劥', '匓', '匟', '吭', '咒', '嗁', '囥', '坑',
@@ -1596,6 +1623,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '刀',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '仞', '仭', '份', '佋', '偰', '儕', '刀', '刃', '刄', '刅', '分', '切', '刕', '初',
             '刧', '刱', '券', '刼', '剏', '剪', '剱', '劈', '劑', '劒', '劔', '劭', '卲', '叧',
@@ -1617,6 +1645,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺉',
         strokes: 2,
+        alternate: Alternate::Glyph('刂'),
         kanji: &[
             '例', '俐', '倒', '偂', '側', '偸', '冽', '刂', '刈', '刊', '刋', '刎', '刑', '划',
             '刓', '刔', '刖', '列', '刘', '刢', '判', '別', '刨', '利', '刪', '刮', '到', '刲',
@@ -1637,6 +1666,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '匚',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '偃', '傴', '凞', '劻', '勘', '匚', '匛', '匜', '匝', '匞', '匟', '匠', '匡', '匣',
             '匥', '匧', '匨', '匩', '匪', '匫', '匬', '匭', '匯', '匰', '匱', '匲', '匳', '匵',
@@ -1656,6 +1686,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '力',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '仂', '伽', '侽', '俲', '働', '別', '力', '劜', '功', '加', '劣', '劤', '劥', '劦',
             '劧', '助', '努', '劫', '劬', '劭', '劯', '劰', '励', '労', '劵', '劶', '劷', '劸',
@@ -1675,6 +1706,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '卩',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '令', '仰', '伌', '伶', '佹', '倇', '倦', '冷', '剜', '勌', '卩', '卬', '卭', '卮',
             '卯', '印', '危', '卲', '即', '却', '卵', '卷', '卸', '卹', '卻', '卾', '卿', '厄',
@@ -1691,6 +1723,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: 'マ',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '予', '伃', '俑', '儗', '凝', '刢', '劀', '勇', '勈', '呤', '埇', '墅', '壄', '妤',
             '姈', '婺', '岺', '序', '彾', '忬', '恿', '愑', '愗', '慂', '懋', '懝', '抒', '拎',
@@ -1704,6 +1737,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '九',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '丸', '九', '仇', '伜', '勢', '匭', '卆', '囈', '埶', '執', '塾', '墊', '孰', '宄',
             '尻', '忰', '抛', '摯', '旭', '枠', '染', '氿', '汍', '熟', '熱', '爇', '犱', '砕',
@@ -1715,6 +1749,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '入',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '久', '入', '兩', '兪', '叺', '圦', '懣', '扖', '抐', '掚', '杁', '枘', '柩', '樠',
             '汭', '滿', '焫', '疚', '瘸', '瞞', '窩', '糴', '胔', '胾', '脼', '膐', '臋', '臡',
@@ -1725,6 +1760,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: 'ユ',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '侌', '侯', '候', '偽', '决', '刔', '叏', '堠', '夬', '峱', '廕', '快', '憨', '抉',
             '曮', '決', '炔', '為', '玦', '瘊', '癊', '睺', '矙', '筷', '篌', '糇', '緱', '缺',
@@ -1735,6 +1771,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '乃',
         strokes: 2,
+        alternate: Alternate::None,
         kanji: &[
             '乃', '仍', '伋', '及', '呄', '唀', '圾', '垜', '奶', '孕', '尮', '扔', '携', '朶',
             '极', '桗', '楹', '琇', '疓', '盁', '礽', '秀', '綉', '艿', '芨', '芿', '蔿', '蜏',
@@ -1744,6 +1781,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '口',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '丣', '中', '串', '乨', '乩', '乱', '事', '亜', '亟', '亨', '享', '京', '亭', '亮',
             '亯', '亳', '亶', '亹', '仲', '估', '伵', '伺', '伽', '佀', '佋', '佑', '佔', '何',
@@ -1953,6 +1991,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '土',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '佉', '佬', '佳', '侁', '侄', '侍', '侾', '俈', '俵', '倒', '倜', '倢', '倩', '倰',
             '偓', '傃', '傲', '債', '僅', '僓', '僥', '儎', '儬', '儹', '先', '兟', '冼', '凋',
@@ -2026,6 +2065,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺾',
         strokes: 3,
+        alternate: Alternate::Image("js03"),
         kanji: &[
             '偀', '偌', '備', '僃', '僅', '僨', '儆', '儚', '儺', '冪', '剳', '募', '勤', '勱',
             '勸', '匿', '厲', '哎', '喏', '嗒', '嘆', '嘩', '噴', '嚄', '嚆', '嚾', '囈', '囌',
@@ -2091,6 +2131,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '大',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '伕', '佒', '佚', '侉', '侠', '俁', '俠', '俣', '俸', '俺', '倁', '倚', '倦', '偀',
             '偆', '偧', '偰', '傄', '傒', '僊', '僑', '僕', '僚', '僣', '儗', '儧', '儯', '儺',
@@ -2147,6 +2188,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '小',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '乿', '京', '亰', '仯', '伱', '佘', '余', '你', '係', '俆', '俏', '俶', '倘', '倞',
             '倧', '偗', '傃', '傆', '傺', '僄', '僘', '僚', '僦', '儭', '儸', '冸', '凉', '凛',
@@ -2202,6 +2244,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺡',
         strokes: 3,
+        alternate: Alternate::Glyph('氵'),
         kanji: &[
             '匯', '嘙', '垽', '埿', '塗', '塰', '壍', '奫', '娑', '婆', '寖', '惉', '愆', '慂',
             '懣', '柒', '染', '桫', '梁', '樑', '欂', '氵', '氻', '氾', '氿', '汀', '汁', '汊',
@@ -2256,6 +2299,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '幺',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '乣', '乿', '侅', '係', '傃', '傒', '僟', '儸', '勬', '卛', '呟', '呦', '喲', '嗉',
             '嗘', '嗞', '嘰', '噄', '嚩', '嚮', '囉', '圝', '坳', '塐', '壅', '奒', '奚', '姟',
@@ -2300,6 +2344,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '扌',
         strokes: 3,
+        alternate: Alternate::Glyph('扌'),
         kanji: &[
             '咑', '哲', '啦', '娎', '嶊', '庪', '悊', '扌', '扎', '扐', '扑', '扒', '打', '扔',
             '払', '扖', '托', '扚', '扛', '扜', '扞', '扠', '扣', '扤', '扨', '扭', '扮', '扯',
@@ -2338,6 +2383,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '宀',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '佇', '佗', '侒', '侘', '俒', '倇', '倌', '倥', '倧', '傛', '傢', '儐', '儜', '剜',
             '割', '叠', '咜', '咤', '咹', '唍', '啌', '啘', '喀', '喧', '嚀', '坨', '坹', '坾',
@@ -2375,6 +2421,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '女',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '伮', '佞', '侒', '侞', '侫', '俀', '倭', '偃', '偠', '僂', '凄', '努', '匽', '呶',
             '咹', '唼', '啛', '喓', '嘍', '嘙', '嚶', '囡', '堰', '塿', '女', '奴', '奵', '奶',
@@ -2408,6 +2455,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '山',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '丗', '両', '乢', '亗', '仙', '仚', '倔', '傜', '催', '傰', '凒', '凱', '出', '剛',
             '剴', '匋', '匩', '咄', '喘', '嗤', '嘊', '圌', '圸', '堀', '堈', '塏', '墔', '墼',
@@ -2438,6 +2486,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: 'ヨ',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '争', '亊', '事', '互', '亹', '仾', '伊', '侓', '侵', '倢', '倳', '傁', '傏', '傔',
             '傭', '儘', '兼', '冿', '凄', '凈', '凲', '剥', '吚', '君', '咿', '唐', '啑', '啛',
@@ -2466,6 +2515,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '尸',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '侭', '侷', '倉', '倔', '倨', '偏', '偓', '傓', '傖', '僝', '僱', '僻', '冺', '凥',
             '刷', '劈', '劚', '匾', '叞', '启', '呎', '呢', '咫', '唳', '啓', '喔', '嗆', '嘏',
@@ -2493,6 +2543,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '廾',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '丼', '併', '侖', '倎', '借', '倫', '偏', '僀', '儕', '冊', '刑', '刪', '剏', '剤',
             '劑', '劓', '匥', '匾', '升', '卉', '厪', '呏', '咈', '哢', '嚊', '嚌', '圇', '型',
@@ -2520,6 +2571,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '夂',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '佟', '俊', '俢', '倏', '倐', '倰', '傯', '僾', '儍', '優', '儵', '冬', '凌', '処',
             '匛', '厦', '各', '咎', '咯', '唆', '喀', '喒', '嗄', '噯', '囱', '埈', '夂', '夅',
@@ -2546,6 +2598,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺖',
         strokes: 3,
+        alternate: Alternate::Glyph('忄'),
         kanji: &[
             '忄', '忇', '忉', '忋', '忓', '忔', '忖', '忙', '忡', '忤', '忨', '忪', '快', '忬',
             '忭', '忮', '忯', '忰', '忱', '忲', '忳', '忶', '忸', '忺', '忻', '忼', '怇', '怊',
@@ -2571,6 +2624,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⻌',
         strokes: 3,
+        alternate: Alternate::Glyph('辶'),
         kanji: &[
             '儙', '嗹', '噠', '噵', '導', '巡', '慥', '搥', '摓', '撻', '撾', '擿', '暹', '曃',
             '槌', '樋', '檛', '漣', '熢', '燧', '燵', '璉', '璡', '璲', '磓', '穟', '篴', '篷',
@@ -2595,6 +2649,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '广',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '侂', '俯', '傏', '傭', '儣', '儷', '兤', '凢', '劘', '唐', '嘛', '嚝', '塘', '塵',
             '墉', '墌', '壙', '孋', '席', '幮', '广', '庀', '庁', '広', '庄', '庇', '床', '庋',
@@ -2618,6 +2673,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '寸',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '付', '侍', '俯', '傅', '傠', '傳', '僔', '儔', '冠', '剸', '博', '厨', '吋', '咐',
             '噂', '嚋', '嚩', '嚼', '囀', '団', '圑', '團', '坿', '垨', '埒', '埓', '塒', '塼',
@@ -2641,6 +2697,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '士',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '仕', '任', '佶', '俵', '僖', '儔', '凭', '劼', '匨', '匵', '厫', '吉', '咭', '喆',
             '喜', '嘉', '嘻', '噎', '嚋', '嚞', '嚭', '囍', '囏', '壔', '士', '壬', '壮', '壯',
@@ -2662,6 +2719,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '囗',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '亶', '佪', '個', '傯', '儃', '冎', '凅', '凛', '剮', '口', '咽', '啚', '嗇', '囗',
             '囙', '囚', '四', '囜', '囝', '回', '囟', '因', '囡', '団', '囤', '囥', '囦', '囧',
@@ -2682,6 +2740,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '巾',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '乕', '伂', '佈', '佩', '偙', '僀', '冪', '凧', '制', '刷', '刺', '匝', '吊', '唏',
             '啻', '啼', '圑', '埽', '壐', '姉', '婦', '嫦', '崹', '巋', '巾', '帀', '市', '布',
@@ -2702,6 +2761,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '彡',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '丯', '俢', '修', '偐', '偰', '傪', '僇', '剹', '勠', '厖', '参', '參', '喭', '嘐',
             '嫪', '寥', '尨', '嵃', '嵺', '嵾', '庬', '廖', '廫', '彡', '形', '彣', '彤', '彦',
@@ -2721,6 +2781,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '工',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '佐', '侙', '倥', '傞', '儔', '剄', '功', '勁', '匞', '卭', '啌', '嗟', '噐', '噬',
             '嚋', '墮', '壔', '壽', '媠', '尋', '崆', '嵯', '嵳', '嶹', '巠', '工', '左', '巧',
@@ -2740,6 +2801,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺌',
         strokes: 3,
+        alternate: Alternate::Image("js04"),
         kanji: &[
             '侊', '俏', '倘', '僘', '償', '儻', '光', '党', '兤', '削', '剿', '労', '勦', '単',
             '厰', '厳', '哨', '営', '嗩', '嘗', '垙', '堂', '姯', '嫦', '学', '宵', '尚', '屑',
@@ -2759,6 +2821,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '夕',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '侈', '倇', '偧', '傑', '傜', '傺', '僢', '僯', '僲', '儚', '儛', '剜', '卥', '名',
             '哆', '啘', '埦', '塟', '墏', '夕', '外', '夘', '多', '夛', '夜', '夝', '夡', '夢',
@@ -2778,6 +2841,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '弓',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '丐', '佛', '俤', '兮', '剃', '勥', '匑', '咈', '咦', '墢', '夷', '姨', '娣', '嫋',
             '宆', '宖', '岪', '嵶', '嵹', '廢', '弓', '弔', '引', '弖', '弗', '弘', '弙', '弛',
@@ -2795,6 +2859,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⻏',
         strokes: 3,
+        alternate: Alternate::Image("kozatoR"),
         kanji: &[
             '俋', '倻', '哪', '唈', '啊', '嚮', '娜', '嫏', '帮', '廊', '廓', '扈', '挪', '挹',
             '捓', '揶', '擲', '曏', '梆', '梛', '椰', '榔', '槨', '浥', '灉', '爺', '琊', '瑯',
@@ -2812,6 +2877,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '子',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '乳', '享', '仔', '侟', '侼', '侾', '俘', '僝', '勃', '厚', '吼', '哮', '啍', '噋',
             '囝', '塾', '墩', '好', '子', '孑', '孒', '孔', '孕', '孖', '字', '存', '孚', '孛',
@@ -2829,6 +2895,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '干',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '丵', '仠', '仵', '伻', '刊', '午', '南', '厈', '哶', '唫', '喃', '圉', '圛', '坪',
             '奸', '姧', '娨', '婞', '婻', '宇', '屽', '岸', '岼', '嶧', '帲', '干', '平', '年',
@@ -2846,6 +2913,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '已',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '俋', '倦', '僊', '僎', '凞', '刨', '包', '匏', '吧', '咆', '唈', '噀', '囘', '圏',
             '圮', '圯', '垉', '夒', '夔', '夿', '妃', '媐', '屺', '岊', '巎', '巙', '己', '已',
@@ -2862,6 +2930,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⻖',
         strokes: 3,
+        alternate: Alternate::Image("kozatoL"),
         kanji: &[
             '啊', '埠', '堕', '墜', '墮', '婀', '屙', '嶐', '廕', '憜', '橢', '檃', '櫽', '泐',
             '滁', '痾', '癃', '癊', '癮', '窿', '篨', '蔭', '蔯', '蕯', '薩', '讔', '阜', '阝',
@@ -2878,6 +2947,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '彳',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '勶', '哘', '垳', '屐', '屜', '屣', '履', '屧', '屨', '屩', '嵂', '幑', '彳', '彴',
             '彵', '彷', '彸', '役', '彺', '彼', '彽', '彾', '彿', '往', '征', '徂', '徃', '径',
@@ -2894,6 +2964,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺨',
         strokes: 3,
+        alternate: Alternate::Glyph('犭'),
         kanji: &[
             '墾', '峱', '懇', '漪', '潴', '犭', '犯', '犱', '犲', '犴', '犹', '犾', '狁', '狂',
             '狃', '狄', '狆', '狇', '狉', '狌', '狎', '狐', '狒', '狕', '狖', '狗', '狘', '狙',
@@ -2909,6 +2980,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '巛',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '剄', '剿', '勁', '勦', '匘', '巛', '巠', '巡', '巤', '廱', '徑', '惱', '拶', '桱',
             '樔', '涇', '淄', '湽', '灉', '災', '爉', '牼', '獵', '瑙', '璅', '甾', '痙', '癰',
@@ -2920,6 +2992,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '弋',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '代', '侙', '倵', '坺', '垈', '妭', '岱', '帒', '弋', '弌', '弍', '弎', '式', '弐',
             '弑', '彧', '忒', '拭', '斌', '曵', '杙', '栻', '樲', '武', '犮', '玳', '珷', '盋',
@@ -2930,6 +3003,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '屮',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '劂', '匩', '厥', '塑', '媸', '孼', '屮', '屰', '弢', '愬', '憠', '搊', '搠', '撅',
             '朔', '槊', '橛', '櫱', '溯', '犓', '獗', '皺', '縐', '艸', '芔', '芻', '蒭', '蒴',
@@ -2940,6 +3014,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '廴',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '之', '乏', '侹', '健', '唌', '埏', '庭', '廴', '延', '廷', '廸', '建', '廻', '廼',
             '徤', '挺', '揵', '旔', '梃', '楗', '涎', '犍', '珽', '筳', '筵', '綖', '脠', '腱',
@@ -2949,6 +3024,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '尢',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '僦', '兂', '兓', '冘', '厖', '尢', '尤', '尨', '就', '嵆', '巘', '庬', '忱', '枕',
             '沈', '牻', '犹', '疣', '眈', '紞', '耽', '肬', '萙', '蚘', '訦', '諬', '蹴', '躭',
@@ -2958,6 +3034,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '川',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '侃', '卅', '圳', '宺', '川', '州', '巤', '慌', '旈', '旒', '梳', '毓', '洲', '流',
             '爉', '琉', '甽', '疏', '硫', '紃', '荒', '蔬', '蠟', '訓', '詶', '謊', '躐', '邋',
@@ -2967,6 +3044,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '亡',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '亡', '侫', '妄', '嬴', '忘', '忙', '恾', '慌', '望', '氓', '汒', '瀛', '甿', '盲',
             '籝', '網', '罔', '羸', '肓', '臝', '芒', '茫', '荒', '虻', '蝱', '蠃', '衁', '誷',
@@ -2976,6 +3054,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '也',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '也', '他', '匜', '咃', '地', '她', '弛', '彵', '拖', '揓', '施', '杝', '柂', '椸',
             '池', '沲', '牠', '竾', '絁', '葹', '虵', '袘', '訑', '貤', '迆', '迤', '酏', '釶',
@@ -2985,6 +3064,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '彑',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '互', '仾', '冱', '堟', '宐', '彑', '彔', '彖', '彘', '彙', '彜', '彝', '恆', '枑',
             '沍', '淥', '瑑', '璏', '盠', '祿', '篆', '籙', '粶', '菉', '蝝', '蠡', '褖', '逯',
@@ -2993,6 +3073,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '及',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &[
             '伋', '及', '吸', '圾', '岌', '扱', '极', '汲', '笈', '級', '芨', '衱', '訯', '鈒',
             '靸', '馺', '魥',
@@ -3001,11 +3082,13 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '久',
         strokes: 3,
+        alternate: Alternate::None,
         kanji: &['久', '柩', '灸', '玖', '畝', '疚', '粂', '镹'],
     },
     Membership {
         radical: '日',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '乹', '乾', '亘', '亯', '亰', '亶', '伷', '伸', '但', '侚', '便', '倎', '倝', '借',
             '倡', '倬', '倲', '偃', '偆', '偈', '偒', '偖', '偟', '偣', '傆', '傖', '傷', '傹',
@@ -3107,6 +3190,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '木',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '休', '体', '來', '侏', '保', '俰', '倮', '倲', '偢', '傑', '傔', '僺', '儝', '凍',
             '凓', '凩', '刹', '刺', '剌', '剿', '劘', '勅', '勑', '勦', '厤', '厯', '呆', '味',
@@ -3201,6 +3285,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '月',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '佾', '侑', '俏', '俼', '倄', '倗', '倩', '偁', '偂', '偦', '偸', '傰', '僩', '儬',
             '儱', '兪', '冐', '冑', '冕', '刖', '削', '前', '剪', '勝', '勶', '匬', '厭', '厴',
@@ -3255,6 +3340,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺣',
         strokes: 4,
+        alternate: Alternate::Glyph('灬'),
         kanji: &[
             '偽', '僞', '儘', '儵', '儻', '冩', '凞', '勲', '勳', '叅', '喣', '嗚', '嘸', '嘿',
             '噍', '嚕', '嚥', '囌', '塢', '塨', '墌', '墨', '墲', '壎', '壗', '壥', '媽', '嫣',
@@ -3307,6 +3393,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '王',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '丟', '主', '任', '住', '佯', '佺', '侹', '偟', '傞', '僐', '僕', '儀', '全', '再',
             '凰', '劷', '劻', '匡', '匩', '叢', '呈', '咩', '哢', '善', '喤', '嗟', '噠', '噩',
@@ -3346,6 +3433,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '止',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '丐', '些', '企', '佂', '佌', '佱', '促', '倵', '凪', '劌', '卸', '呰', '唌', '啣',
             '嘴', '噛', '噦', '嚙', '嚦', '嚬', '囓', '址', '埏', '堽', '壢', '夒', '夔', '奯',
@@ -3377,6 +3465,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '心',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '伈', '佖', '倊', '偬', '偲', '傯', '僾', '億', '儜', '優', '勰', '勴', '厯', '唸',
             '噁', '噫', '噯', '嚀', '埝', '妼', '媳', '嬡', '孞', '宓', '密', '寍', '寗', '寧',
@@ -3407,6 +3496,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '戈',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '伐', '俄', '僟', '儀', '儎', '减', '划', '剗', '劌', '咸', '哉', '哦', '喊', '嘎',
             '嘰', '噦', '嚱', '國', '垡', '城', '域', '堿', '奯', '娀', '威', '娍', '娥', '媙',
@@ -3433,6 +3523,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '火',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '伙', '倐', '倓', '偢', '儝', '剡', '勞', '啖', '啾', '塋', '嫈', '嵤', '嶸', '恢',
             '惔', '愀', '愁', '憥', '掞', '揪', '撈', '棪', '楸', '榮', '樮', '檆', '檾', '欻',
@@ -3457,6 +3548,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '攵',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '修', '倏', '倣', '做', '傚', '傲', '僌', '僘', '儆', '儌', '儵', '儼', '冬', '処',
             '務', '勶', '厫', '厰', '厳', '各', '啓', '嗷', '噉', '噋', '噭', '嚴', '坆', '墩',
@@ -3479,6 +3571,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '爪',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '乳', '乿', '亂', '俀', '俘', '偁', '傒', '傜', '僞', '僢', '僾', '凈', '受', '嗘',
             '噯', '嚼', '埒', '埰', '墾', '奚', '奨', '妥', '娞', '婇', '婬', '媛', '媱', '嬀',
@@ -3498,6 +3591,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '曰',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '亯', '倝', '倬', '偒', '傹', '傽', '凕', '勆', '匫', '噆', '墇', '嫽', '嵑', '嶂',
             '嶛', '幜', '廜', '廥', '戠', '掉', '揎', '撦', '斮', '旵', '旾', '旿', '昍', '昒',
@@ -3514,6 +3608,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '方',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '仿', '倣', '傍', '傲', '儌', '厫', '唹', '嗷', '嗾', '噭', '圀', '坊', '堃', '妨',
             '嵭', '廒', '彷', '徬', '徼', '慠', '懯', '房', '揓', '搒', '撽', '放', '敖', '敫',
@@ -3530,6 +3625,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '水',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '俅', '冰', '函', '剥', '劚', '厡', '呇', '咏', '嘯', '嚟', '囦', '埭', '尿', '巹',
             '康', '彔', '怺', '慷', '拯', '捄', '捸', '救', '昶', '昹', '暴', '曃', '曝', '柡',
@@ -3546,6 +3642,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '斤',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '丘', '乴', '伒', '俽', '偱', '儨', '兵', '凘', '劕', '劤', '励', '匠', '厮', '后',
             '听', '哲', '唽', '啠', '嘶', '噺', '圻', '坵', '坼', '垢', '垽', '塹', '壍', '娎',
@@ -3562,6 +3659,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '比',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '仳', '偕', '儳', '儷', '劖', '吡', '喈', '坒', '堦', '塵', '妣', '媲', '孋', '屁',
             '崑', '巉', '庇', '彲', '悂', '批', '揩', '摝', '攈', '攙', '攟', '攦', '昆', '曬',
@@ -3577,6 +3675,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '欠',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '佽', '俽', '劂', '厥', '吹', '咨', '嗽', '坎', '姿', '崁', '嵌', '嶔', '廞', '忺',
             '恣', '惞', '慾', '憠', '懿', '掀', '撅', '撳', '杴', '栨', '樕', '橛', '欠', '次',
@@ -3591,6 +3690,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '殳',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '匓', '墢', '墼', '壂', '媻', '幋', '廏', '廢', '彀', '役', '愨', '慇', '投', '搬',
             '摋', '撃', '擊', '椴', '榖', '槃', '殳', '殴', '段', '殷', '殸', '殹', '殺', '殻',
@@ -3604,6 +3704,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '氏',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '低', '儕', '冺', '劑', '厎', '呧', '嚌', '坻', '奃', '姄', '婚', '岷', '岻', '帋',
             '底', '弤', '彽', '忯', '怟', '惛', '惽', '愍', '扺', '抵', '抿', '擠', '敃', '昏',
@@ -3617,6 +3718,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '牛',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '件', '侏', '侔', '制', '剷', '劺', '吽', '嚳', '墀', '嶰', '廨', '恈', '惣', '懈',
             '掣', '朱', '株', '桙', '樨', '檞', '殊', '洙', '淛', '澔', '澥', '牛', '牜', '牝',
@@ -3630,6 +3732,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺹',
         strokes: 4,
+        alternate: Alternate::Image("js05"),
         kanji: &[
             '佬', '侾', '偖', '儲', '咾', '哮', '嗜', '堵', '墸', '奢', '奲', '姥', '孝', '宯',
             '屠', '帾', '庨', '廜', '拷', '搘', '撦', '教', '暑', '曙', '栲', '栳', '楮', '櫧',
@@ -3642,6 +3745,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '勿',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '偒', '偬', '傷', '刎', '剔', '勿', '匆', '匫', '吻', '囫', '圽', '埸', '場', '塲',
             '忽', '怱', '惕', '惚', '惣', '愓', '愡', '慯', '掦', '揔', '揚', '昒', '易', '昜',
@@ -3654,6 +3758,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺭',
         strokes: 4,
+        alternate: Alternate::Glyph('礻'),
         kanji: &[
             '杯', '榊', '礻', '礼', '礽', '社', '礿', '祀', '祁', '祄', '祅', '祆', '祇', '祈',
             '祉', '祊', '祋', '祏', '祐', '祑', '祓', '祔', '祕', '祖', '祗', '祚', '祛', '祜',
@@ -3666,6 +3771,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '爿',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '乖', '乘', '冀', '剩', '北', '匨', '嚥', '埀', '墏', '壮', '壯', '奘', '奨', '奫',
             '奬', '妝', '娤', '婣', '寐', '寝', '寢', '寤', '寱', '将', '將', '嶈', '弉', '彇',
@@ -3678,6 +3784,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '戸',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '偏', '傓', '傖', '僱', '匾', '唳', '啓', '妒', '徧', '悷', '惼', '愴', '戸', '戹',
             '戻', '戽', '房', '所', '扁', '扃', '扆', '扇', '扈', '扉', '捩', '掮', '搶', '撝',
@@ -3690,6 +3797,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '犬',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '伏', '倏', '厭', '厴', '吠', '哭', '嗅', '嚈', '囐', '壓', '宊', '嶽', '悷', '憖',
             '懕', '揬', '撚', '擪', '栿', '棙', '橪', '檿', '殠', '洑', '漭', '然', '燃', '犬',
@@ -3701,6 +3809,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '歹',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '例', '冽', '列', '劽', '咧', '塟', '夙', '姴', '屍', '歹', '死', '歽', '歾', '歿',
             '殀', '殂', '殃', '殄', '殅', '殆', '殉', '殊', '残', '殍', '殕', '殖', '殗', '殘',
@@ -3712,6 +3821,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '支',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '伎', '傁', '叏', '叓', '叟', '吱', '嘙', '妓', '屐', '岐', '帔', '庋', '庪', '廋',
             '弢', '忮', '技', '搜', '支', '攱', '枝', '柀', '歧', '溲', '瘦', '皷', '皽', '瞍',
@@ -3722,6 +3832,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '巴',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '俋', '吧', '唈', '夿', '岊', '巴', '巵', '廱', '悒', '扈', '把', '挹', '杷', '栬',
             '梔', '浥', '淝', '滬', '灉', '灔', '爬', '爸', '琶', '疤', '癰', '皅', '笆', '簄',
@@ -3732,6 +3843,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '手',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '俄', '儀', '劧', '峨', '我', '手', '承', '拏', '拲', '拳', '拿', '挈', '挐', '挙',
             '掌', '掔', '掣', '揅', '搴', '搿', '摩', '摯', '撃', '撑', '擊', '擎', '擘', '擥',
@@ -3742,6 +3854,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '元',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '元', '兘', '冠', '冦', '刓', '唍', '园', '垸', '完', '寇', '岏', '忨', '抏', '杬',
             '梡', '沅', '玩', '皖', '盶', '睆', '筦', '簆', '籞', '羦', '翫', '脘', '芫', '莞',
@@ -3751,6 +3864,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '父',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '交', '佼', '俲', '傚', '効', '咬', '姣', '恔', '挍', '效', '斧', '校', '洨', '滏',
             '父', '爸', '爹', '爺', '狡', '珓', '皎', '窔', '絞', '纐', '胶', '茭', '虠', '蛟',
@@ -3760,6 +3874,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '爻',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '嚳', '壐', '嬭', '彌', '攀', '斅', '棥', '樊', '濔', '瀰', '燓', '爻', '爼', '爾',
             '獮', '獼', '璽', '礐', '礟', '礬', '禰', '穪', '薾', '蠒', '蠜', '襻', '覐', '覺',
@@ -3769,6 +3884,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '文',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '刘', '剤', '吝', '対', '彣', '忞', '悋', '憫', '文', '斈', '斉', '斊', '斌', '斎',
             '斐', '斑', '旻', '汶', '済', '炆', '玟', '紊', '紋', '緕', '芠', '萕', '虔', '蚊',
@@ -3778,6 +3894,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '毋',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '侮', '勄', '塰', '姆', '娒', '悔', '慣', '拇', '敏', '晦', '栂', '梅', '毋', '母',
             '毎', '毒', '毓', '海', '珻', '瑇', '痗', '碡', '繁', '纛', '苺', '莓', '蘩', '袰',
@@ -3787,6 +3904,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '片',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '奫', '婣', '彇', '沜', '潚', '片', '版', '牋', '牌', '牎', '牏', '牐', '牒', '牓',
             '牕', '牖', '牘', '繡', '肅', '蠨', '鏽', '驌', '魸', '鱐', '鷫', '鼎', '鼏', '鼐',
@@ -3796,6 +3914,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '斗',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '図', '嵙', '戽', '抖', '斗', '斘', '料', '斛', '斜', '斝', '斟', '斠', '斡', '斣',
             '枓', '槲', '槹', '櫆', '科', '紏', '萪', '蚪', '蝌', '酙', '鈄', '鬦', '魁',
@@ -3804,6 +3923,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '五',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '五', '伍', '俉', '吾', '唔', '圄', '娪', '寤', '忢', '悟', '捂', '敔', '晤', '梧',
             '浯', '牾', '珸', '窹', '絚', '緪', '衙', '語', '鋙', '鼯', '齬',
@@ -3812,6 +3932,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '屯',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '吨', '噸', '囤', '屯', '忳', '扽', '旾', '杶', '沌', '炖', '瓲', '盹', '窀', '純',
             '肫', '芚', '蒓', '豘', '迍', '邨', '鈍', '頓', '飩', '魨',
@@ -3820,6 +3941,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '井',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '丼', '井', '儺', '刱', '囲', '寒', '巷', '恭', '攤', '暴', '曩', '港', '爆', '畊',
             '異', '穽', '糞', '翼', '耕', '襄', '驤',
@@ -3828,6 +3950,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '无',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '僭', '嘅', '噆', '墍', '妋', '廐', '悞', '摡', '无', '旡', '曁', '漑', '潛', '灊',
             '炁', '簪', '蔇', '蠶', '譖', '鐕', '鬵',
@@ -3836,6 +3959,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '尤',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '僦', '厖', '尤', '尨', '就', '嵆', '巘', '庬', '牻', '犹', '疣', '稽', '肬', '蚘',
             '諬', '蹴', '鑙', '魷', '鷲',
@@ -3844,6 +3968,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '毛',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '娓', '尾', '旄', '梶', '橇', '毛', '毟', '毫', '毬', '毯', '毳', '氈', '瓱', '竓',
             '粍', '耄', '耗', '髦', '麾',
@@ -3852,6 +3977,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '气',
         strokes: 4,
+        alternate: Alternate::None,
         kanji: &[
             '愾', '气', '気', '氙', '氛', '氟', '氣', '氤', '氦', '氧', '氨', '氬', '氮', '氳',
             '汽', '滊', '餼',
@@ -3860,6 +3986,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '田',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '亀', '伷', '伸', '佃', '侽', '便', '俜', '俾', '倮', '偎', '偪', '偲', '偶', '傀',
             '傅', '傳', '僤', '僧', '僵', '儡', '儣', '兤', '兾', '冀', '冑', '冨', '凍', '副',
@@ -3929,6 +4056,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '目',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '且', '亹', '伹', '俎', '倛', '値', '倶', '偗', '偱', '側', '偵', '傄', '傊', '傎',
             '債', '傾', '僓', '僨', '價', '儐', '償', '儧', '儨', '儬', '儭', '儹', '具', '冒',
@@ -3994,6 +4122,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '立',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '乵', '位', '倍', '倖', '偐', '偙', '偣', '傍', '傡', '傹', '傽', '僮', '僻', '億',
             '儭', '儱', '剖', '剷', '劈', '勏', '咅', '唼', '商', '啦', '啻', '啼', '喑', '噫',
@@ -4025,6 +4154,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '禾',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '乗', '乘', '俐', '俰', '倭', '偢', '傔', '凜', '利', '剩', '厤', '叙', '和', '唀',
             '唎', '啝', '啾', '嚟', '嚦', '囌', '囷', '壈', '壢', '奏', '委', '季', '峲', '崣',
@@ -4050,6 +4180,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⺲',
         strokes: 5,
+        alternate: Alternate::Glyph('罒'),
         kanji: &[
             '儇', '儚', '儜', '儸', '劅', '劚', '匵', '噣', '嚀', '嚼', '囉', '囑', '圛', '圜',
             '堽', '墁', '壊', '壞', '夢', '夣', '嫚', '嬛', '寧', '寰', '屬', '嶧', '幔', '幭',
@@ -4071,6 +4202,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '白',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '伯', '佰', '偕', '偟', '傆', '儌', '優', '兜', '凮', '凰', '劰', '原', '厡', '厵',
             '咟', '唕', '啤', '喈', '喤', '嘅', '噭', '堦', '堭', '堲', '墍', '奭', '媓', '嫄',
@@ -4092,6 +4224,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '石',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '劯', '喏', '坧', '妬', '宕', '岩', '拓', '斫', '暱', '柘', '槖', '橐', '沰', '石',
             '矴', '矸', '矻', '矼', '砂', '砅', '砆', '砉', '砌', '砍', '砎', '砑', '砒', '研',
@@ -4112,6 +4245,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '皿',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '侐', '儖', '儘', '卹', '嗌', '嗑', '嗢', '嚀', '嚧', '塧', '塩', '壒', '壗', '壚',
             '媼', '孟', '寍', '寧', '尶', '廬', '恤', '慍', '懢', '懧', '搤', '搵', '擰', '敟',
@@ -4131,6 +4265,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⽧',
         strokes: 5,
+        alternate: Alternate::Glyph('疒'),
         kanji: &[
             '嫉', '愱', '疒', '疓', '疔', '疕', '疙', '疚', '疜', '疝', '疢', '疣', '疤', '疥',
             '疫', '疱', '疲', '疳', '疴', '疵', '疸', '疹', '疺', '疼', '疽', '疾', '疿', '痀',
@@ -4148,6 +4283,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⻂',
         strokes: 5,
+        alternate: Alternate::Glyph('衤'),
         kanji: &[
             '初', '衤', '衩', '衫', '衱', '衲', '衵', '衹', '衻', '衽', '衿', '袀', '袂', '袍',
             '袒', '袖', '袗', '袘', '袙', '袚', '袛', '袜', '袟', '袢', '袨', '袪', '被', '袮',
@@ -4163,6 +4299,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '矢',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '侯', '俟', '倁', '候', '儗', '凝', '勧', '医', '唉', '喉', '嗾', '垁', '埃', '堠',
             '娭', '嫉', '嶷', '彘', '悘', '愱', '懝', '挨', '擬', '族', '昳', '智', '椥', '榘',
@@ -4176,6 +4313,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '示',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '佘', '余', '俆', '倧', '傺', '僄', '凛', '剽', '勡', '叞', '呩', '噤', '奈', '嫖',
             '孮', '宗', '察', '尉', '崇', '幖', '彯', '徖', '徱', '悆', '悰', '慓', '慰', '捺',
@@ -4189,6 +4327,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '甘',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '倛', '其', '凘', '剘', '勘', '卙', '厮', '咁', '啿', '嘶', '坩', '基', '堪', '墈',
             '媒', '尠', '嵌', '廝', '愖', '憇', '戡', '拑', '揕', '撕', '斟', '斯', '旗', '朞',
@@ -4201,6 +4340,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '疋',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '丐', '倢', '偦', '儗', '凝', '匙', '嚏', '嚔', '埞', '堤', '壻', '婿', '媞', '定',
             '寔', '尟', '嶷', '従', '徙', '從', '慫', '懝', '懥', '捷', '掟', '提', '擬', '旋',
@@ -4213,6 +4353,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '用',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '佣', '俌', '俑', '備', '傭', '僃', '勈', '匍', '哺', '嚩', '圃', '埇', '埔', '墉',
             '庸', '恿', '悑', '愑', '慂', '慵', '憊', '懯', '捔', '捕', '斠', '晡', '桶', '樋',
@@ -4224,6 +4365,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '矛',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '劀', '務', '壄', '妤', '婺', '忬', '愗', '懋', '抒', '揉', '杼', '柔', '楙', '楺',
             '橘', '櫲', '氄', '潏', '澦', '猱', '獝', '璚', '瞀', '矛', '矜', '矞', '矟', '矠',
@@ -4235,6 +4377,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '⽱',
         strokes: 5,
+        alternate: Alternate::Glyph('禸'),
         kanji: &[
             '偊', '偶', '勱', '勵', '厲', '喁', '嘱', '寓', '属', '嵎', '愚', '摛', '擒', '檎',
             '櫔', '漓', '灕', '瑀', '璃', '癘', '礪', '禑', '禸', '禹', '禺', '离', '禽', '竊',
@@ -4245,6 +4388,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '冊',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '侖', '倎', '倫', '偏', '冊', '刪', '匾', '圇', '姍', '崘', '崙', '徧', '惼', '扁',
             '掄', '斒', '柵', '棆', '淪', '瀹', '爚', '猵', '珊', '癟', '禴', '笧', '篇', '籥',
@@ -4255,6 +4399,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '穴',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '倥', '啌', '坹', '容', '悾', '控', '搾', '椌', '榕', '溶', '熔', '穃', '穴', '究',
             '穹', '空', '穽', '穿', '突', '窃', '窄', '窈', '窒', '窓', '窕', '窖', '窗', '窘',
@@ -4265,6 +4410,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '生',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '夝', '姓', '嶐', '徃', '性', '惺', '旌', '星', '暒', '殅', '泩', '滻', '煋', '牲',
             '狌', '猩', '瑆', '生', '甠', '甡', '産', '甤', '甥', '甦', '甧', '癃', '眚', '睲',
@@ -4275,6 +4421,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '玄',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '侅', '呟', '奒', '姟', '弦', '慉', '搐', '摔', '昡', '欬', '氦', '泫', '滀', '炫',
             '玄', '玅', '玆', '率', '玹', '畜', '畡', '痃', '痎', '眩', '絃', '胘', '胲', '舷',
@@ -4284,6 +4431,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '世',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '世', '伳', '勩', '呭', '喋', '堞', '媟', '屜', '屧', '幉', '弽', '惵', '揲', '枻',
             '枼', '楪', '泄', '渫', '煠', '牃', '牒', '碟', '笹', '紲', '緤', '葉', '蝶', '詍',
@@ -4293,6 +4441,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '母',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '侮', '勄', '塰', '姆', '娒', '悔', '慣', '拇', '敏', '晦', '栂', '梅', '毋', '母',
             '毎', '毒', '毓', '海', '珻', '瑇', '痗', '碡', '繁', '纛', '苺', '莓', '蘩', '袰',
@@ -4302,6 +4451,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '癶',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '僜', '凳', '噔', '墢', '墱', '嬁', '察', '嶝', '廃', '廢', '戣', '揆', '撥', '擦',
             '暌', '橙', '櫈', '溌', '澄', '燈', '癈', '癶', '癸', '発', '登', '發', '磴', '祭',
@@ -4311,6 +4461,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '牙',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '冴', '厩', '呀', '嘅', '墍', '姊', '岈', '慨', '捓', '撐', '既', '柹', '概', '橕',
             '牙', '牚', '琊', '砑', '穿', '竆', '笫', '芽', '蚜', '訝', '谺', '迓', '邪', '鋣',
@@ -4320,6 +4471,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '皮',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '坡', '婆', '彼', '披', '波', '玻', '疲', '皮', '皰', '皴', '皷', '皸', '皹', '皺',
             '破', '碆', '簸', '菠', '被', '跛', '陂', '鞁', '頗',
@@ -4328,6 +4480,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '瓦',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &[
             '瓦', '瓧', '瓩', '瓮', '瓰', '瓱', '瓲', '瓶', '瓷', '瓸', '甃', '甄', '甅', '甌',
             '甍', '甎', '甑', '甓', '甕',
@@ -4336,11 +4489,13 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '巨',
         strokes: 5,
+        alternate: Alternate::None,
         kanji: &['巨', '拒', '渠', '炬', '矩', '秬', '苣', '距', '鉅'],
     },
     Membership {
         radical: '虫',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '偊', '偑', '僓', '儙', '剸', '劅', '劚', '勥', '嗤', '嘳', '噣', '囑', '圚', '壝',
             '媸', '嫥', '專', '尵', '屬', '嵹', '庽', '強', '惠', '慅', '慱', '憒', '憓', '掻',
@@ -4375,6 +4530,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '糸',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '乿', '係', '傃', '儸', '勬', '卛', '喲', '嗉', '噄', '嚩', '囉', '圝', '塐', '孌',
             '孫', '孿', '巒', '彎', '彝', '徽', '愫', '懸', '戀', '攞', '攣', '曫', '榡', '樏',
@@ -4409,6 +4565,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '竹',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '噬', '撘', '擌', '擶', '檱', '櫛', '櫤', '澨', '癤', '竹', '竺', '竻', '竽', '竾',
             '竿', '笂', '笄', '笆', '笇', '笈', '笊', '笋', '笏', '笑', '笔', '笘', '笙', '笛',
@@ -4433,6 +4590,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '米',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '侎', '僯', '匊', '咪', '嘯', '噃', '噛', '噢', '囓', '墦', '墺', '奥', '奧', '娄',
             '婅', '嬸', '宩', '宷', '審', '屎', '屡', '嶓', '嶙', '嶴', '幡', '彛', '彜', '彝',
@@ -4455,6 +4613,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '衣',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '依', '侲', '俵', '偎', '儂', '儇', '儴', '勷', '哀', '唇', '喂', '喪', '嚢', '嚷',
             '囅', '囊', '園', '圜', '壊', '壌', '壞', '壤', '娠', '嬛', '嬝', '嬢', '孃', '宸',
@@ -4474,6 +4633,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '耳',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '倻', '儼', '冣', '刵', '厳', '取', '叢', '咡', '嘬', '噉', '嚴', '囁', '埾', '娵',
             '娶', '巌', '巖', '廰', '廳', '弭', '恥', '憨', '懾', '戢', '掫', '揖', '揶', '摂',
@@ -4490,6 +4650,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '臼',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '倪', '傁', '兒', '冩', '叟', '唲', '啗', '嚳', '堄', '夓', '學', '寫', '嶼', '帠',
             '庾', '廋', '惂', '惥', '慆', '憃', '掐', '揑', '插', '搜', '搯', '摏', '擧', '攛',
@@ -4505,6 +4666,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '羽',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '傓', '傟', '僇', '剹', '勜', '勠', '嘐', '噏', '塌', '塕', '嫪', '嬥', '寥', '嵡',
             '嵺', '廖', '廫', '慴', '憀', '戮', '扇', '挧', '搨', '摎', '摺', '栩', '榻', '槢',
@@ -4520,6 +4682,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '羊',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '佯', '傞', '僐', '僕', '儀', '劷', '叢', '咩', '善', '嗟', '噠', '垟', '墡', '姜',
             '對', '嵯', '嵳', '嶫', '嶬', '差', '幞', '庠', '徉', '恙', '懟', '搓', '撲', '撻',
@@ -4535,6 +4698,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '虍',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '俿', '劇', '勴', '嗁', '嘑', '嘘', '噱', '嚧', '嚱', '囐', '墟', '壚', '巇', '巘',
             '廬', '彪', '慮', '戯', '戲', '摣', '擄', '據', '攄', '樝', '櫖', '櫨', '歔', '滮',
@@ -4549,6 +4713,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '西',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '価', '偠', '僄', '僊', '僲', '價', '凓', '剽', '勡', '哂', '喓', '噂', '垔', '堙',
             '嫖', '幖', '廼', '弻', '彯', '徱', '慄', '慓', '憟', '拪', '摽', '晒', '栖', '栗',
@@ -4562,6 +4727,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '艮',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '佷', '俍', '匓', '即', '卿', '厩', '喞', '嚮', '嚼', '垠', '埌', '墾', '娘', '廊',
             '廏', '廐', '很', '恨', '悢', '慨', '懇', '懩', '攁', '既', '曁', '朖', '朗', '根',
@@ -4574,6 +4740,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '自',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '偱', '優', '劓', '厦', '咱', '嗄', '嗅', '嘎', '嘷', '噵', '嚊', '夏', '夒', '夓',
             '夔', '奡', '媳', '嬶', '寡', '寱', '導', '巎', '巙', '廈', '息', '憂', '憩', '戛',
@@ -4586,6 +4753,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '舟',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '坍', '媻', '幋', '彤', '搬', '槃', '瘢', '盤', '矪', '磐', '舟', '舡', '舢', '舨',
             '舩', '航', '舫', '般', '舮', '舲', '舳', '舴', '舵', '舶', '舷', '舸', '船', '舺',
@@ -4597,6 +4765,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '而',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '儒', '喘', '嚅', '圌', '堧', '壖', '嬬', '孺', '恧', '惴', '愞', '懦', '揣', '擩',
             '栭', '檽', '洏', '湍', '濡', '煗', '燸', '猯', '瑞', '碝', '端', '篅', '粫', '糯',
@@ -4608,6 +4777,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '聿',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '侓', '健', '傭', '冿', '劃', '嘯', '圕', '壗', '嵂', '庸', '建', '律', '徤', '慵',
             '揵', '旔', '晝', '書', '楗', '津', '滽', '燼', '牅', '犍', '珒', '畫', '盡', '筆',
@@ -4618,6 +4788,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '缶',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '傜', '匋', '媱', '寚', '寶', '徭', '掏', '掣', '搖', '旆', '暚', '淘', '猺', '瑤',
             '瑶', '祹', '窑', '窰', '綯', '繇', '缶', '缸', '缺', '缻', '缼', '缽', '缾', '缿',
@@ -4628,6 +4799,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '至',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '倒', '偓', '到', '厔', '咥', '喔', '垤', '姪', '室', '屋', '幄', '庢', '挃', '握',
             '擡', '晊', '桎', '椡', '榁', '檯', '渥', '窒', '絰', '緻', '耊', '耋', '腟', '膣',
@@ -4638,6 +4810,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '行',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '哘', '垳', '愆', '桁', '珩', '筕', '絎', '荇', '行', '衍', '衎', '衑', '衒', '術',
             '衕', '衖', '街', '衘', '衙', '衚', '衛', '衜', '衝', '衞', '衟', '衠', '衡', '衢',
@@ -4647,6 +4820,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '舌',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '乱', '佸', '刮', '恬', '憇', '憩', '括', '栝', '活', '湉', '濶', '甜', '筈', '聒',
             '舌', '舍', '舏', '舐', '舒', '舓', '舔', '舖', '舙', '舚', '蛞', '話', '辞', '适',
@@ -4656,6 +4830,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '耒',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '棅', '業', '籍', '耒', '耓', '耔', '耕', '耖', '耗', '耘', '耙', '耜', '耝', '耞',
             '耟', '耠', '耡', '耤', '耦', '耨', '耬', '耮', '耰', '藉', '藕', '誄',
@@ -4664,6 +4839,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '瓜',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '呱', '孤', '寙', '弧', '柧', '泒', '狐', '瓜', '瓞', '瓟', '瓠', '瓢', '瓣', '瓤',
             '窊', '窳', '笟', '箛', '罛', '胍', '苽', '菰', '蓏', '觚', '軱',
@@ -4672,6 +4848,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '血',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '侐', '卹', '恤', '洫', '血', '衁', '衂', '衃', '衄', '衅', '衆', '衈', '衉', '衊',
             '衋', '賉',
@@ -4680,6 +4857,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '色',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &[
             '栬', '灔', '絶', '脃', '色', '艴', '艶', '艷', '蕝', '赩', '銫', '鑱', '饞',
         ],
@@ -4687,11 +4865,13 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '肉',
         strokes: 6,
+        alternate: Alternate::None,
         kanji: &['哊', '瘸', '肉', '胔', '胾', '腐', '膐', '臋', '臠', '臡'],
     },
     Membership {
         radical: '貝',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '側', '偵', '傊', '債', '傾', '僓', '僨', '價', '儐', '償', '儧', '儨', '儬', '儹',
             '則', '劕', '劗', '勛', '勣', '勩', '匱', '匵', '厠', '員', '唄', '嗩', '嗿', '嘖',
@@ -4729,6 +4909,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '言',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '信', '傠', '儋', '儲', '卛', '唁', '噡', '圁', '圝', '孌', '孿', '嶽', '巒', '幨',
             '彎', '悥', '憺', '戀', '揈', '擔', '攣', '曫', '檐', '櫧', '欒', '渹', '滸', '澹',
@@ -4761,6 +4942,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '車',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '俥', '偅', '僌', '儎', '匭', '厙', '喗', '嗹', '囀', '埵', '堹', '塹', '塼', '墼',
             '壍', '壎', '尰', '嶃', '嶄', '庫', '惲', '慙', '慚', '懂', '揮', '撃', '擊', '斬',
@@ -4781,6 +4963,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '足',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '促', '捉', '攓', '浞', '潞', '珿', '璐', '簬', '蕗', '虂', '足', '趵', '趷', '趹',
             '趺', '趻', '趼', '趾', '跀', '跂', '跅', '跆', '跇', '跈', '跊', '跋', '跌', '跎',
@@ -4799,6 +4982,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '豆',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '僖', '僜', '凒', '凱', '凳', '剅', '剴', '厨', '喜', '嘻', '噎', '噔', '嚭', '嚱',
             '囍', '囏', '塏', '墱', '壹', '嬁', '嬉', '尌', '嶝', '巇', '幮', '廚', '彭', '愷',
@@ -4815,6 +4999,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '酉',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '僔', '墫', '奠', '尊', '嶟', '撙', '擲', '梄', '楢', '槱', '樽', '歠', '猶', '猷',
             '盦', '竴', '罇', '蓜', '蕕', '蘸', '蝤', '蹲', '躑', '輏', '輶', '逎', '遒', '遵',
@@ -4830,6 +5015,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '豕',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '傢', '像', '冡', '冢', '劇', '勨', '啄', '喙', '噱', '圂', '堟', '塚', '墜', '壕',
             '嫁', '家', '幪', '彖', '慁', '懞', '掾', '據', '曚', '朦', '椓', '椽', '橡', '檬',
@@ -4844,6 +5030,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '里',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '俚', '僮', '儵', '儻', '動', '厘', '哩', '嗶', '嘿', '埋', '墅', '墨', '壥', '娌',
             '幢', '廛', '徸', '悝', '憧', '戃', '撞', '攩', '曈', '梩', '榸', '橦', '浬', '滭',
@@ -4858,6 +5045,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '辛',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '乵', '倖', '僻', '劈', '噺', '圉', '執', '報', '壁', '嬖', '孼', '孽', '宰', '幸',
             '懌', '摯', '撻', '擇', '擗', '擘', '新', '梓', '檗', '櫱', '滓', '澤', '澼', '璧',
@@ -4870,6 +5058,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '見',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '俔', '儭', '哯', '嚫', '夔', '寛', '峴', '悓', '撹', '攪', '攬', '晛', '梘', '槻',
             '櫬', '欖', '欟', '現', '睍', '硯', '窺', '筧', '絸', '纜', '臗', '莧', '虁', '蜆',
@@ -4881,6 +5070,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '臣',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '儖', '凞', '嚚', '堅', '姫', '媐', '孯', '宦', '尶', '巸', '弫', '弬', '慳', '懢',
             '挋', '掔', '擥', '攬', '朢', '榘', '樫', '檻', '欖', '濫', '煕', '熈', '監', '盬',
@@ -4892,6 +5082,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '角',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '嘴', '埆', '嶰', '廨', '懈', '斛', '桷', '槲', '檞', '澥', '獬', '确', '葪', '薢',
             '蟕', '蟹', '蠏', '角', '觔', '觕', '觖', '觗', '觘', '觚', '觜', '觝', '解', '觥',
@@ -4902,6 +5093,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '走',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '倢', '啑', '婕', '寁', '徒', '徢', '睫', '箑', '縱', '蜨', '走', '赱', '赳', '赴',
             '起', '赿', '趁', '趂', '超', '趈', '越', '趍', '趐', '趑', '趕', '趙', '趞', '趟',
@@ -4911,6 +5103,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '辰',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '侲', '儂', '唇', '娠', '宸', '憹', '振', '敐', '晨', '曟', '槈', '溽', '漘', '濃',
             '禯', '穠', '縟', '耨', '脣', '脤', '膿', '莀', '蓐', '蕽', '薅', '蜃', '蜄', '褥',
@@ -4920,6 +5113,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '舛',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '傑', '僢', '僯', '僲', '儛', '嶙', '憐', '搩', '撛', '斴', '曻', '桀', '桝', '椉',
             '橉', '潾', '燐', '璘', '瞬', '瞵', '磔', '磷', '粦', '粼', '舛', '舜', '舞', '蕣',
@@ -4929,6 +5123,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '谷',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '俗', '傛', '卻', '壑', '壡', '嫆', '容', '峪', '愹', '慾', '榕', '欲', '浴', '溶',
             '熔', '瑢', '硲', '穃', '綌', '蓉', '裕', '谷', '谸', '谹', '谺', '谽', '谾', '谿',
@@ -4938,6 +5133,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '釆',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '墺', '奧', '審', '幡', '悉', '懊', '播', '旙', '旛', '潘', '澳', '瀋', '燔', '燠',
             '番', '礇', '竊', '粤', '繙', '翻', '膰', '蕃', '藩', '蟋', '蟠', '襖', '釆', '釈',
@@ -4947,6 +5143,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '豸',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '墾', '懇', '薶', '藐', '豸', '豹', '豺', '豻', '豼', '豾', '貂', '貅', '貆', '貇',
             '貉', '貊', '貋', '貌', '貍', '貎', '貐', '貒', '貓', '貔', '貘', '貙', '貛', '貜',
@@ -4956,6 +5153,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '身',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '匑', '射', '窮', '藭', '裑', '謝', '身', '躬', '躭', '躮', '躯', '躰', '躱', '躳',
             '躵', '躺', '躻', '躾', '軀', '軁', '軃', '軄', '軅', '軆', '軇', '軈', '鵢', '麝',
@@ -4964,6 +5162,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '赤',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &[
             '嚇', '奕', '弯', '焃', '爀', '繊', '螫', '赤', '赥', '赦', '赧', '赩', '赫', '赬',
             '赭', '赮', '趄', '跡', '迹', '郝', '頳',
@@ -4972,11 +5171,13 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '麦',
         strokes: 7,
+        alternate: Alternate::None,
         kanji: &['麦', '麸', '麹', '麺'],
     },
     Membership {
         radical: '金',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '劉', '嚠', '崟', '嶔', '廞', '捦', '撳', '欽', '淦', '瀏', '瘹', '籙', '籛', '金',
             '釓', '釔', '釖', '釗', '釘', '釙', '釚', '釛', '釜', '針', '釞', '釟', '釡', '釣',
@@ -5015,6 +5216,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '隹',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '催', '僱', '儁', '儸', '儺', '准', '凖', '勧', '勸', '匯', '售', '唯', '噍', '嚄',
             '嚾', '囃', '囉', '堆', '墔', '壅', '奞', '奪', '奮', '嫶', '嬥', '寉', '寯', '崔',
@@ -5040,6 +5242,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '門',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '們', '僩', '問', '墹', '嫺', '嫻', '悶', '憪', '憫', '捫', '擱', '攔', '斕', '椚',
             '櫚', '欄', '欗', '潤', '澖', '澗', '濶', '瀾', '熌', '燗', '燜', '爓', '爛', '爤',
@@ -5056,6 +5259,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '雨',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '儒', '嚅', '壖', '壜', '壩', '嫮', '嬬', '孀', '孁', '孺', '屚', '懦', '摴', '擂',
             '擩', '攉', '曇', '樗', '樰', '橒', '檑', '檽', '櫺', '欛', '欞', '漏', '澐', '澪',
@@ -5071,6 +5275,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '長',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '倀', '套', '帳', '張', '悵', '棖', '漲', '痮', '粻', '肆', '脹', '萇', '蕻', '賬',
             '鋹', '長', '韔', '餦', '髟', '髠', '髢', '髣', '髤', '髥', '髦', '髧', '髩', '髪',
@@ -5082,6 +5287,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '非',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '俳', '剕', '劘', '匪', '啡', '徘', '悱', '悲', '扉', '排', '斐', '暃', '棐', '棑',
             '榧', '櫼', '渄', '瀣', '猅', '琲', '痱', '篚', '緋', '罪', '翡', '腓', '菲', '蕜',
@@ -5092,6 +5298,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '青',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '倩', '儬', '圊', '婧', '情', '晴', '棈', '清', '瀞', '猜', '睛', '箐', '精', '綪',
             '菁', '蒨', '蔳', '蜻', '請', '錆', '青', '靕', '靖', '靗', '靘', '静', '靚', '靛',
@@ -5101,6 +5308,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '免',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '俛', '儳', '免', '兔', '冕', '冤', '凂', '劖', '勉', '堍', '娩', '寃', '悗', '挽',
             '攙', '晩', '梚', '欃', '毚', '浼', '絻', '莬', '輓', '逸', '鮸',
@@ -5109,6 +5317,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '隶',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '嘯', '埭', '康', '慷', '捸', '曃', '棣', '碌', '祿', '禄', '穅', '簫', '粛', '糠',
             '緑', '繍', '逮', '録', '隶', '隷', '隸', '靆', '鱇',
@@ -5117,6 +5326,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '奄',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &[
             '俺', '剦', '唵', '奄', '奙', '崦', '掩', '晻', '殗', '淹', '罨', '腌', '菴', '醃',
             '閹', '黤',
@@ -5125,16 +5335,19 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '岡',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &['剛', '堈', '岡', '崗', '棡', '綱', '鋼'],
     },
     Membership {
         radical: '斉',
         strokes: 8,
+        alternate: Alternate::None,
         kanji: &['剤', '斉', '斎', '済', '緕'],
     },
     Membership {
         radical: '頁',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '傾', '噸', '嚬', '囂', '夒', '夓', '嬾', '寘', '嵿', '嶺', '巎', '巓', '廎', '懶',
             '擷', '湏', '潁', '澃', '澒', '澦', '瀕', '瀬', '灝', '煩', '熲', '獺', '癩', '癲',
@@ -5150,6 +5363,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '革',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '勒', '厪', '囏', '墐', '壩', '廑', '慬', '戁', '斳', '暵', '欛', '殣', '漌', '灞',
             '熯', '癱', '緙', '羇', '羈', '臡', '覇', '覉', '覊', '鄞', '霸', '革', '靪', '靫',
@@ -5163,6 +5377,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '音',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '偣', '傹', '傽', '億', '喑', '噫', '境', '嫜', '嶂', '幟', '彰', '意', '憶', '暗',
             '樟', '檍', '歆', '漳', '熾', '獍', '獐', '璋', '瘖', '瘴', '瞕', '竟', '章', '竸',
@@ -5173,6 +5388,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '食',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '喰', '瀁', '癢', '蝕', '食', '飢', '飩', '飫', '飭', '飮', '飯', '飲', '飴', '飼',
             '飽', '飾', '餃', '餅', '餉', '養', '餌', '餐', '餒', '餓', '餔', '餘', '餝', '餞',
@@ -5183,6 +5399,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '風',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '偑', '嵐', '楓', '渢', '瘋', '繭', '諷', '風', '颪', '颫', '颭', '颮', '颯', '颰',
             '颱', '颴', '颶', '颷', '颸', '颺', '颻', '颿', '飂', '飃', '飄', '飅', '飆', '飈',
@@ -5192,6 +5409,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '品',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '傴', '區', '品', '嘔', '噪', '奩', '嫗', '嵒', '嶇', '懆', '操', '樞', '歐', '毆',
             '澡', '燥', '甌', '癌', '繰', '臨', '蕚', '藻', '謳', '譟', '躁', '驅', '髞',
@@ -5200,6 +5418,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '韭',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '孅', '懴', '懺', '殱', '殲', '籖', '籤', '纎', '纖', '薤', '讖', '韭', '韮', '韲',
             '齏',
@@ -5208,6 +5427,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '面',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '偭', '勔', '愐', '湎', '緬', '面', '靤', '靦', '靧', '靨', '麵', '麺',
         ],
@@ -5215,6 +5435,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '首',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &[
             '噵', '夔', '導', '巙', '渞', '艏', '虁', '衜', '道', '首', '馗', '馘',
         ],
@@ -5222,16 +5443,19 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '香',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &['楿', '香', '馥', '馨'],
     },
     Membership {
         radical: '飛',
         strokes: 9,
+        alternate: Alternate::None,
         kanji: &['飛', '飜'],
     },
     Membership {
         radical: '馬',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '儯', '凴', '嗎', '媽', '慿', '憑', '瑪', '碼', '礟', '禡', '篤', '罵', '羈', '螞',
             '覊', '闖', '隲', '颿', '馬', '馭', '馮', '馰', '馱', '馲', '馳', '馴', '馵', '馹',
@@ -5248,6 +5472,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '髟',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '髟', '髠', '髢', '髣', '髤', '髥', '髦', '髧', '髩', '髪', '髫', '髬', '髭', '髮',
             '髯', '髱', '髲', '髳', '髴', '髵', '髷', '髹', '髺', '髻', '髽', '髿', '鬀', '鬁',
@@ -5258,6 +5483,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '骨',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '嗗', '搰', '榾', '滑', '猾', '磆', '蓇', '骨', '骪', '骬', '骭', '骮', '骯', '骰',
             '骲', '骴', '骵', '骶', '骸', '骹', '骻', '骼', '骾', '骿', '髀', '髁', '髃', '髄',
@@ -5267,6 +5493,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鬼',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '傀', '塊', '媿', '嵬', '巍', '廆', '愧', '槐', '櫆', '瑰', '瘣', '磈', '聭', '莵',
             '蒐', '蘶', '醜', '隗', '餽', '鬼', '鬽', '魁', '魂', '魃', '魄', '魅', '魈', '魋',
@@ -5276,6 +5503,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '韋',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '偉', '圍', '幃', '徫', '愇', '暐', '湋', '煒', '瑋', '禕', '緯', '葦', '衛', '褘',
             '諱', '違', '闈', '韋', '韌', '韍', '韎', '韐', '韑', '韓', '韔', '韗', '韘', '韙',
@@ -5285,6 +5513,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '高',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '傐', '嗃', '嚆', '塙', '嵩', '嵪', '搞', '敲', '暠', '槁', '歊', '毃', '滈', '熇',
             '犒', '皜', '碻', '稾', '稿', '篙', '縞', '翯', '膏', '蒿', '藁', '藳', '謞', '鄗',
@@ -5294,6 +5523,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鬲',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '囐', '巘', '槅', '灊', '獻', '瓛', '甗', '翮', '膈', '融', '讞', '鎘', '隔', '鬲',
             '鬳', '鬴', '鬵', '鬷', '鬹', '鬺', '鬻', '鷊',
@@ -5302,6 +5532,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '竜',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &[
             '嚨', '巃', '攏', '曨', '槞', '滝', '礱', '竉', '竜', '篭', '讋', '豅', '鑨', '龏',
             '龐', '龑', '龒', '龔', '龖', '龗',
@@ -5310,16 +5541,19 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鬥',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &['鬥', '鬧', '鬨', '鬩', '鬪', '鬮'],
     },
     Membership {
         radical: '鬯',
         strokes: 10,
+        alternate: Alternate::None,
         kanji: &['鬯', '鬱'],
     },
     Membership {
         radical: '魚',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &[
             '嚕', '囌', '櫓', '漁', '癬', '穌', '穭', '艪', '薊', '蘇', '蘓', '蘚', '魚', '魛',
             '魞', '魡', '魣', '魥', '魦', '魨', '魪', '魫', '魬', '魭', '魮', '魯', '魳', '魴',
@@ -5341,6 +5575,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鳥',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &[
             '嗚', '塢', '嫣', '嬝', '島', '嶋', '嶌', '搗', '梟', '槝', '樢', '歍', '烏', '瑦',
             '窵', '篶', '舃', '蔦', '螐', '裊', '贗', '鄔', '鄥', '隖', '隝', '靎', '靏', '鰞',
@@ -5362,6 +5597,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鹿',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &[
             '儷', '塵', '孋', '彲', '摝', '攈', '攟', '攦', '曬', '樚', '欐', '漉', '灑', '簏',
             '籭', '纚', '臕', '薼', '藨', '躧', '轆', '邐', '鄜', '酈', '釃', '鏖', '鑣', '驪',
@@ -5372,6 +5608,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '黒',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &[
             '儵', '儻', '嘿', '墨', '壥', '戃', '攩', '濹', '纆', '纒', '讜', '黒', '黔', '黕',
             '默', '黙', '黛', '黜', '黝', '點', '黟', '黠', '黤', '黥', '黧', '黨', '黬', '黭',
@@ -5381,6 +5618,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '黄',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &[
             '儣', '兤', '嚝', '壙', '廣', '懬', '懭', '搆', '擴', '曠', '横', '潢', '瀇', '熿',
             '獷', '璜', '癀', '磺', '礦', '簧', '纊', '鄺', '鐄', '鑛', '黄', '黆', '黈', '黋',
@@ -5390,6 +5628,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '麻',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &[
             '嘛', '懡', '摩', '擵', '暦', '歴', '磨', '穈', '糜', '縻', '蔴', '藦', '蘑', '蘼',
             '醿', '靡', '魔', '麻', '麼', '麾', '麿',
@@ -5398,6 +5637,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '啇',
         strokes: 11,
+        alternate: Alternate::Glyph('啇'),
         kanji: &[
             '嫡', '摘', '擿', '敵', '樀', '歒', '滴', '蔐', '謫', '讁', '適', '鏑',
         ],
@@ -5405,16 +5645,19 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '亀',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &['亀', '穐', '竃', '縄', '蘒', '蝿', '鬮', '龜', '龝'],
     },
     Membership {
         radical: '鹵',
         strokes: 11,
+        alternate: Alternate::None,
         kanji: &['滷', '磠', '鹵', '鹸', '鹹', '鹺', '鹻', '鹼', '鹽'],
     },
     Membership {
         radical: '歯',
         strokes: 12,
+        alternate: Alternate::None,
         kanji: &[
             '噛', '囓', '歯', '齒', '齓', '齔', '齕', '齖', '齗', '齘', '齚', '齝', '齞', '齟',
             '齠', '齡', '齢', '齣', '齦', '齧', '齨', '齩', '齪', '齬', '齮', '齯', '齰', '齱',
@@ -5424,6 +5667,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '無',
         strokes: 12,
+        alternate: Alternate::None,
         kanji: &[
             '儛', '嘸', '墲', '嫵', '廡', '憮', '撫', '橅', '無', '璑', '甒', '膴', '舞', '蕪',
             '蟱',
@@ -5432,16 +5676,19 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '黍',
         strokes: 12,
+        alternate: Alternate::None,
         kanji: &['藜', '黍', '黎', '黏', '黐'],
     },
     Membership {
         radical: '黹',
         strokes: 12,
+        alternate: Alternate::None,
         kanji: &['黹', '黻', '黼'],
     },
     Membership {
         radical: '鼠',
         strokes: 13,
+        alternate: Alternate::None,
         kanji: &[
             '攛', '獵', '癙', '竄', '臘', '鑞', '鬣', '鼠', '鼢', '鼦', '鼪', '鼫', '鼬', '鼯',
             '鼱', '鼲', '鼴', '鼷', '鼹', '鼺',
@@ -5450,6 +5697,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '黽',
         strokes: 13,
+        alternate: Alternate::None,
         kanji: &[
             '僶', '澠', '竈', '繩', '蠅', '黽', '黿', '鼂', '鼃', '鼇', '鼈', '鼉',
         ],
@@ -5457,6 +5705,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鼓',
         strokes: 13,
+        alternate: Alternate::None,
         kanji: &[
             '皷', '瞽', '鼓', '鼔', '鼕', '鼖', '鼗', '鼙', '鼚', '鼛', '鼟',
         ],
@@ -5464,11 +5713,13 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鼎',
         strokes: 13,
+        alternate: Alternate::None,
         kanji: &['鼎', '鼏', '鼐', '鼑', '鼒'],
     },
     Membership {
         radical: '齊',
         strokes: 14,
+        alternate: Alternate::None,
         kanji: &[
             '儕', '劑', '嚌', '擠', '濟', '穧', '纃', '臍', '薺', '蠐', '躋', '隮', '霽', '韲',
             '齊', '齋', '齎', '齏',
@@ -5477,6 +5728,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '鼻',
         strokes: 14,
+        alternate: Alternate::None,
         kanji: &[
             '劓', '嚊', '嬶', '擤', '濞', '襣', '鼻', '鼼', '鼽', '鼾', '鼿', '齁', '齃', '齄',
             '齅', '齆', '齇',
@@ -5485,6 +5737,7 @@ pub const MEMBERSHIPS: &[Membership] = &[
     Membership {
         radical: '龠',
         strokes: 17,
+        alternate: Alternate::None,
         kanji: &[
             '瀹', '爚', '禴', '籥', '籲', '鑰', '鸙', '龠', '龡', '龢', '龣', '龥',
         ],